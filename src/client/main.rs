@@ -10,9 +10,11 @@ use termion::raw::RawTerminal;
 
 use tt::connection::Connection;
 
-use tt::message::{ServerMessage, ClientMessage, Position, Size};
+use tt::message::{ServerMessage, ClientMessage, Color, ColorDepth, Position, Size, Style, StyledLine};
+use tt::unicode;
+use unicode_segmentation::UnicodeSegmentation;
 
-const IPC_DIR: &'static str = "/home/tac-tics/projects/tt/ipc";
+const IPC_DIR: &str = "/home/tac-tics/projects/tt/ipc";
 
 fn clear_screen<T: Write>(stdout: &mut RawTerminal<T>) {
     write!(
@@ -66,6 +68,53 @@ enum ClientEvent {
     Resize(Size),
 }
 
+/// How long to wait for the terminal to answer a clipboard query before giving up.
+const CLIPBOARD_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// A pending OSC 52 clipboard query. The terminal's reply arrives through the
+/// keyboard as `ESC ] 52 ; c ; <base64> BEL`, which is collected into `reply`.
+struct ClipboardRequest {
+    sent: std::time::Instant,
+    reply: Option<String>,
+}
+
+impl ClipboardRequest {
+    /// Feeds a key to the request, returning true if the key was part of the reply.
+    fn accept(&mut self, key: Key) -> bool {
+        match (&mut self.reply, key) {
+            (None, Key::Alt(']')) => self.reply = Some(String::new()),
+            (Some(_), Key::Ctrl('g') | Key::Alt('\\')) => (),
+            (Some(reply), Key::Char(ch)) => reply.push(ch),
+            _ => return false,
+        }
+        true
+    }
+
+    fn is_complete(&self, key: Key) -> bool {
+        self.reply.is_some() && matches!(key, Key::Ctrl('g') | Key::Alt('\\'))
+    }
+
+    fn contents(&self) -> Option<String> {
+        use base64::Engine;
+        let reply = self.reply.as_ref()?;
+        let data = reply.strip_prefix("52;")?.split(';').nth(1)?;
+        let bytes = base64::engine::general_purpose::STANDARD.decode(data).ok()?;
+        String::from_utf8(bytes).ok()
+    }
+}
+
+fn set_clipboard<W: Write>(stdout: &mut W, text: &str) {
+    use base64::Engine;
+    let data = base64::engine::general_purpose::STANDARD.encode(text);
+    write!(stdout, "\x1b]52;c;{data}\x07").unwrap();
+    stdout.flush().unwrap();
+}
+
+fn request_clipboard<W: Write>(stdout: &mut W) {
+    write!(stdout, "\x1b]52;c;?\x07").unwrap();
+    stdout.flush().unwrap();
+}
+
 fn goto<T: Write>(stdout: &mut T, pos: Position) -> anyhow::Result<()> {
     write!(stdout, "{}", termion::cursor::Goto(pos.0 + 1, pos.1 + 1))?;
     Ok(())
@@ -101,19 +150,46 @@ fn main() {
     let keyboard_input_thread_receiver = sender.clone();
     let resize_receiver = sender.clone();
     let server_message_receiver = sender;
-    let connection2 = connection.clone();
+    let connection2 = connection;
     std::thread::spawn(move || server_message_received_thread(connection2, server_message_receiver));
     std::thread::spawn(move || keyboard_input_thread(keyboard_input_thread_receiver));
     std::thread::spawn(move || resize_listener(resize_receiver));
 
     clear_screen(&mut stdout);
-    let args: Vec<String> = std::env::args().skip(1).collect();
-    connection.send(ClientMessage::Connect(args)).unwrap();
+    let (flags, args): (Vec<String>, Vec<String>) = std::env::args().skip(1).partition(|arg| arg == "--readonly");
+    let name = std::env::var("USER").unwrap_or_default();
+    connection.send(ClientMessage::Connect {
+        args,
+        color_depth: color_depth(),
+        name,
+        readonly: !flags.is_empty(),
+    }).unwrap();
+
+    let mut clipboard_request: Option<ClipboardRequest> = None;
 
     'runloop: loop {
-        let event = receiver.recv().unwrap();
+        let event = match receiver.recv_timeout(CLIPBOARD_TIMEOUT) {
+            Ok(event) => event,
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                if clipboard_request.as_ref().map(|request| request.sent.elapsed() > CLIPBOARD_TIMEOUT).unwrap_or(false) {
+                    warn!("Terminal did not answer the clipboard query");
+                    clipboard_request = None;
+                    connection.send(ClientMessage::ClipboardContents(None)).unwrap();
+                }
+                continue;
+            },
+            Err(mpsc::RecvTimeoutError::Disconnected) => break 'runloop,
+        };
         info!("Got event: {event:?}");
         match event {
+            ClientEvent::Key(key) if clipboard_request.as_mut().map(|request| request.accept(key)).unwrap_or(false) => {
+                let request = clipboard_request.as_ref().unwrap();
+                if request.is_complete(key) {
+                    let contents = request.contents();
+                    clipboard_request = None;
+                    connection.send(ClientMessage::ClipboardContents(contents)).unwrap();
+                }
+            },
             ClientEvent::Key(key) => {
                 if key == Key::Ctrl('c') {
                     info!("Detected C-c. Exiting.");
@@ -131,6 +207,19 @@ fn main() {
                     ServerMessage::Update(pos, size, lines) => {
                         do_update(&mut stdout, pos, size, lines);
                     },
+                    ServerMessage::Patch(patches) => {
+                        do_patch(&mut stdout, patches);
+                    },
+                    ServerMessage::SetClipboard(text) => {
+                        set_clipboard(&mut stdout, &text);
+                    },
+                    ServerMessage::RequestClipboard => {
+                        request_clipboard(&mut stdout);
+                        clipboard_request = Some(ClipboardRequest {
+                            sent: std::time::Instant::now(),
+                            reply: None,
+                        });
+                    },
                     ServerMessage::Cursor(pos) => {
                         goto(&mut stdout, pos).unwrap();
                         stdout.flush().unwrap();
@@ -148,6 +237,18 @@ fn main() {
     info!("Good-bye!");
 }
 
+/// The colors the terminal supports, going by what it advertises in the environment.
+fn color_depth() -> ColorDepth {
+    let var = |name| std::env::var(name).unwrap_or_default();
+    if matches!(var("COLORTERM").as_str(), "truecolor" | "24bit") {
+        ColorDepth::TrueColor
+    } else if var("TERM").contains("256color") {
+        ColorDepth::Indexed256
+    } else {
+        ColorDepth::Ansi16
+    }
+}
+
 fn setup_logging() {
     WriteLogger::init(
         LevelFilter::Warn,
@@ -156,26 +257,87 @@ fn setup_logging() {
     ).unwrap();
 }
 
-fn do_update<W: Write>(stdout: &mut W, pos: Position, size: Size, lines: Vec<String>) {
+/// Writes the escape sequences that switch the terminal to `style`.
+fn set_style<W: Write>(stdout: &mut W, style: Style) -> std::io::Result<()> {
+    use termion::{color, style};
+
+    write!(stdout, "{}", style::Reset)?;
+    match style.fg {
+        Color::Default => write!(stdout, "{}", color::Fg(color::Reset))?,
+        Color::Ansi(n) => write!(stdout, "\x1b[{}m", ansi_sgr(n, 30))?,
+        Color::Indexed(n) => write!(stdout, "{}", color::Fg(color::AnsiValue(n)))?,
+        Color::Rgb(r, g, b) => write!(stdout, "{}", color::Fg(color::Rgb(r, g, b)))?,
+    }
+    match style.bg {
+        Color::Default => write!(stdout, "{}", color::Bg(color::Reset))?,
+        Color::Ansi(n) => write!(stdout, "\x1b[{}m", ansi_sgr(n, 40))?,
+        Color::Indexed(n) => write!(stdout, "{}", color::Bg(color::AnsiValue(n)))?,
+        Color::Rgb(r, g, b) => write!(stdout, "{}", color::Bg(color::Rgb(r, g, b)))?,
+    }
+    if style.bold {
+        write!(stdout, "{}", style::Bold)?;
+    }
+    if style.italic {
+        write!(stdout, "{}", style::Italic)?;
+    }
+    if style.underline {
+        write!(stdout, "{}", style::Underline)?;
+    }
+    if style.reverse {
+        write!(stdout, "{}", style::Invert)?;
+    }
+    Ok(())
+}
+
+/// The SGR parameter for one of the 16 terminal colors, given the one for black:
+/// 30 for the foreground or 40 for the background. The bright colors are 60 past
+/// those. termion's named colors go through the 256 color palette instead, which
+/// is what a terminal with only 16 colors may not understand.
+fn ansi_sgr(n: u8, black: u8) -> u8 {
+    match n {
+        0..=7 => black + n,
+        _ => black + 60 + (n & 7),
+    }
+}
+
+/// Draws `line` from `pos`, stopping before a character that would pass column `right`.
+/// Returns the column after the last one drawn.
+fn draw_line<W: Write>(stdout: &mut W, pos: Position, line: &StyledLine, right: u16) -> u16 {
+    goto(stdout, pos).unwrap();
+    let mut x = pos.0;
+    'spans: for span in line {
+        set_style(stdout, span.style).unwrap();
+        for grapheme in span.text.graphemes(true) {
+            let cols = unicode::grapheme_width(grapheme) as u16;
+            if x + cols > right {
+                break 'spans;
+            }
+            write!(stdout, "{}", grapheme).unwrap();
+            x += cols;
+        }
+    }
+    set_style(stdout, Style::PLAIN).unwrap();
+    x
+}
+
+fn do_update<W: Write>(stdout: &mut W, pos: Position, size: Size, lines: Vec<StyledLine>) {
     let (x, y) = pos;
     let (width, height) = size;
     info!("{lines:?}");
 
-    let empty = String::new();
-
+    let empty = StyledLine::new();
     for i in 0..height {
-        let line: &str = &lines.get(i as usize).unwrap_or_else(|| &empty);
-        let cur_pos = (x, y + i as u16);
+        let line = lines.get(i as usize).unwrap_or(&empty);
+        let end = draw_line(stdout, (x, y + i), line, x + width);
+        write!(stdout, "{}", " ".repeat((x + width - end) as usize)).unwrap();
+    }
 
-        goto(stdout, cur_pos).unwrap();
+    stdout.flush().unwrap();
+}
 
-        for ch in line.chars().take(width as usize) {
-            write!(stdout, "{}", ch).unwrap();
-        }
-        for _ in line.len()..width as usize {
-            write!(stdout, "{}", ' ').unwrap();
-        }
+fn do_patch<W: Write>(stdout: &mut W, patches: Vec<(Position, StyledLine)>) {
+    for (pos, line) in patches {
+        draw_line(stdout, pos, &line, u16::MAX);
     }
-
     stdout.flush().unwrap();
 }
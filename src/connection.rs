@@ -81,11 +81,11 @@ impl Connection {
         }
 
         let mut buf = vec![];
-        buf.write(json_data.as_bytes())?;
+        buf.write_all(json_data.as_bytes())?;
 
         let mut total_written = 0;
         while total_written < json_data_len as usize {
-            total_written += send(fd, &buf, flags)?;
+            total_written += send(fd, &buf[total_written..], flags)?;
         }
         debug!("<-- {message:?}");
         Ok(())
@@ -117,7 +117,7 @@ impl Connection {
             }
             total_read += read;
         }
-        let message: T = serde_json::from_slice::<T>(&mut message_buf)?;
+        let message: T = serde_json::from_slice::<T>(&message_buf)?;
         debug!("--> {message:?}");
         Ok(Some(message))
     }
@@ -1,5 +1,6 @@
 pub mod connection;
 pub mod message;
+pub mod unicode;
 
 
 #[cfg(test)]
@@ -5,21 +5,103 @@ pub type Position = (u16, u16);
 pub type Size = (u16, u16);
 
 
+/// How many colors a client's terminal can show. Colors beyond it are sent as
+/// the nearest it has.
+#[derive(Hash, PartialEq, Eq, Debug, Serialize, Deserialize, Clone, Copy, Default, PartialOrd, Ord)]
+pub enum ColorDepth {
+    #[default]
+    Ansi16,
+    Indexed256,
+    TrueColor,
+}
+
 #[derive(Hash, PartialEq, Eq, Debug, Serialize, Deserialize, Clone)]
 pub enum ClientMessage {
-    Connect(Vec<String>),
+    Connect {
+        /// The command line arguments.
+        args: Vec<String>,
+        /// The colors the terminal supports.
+        color_depth: ColorDepth,
+        /// The name other clients know this one by.
+        name: String,
+        /// Whether the client may only watch, not edit.
+        readonly: bool,
+    },
     RequestRefresh,
     SendInput(Key),
     Resize(Size),
+    /// The terminal's clipboard, in reply to `RequestClipboard`. `None` if it could not be read.
+    ClipboardContents(Option<String>),
     Disconnect,
 }
 
 
+/// A terminal color, in whichever of the three common palettes it was chosen from.
+#[derive(Hash, PartialEq, Eq, Debug, Serialize, Deserialize, Clone, Copy, Default)]
+pub enum Color {
+    /// The terminal's own foreground or background.
+    #[default]
+    Default,
+    /// One of the 16 basic colors: 0-7, then their bright versions 8-15.
+    Ansi(u8),
+    /// One of the 256 indexed colors.
+    Indexed(u8),
+    /// A truecolor value.
+    Rgb(u8, u8, u8),
+}
+
+/// How a run of text is drawn.
+#[derive(Hash, PartialEq, Eq, Debug, Serialize, Deserialize, Clone, Copy, Default)]
+pub struct Style {
+    pub fg: Color,
+    pub bg: Color,
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub reverse: bool,
+}
+
+impl Style {
+    /// The terminal's default look, usable in constants.
+    pub const PLAIN: Style = Style {
+        fg: Color::Default,
+        bg: Color::Default,
+        bold: false,
+        italic: false,
+        underline: false,
+        reverse: false,
+    };
+}
+
+/// Text drawn in one style.
+#[derive(Hash, PartialEq, Eq, Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Span {
+    pub text: String,
+    pub style: Style,
+}
+
+impl Span {
+    pub fn new(text: impl Into<String>, style: Style) -> Self {
+        Span { text: text.into(), style }
+    }
+}
+
+/// A screen row, as the spans drawn left to right from its start.
+pub type StyledLine = Vec<Span>;
+
 #[derive(Hash, PartialEq, Eq, Debug, Serialize, Deserialize, Clone)]
 pub enum ServerMessage {
     Log(String),
-    Update(Position, Size, Vec<String>),
+    /// Redraws the rectangle at a position of a size. Rows past the lines
+    /// given, and columns past the end of a line, are cleared.
+    Update(Position, Size, Vec<StyledLine>),
+    /// Lines to draw from each position, leaving the rest of the screen as it is.
+    Patch(Vec<(Position, StyledLine)>),
     Cursor(Position),
+    /// Copy text to the client's system clipboard.
+    SetClipboard(String),
+    /// Ask the client for the contents of its system clipboard.
+    RequestClipboard,
     Shutdown,
 }
 
@@ -0,0 +1,552 @@
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use ropey::Rope;
+use serde::{Serialize, Deserialize};
+
+use crate::options::Indent;
+use crate::render;
+use crate::syntax::Highlights;
+use crate::undo::{Edit, UndoTree};
+use tt::unicode;
+
+/// A location in a buffer, counted in lines and characters (not bytes).
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Default, Hash)]
+pub struct Cursor {
+    pub line: usize,
+    pub col: usize,
+}
+
+impl Cursor {
+    pub fn new(line: usize, col: usize) -> Self {
+        Cursor { line, col }
+    }
+}
+
+/// A change to a buffer's text: what was between `start` and `old_end` is now
+/// between `start` and `new_end`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Change {
+    pub start: Cursor,
+    pub old_end: Cursor,
+    pub new_end: Cursor,
+}
+
+impl Change {
+    /// Where a cursor on the old text is after the change. A cursor past it
+    /// stays on the same character, and one in the replaced text moves to its start.
+    pub fn shift(&self, cursor: Cursor) -> Cursor {
+        if cursor < self.start {
+            cursor
+        } else if cursor < self.old_end {
+            self.start
+        } else if cursor.line == self.old_end.line {
+            Cursor::new(self.new_end.line, self.new_end.col + cursor.col - self.old_end.col)
+        } else {
+            Cursor::new(cursor.line + self.new_end.line - self.old_end.line, cursor.col)
+        }
+    }
+}
+
+pub type BufferId = usize;
+
+#[derive(Default)]
+pub struct Buffer {
+    /// Assigned when the buffer is added to the buffer list.
+    pub id: BufferId,
+    pub path: Option<PathBuf>,
+    pub text: Rope,
+    pub pos: Cursor,
+    pub want_col: usize,
+    /// Where the Visual mode selection started, while there is one.
+    pub anchor: Option<Cursor>,
+    /// The first and last lines of the previous Visual selection, for `'<` and `'>`.
+    pub visual_marks: Option<(usize, usize)>,
+    pub history: UndoTree,
+    /// The change the file on disk matches.
+    saved_seq: usize,
+    pub indent: Indent,
+    pub highlights: Highlights,
+    /// The changes made since they were last taken, for moving the cursors
+    /// that other windows keep in the buffer.
+    pub changes: Vec<Change>,
+}
+
+/// The on-disk form of a buffer's history. `hash` identifies the text it applies to.
+#[derive(Serialize, Deserialize)]
+struct UndoFile {
+    hash: u64,
+    history: UndoTree,
+}
+
+impl Buffer {
+    pub fn new(path: Option<PathBuf>, data: &str) -> Self {
+        Buffer {
+            id: 0,
+            path,
+            text: Rope::from_str(data),
+            pos: Cursor::default(),
+            want_col: 0,
+            anchor: None,
+            visual_marks: None,
+            history: UndoTree::default(),
+            saved_seq: 0,
+            indent: Indent::default(),
+            highlights: Highlights::default(),
+            changes: vec![],
+        }
+    }
+
+    pub fn load(&mut self, path: &Path) -> anyhow::Result<()> {
+        let file = std::fs::File::open(path)?;
+        self.text = Rope::from_reader(BufReader::new(file))?;
+        self.highlights.invalidate(0);
+        self.history = UndoTree::default();
+        self.saved_seq = 0;
+        self.pos = self.clamp(self.pos);
+        Ok(())
+    }
+
+    /// Where the persistent history for the file at `path` is kept.
+    pub fn undo_file_path(path: &Path) -> Option<PathBuf> {
+        let name = path.file_name()?.to_string_lossy();
+        Some(path.with_file_name(format!(".{name}.tt-undo")))
+    }
+
+    /// An FNV-1a hash of the text's bytes. It doesn't depend on how the rope
+    /// happens to be split into chunks, or on the Rust version, so it still
+    /// matches the text when the undo file is read back.
+    fn text_hash(&self) -> u64 {
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for byte in self.text.chunks().flat_map(|chunk| chunk.bytes()) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        hash
+    }
+
+    /// Restores history saved by `save_history`, if it matches the current text.
+    pub fn load_history(&mut self, path: &Path) -> anyhow::Result<bool> {
+        let undo_path = match Buffer::undo_file_path(path) {
+            Some(undo_path) if undo_path.exists() => undo_path,
+            _ => return Ok(false),
+        };
+        let file = std::fs::File::open(undo_path)?;
+        let undo_file: UndoFile = serde_json::from_reader(BufReader::new(file))?;
+        if undo_file.hash != self.text_hash() {
+            return Ok(false);
+        }
+        self.history = undo_file.history;
+        self.saved_seq = self.history.seq();
+        Ok(true)
+    }
+
+    pub fn save_history(&mut self, path: &Path) -> anyhow::Result<()> {
+        self.history.end_group();
+        let undo_path = match Buffer::undo_file_path(path) {
+            Some(undo_path) => undo_path,
+            None => return Ok(()),
+        };
+        let file = std::fs::File::create(undo_path)?;
+        let undo_file = UndoFile {
+            hash: self.text_hash(),
+            history: self.history.clone(),
+        };
+        serde_json::to_writer(BufWriter::new(file), &undo_file)?;
+        Ok(())
+    }
+
+    pub fn save(&mut self, path: &Path) -> anyhow::Result<()> {
+        let file = std::fs::File::options()
+            .write(true)
+            .truncate(true)
+            .create(true)
+            .open(path)?;
+        self.text.write_to(BufWriter::new(file))?;
+        self.saved_seq = self.history.seq();
+        Ok(())
+    }
+
+    /// The buffer's path relative to the working directory when it's inside it.
+    pub fn name(&self) -> String {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return "[No Name]".to_string(),
+        };
+        let relative = std::env::current_dir().ok().and_then(|dir| path.strip_prefix(dir).ok().map(Path::to_path_buf));
+        relative.as_deref().unwrap_or(path).to_string_lossy().into_owned()
+    }
+
+    /// Whether the text has changed since it was loaded or saved, counting
+    /// edits in groups that are still open.
+    pub fn is_modified(&self) -> bool {
+        self.history.seq() != self.saved_seq || self.history.has_open_edits()
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.text.len_lines()
+    }
+
+    /// The contents of the given line, without its line ending.
+    pub fn line(&self, line: usize) -> String {
+        match self.text.get_line(line) {
+            Some(slice) => {
+                let mut s = slice.to_string();
+                while s.ends_with('\n') || s.ends_with('\r') {
+                    s.pop();
+                }
+                s
+            },
+            None => String::new(),
+        }
+    }
+
+    /// Length of the given line in characters, not counting its line ending.
+    pub fn line_len(&self, line: usize) -> usize {
+        let slice = match self.text.get_line(line) {
+            Some(slice) => slice,
+            None => return 0,
+        };
+        let mut len = slice.len_chars();
+        while len > 0 && matches!(slice.char(len - 1), '\n' | '\r') {
+            len -= 1;
+        }
+        len
+    }
+
+    pub fn clamp(&self, cursor: Cursor) -> Cursor {
+        let line = cursor.line.min(self.line_count() - 1);
+        let col = cursor.col.min(self.line_len(line));
+        Cursor { line, col }
+    }
+
+    /// Like `clamp`, but keeps the cursor on the start of a character, as
+    /// Normal mode requires.
+    pub fn clamp_normal(&self, cursor: Cursor) -> Cursor {
+        let cursor = self.clamp(cursor);
+        let len = self.line_len(cursor.line);
+        Cursor {
+            line: cursor.line,
+            col: unicode::grapheme_start(&self.line(cursor.line), cursor.col.min(len.saturating_sub(1))),
+        }
+    }
+
+    pub fn char_idx(&self, cursor: Cursor) -> usize {
+        let cursor = self.clamp(cursor);
+        self.text.line_to_char(cursor.line) + cursor.col
+    }
+
+    pub fn cursor_at(&self, char_idx: usize) -> Cursor {
+        let char_idx = char_idx.min(self.text.len_chars());
+        let line = self.text.char_to_line(char_idx);
+        Cursor {
+            line,
+            col: char_idx - self.text.line_to_char(line),
+        }
+    }
+
+    pub fn char_at(&self, cursor: Cursor) -> Option<char> {
+        if cursor.col < self.line_len(cursor.line) {
+            self.text.get_char(self.char_idx(cursor))
+        } else {
+            None
+        }
+    }
+
+    /// Replaces the characters from `start` to `end` with `text`, without
+    /// recording it in the history.
+    fn splice(&mut self, start: usize, end: usize, text: &str) {
+        let (start_cursor, old_end) = (self.cursor_at(start), self.cursor_at(end));
+        self.highlights.invalidate(start_cursor.line);
+        self.text.remove(start..end);
+        self.text.insert(start, text);
+        let new_end = self.cursor_at(start + text.chars().count());
+        self.changes.push(Change { start: start_cursor, old_end, new_end });
+    }
+
+    /// Applies an edit to the text without recording it in the history.
+    fn apply(&mut self, edit: &Edit) {
+        let removed_len = edit.removed.chars().count();
+        self.splice(edit.at, edit.at + removed_len, &edit.inserted);
+    }
+
+    fn remove_range(&mut self, start: usize, end: usize) -> String {
+        let removed = self.text.slice(start..end).to_string();
+        self.splice(start, end, "");
+        self.history.record(Edit { at: start, removed: removed.clone(), inserted: String::new() }, self.pos);
+        removed
+    }
+
+    /// Ends the Visual selection, remembering its lines for `'<` and `'>`.
+    pub fn end_visual(&mut self) {
+        if let Some(anchor) = self.anchor.take() {
+            self.visual_marks = Some((anchor.line.min(self.pos.line), anchor.line.max(self.pos.line)));
+        }
+    }
+
+    /// Inserts `text` at `at` and returns the position just past the inserted text.
+    pub fn insert(&mut self, at: Cursor, text: &str) -> Cursor {
+        let idx = self.char_idx(at);
+        self.splice(idx, idx, text);
+        self.history.record(Edit { at: idx, removed: String::new(), inserted: text.to_string() }, self.pos);
+        self.cursor_at(idx + text.chars().count())
+    }
+
+    /// Removes the text between `start` (inclusive) and `end` (exclusive), returning it.
+    pub fn remove(&mut self, start: Cursor, end: Cursor) -> String {
+        let start = self.char_idx(start);
+        let end = self.char_idx(end);
+        let (start, end) = if start <= end { (start, end) } else { (end, start) };
+        self.remove_range(start, end)
+    }
+
+    /// Undoes up to `count` changes, returning how many were undone.
+    pub fn undo(&mut self, count: usize) -> usize {
+        let mut undone = 0;
+        while undone < count {
+            let Some((edits, cursor)) = self.history.undo() else { break };
+            for edit in &edits {
+                self.apply(edit);
+            }
+            self.pos = self.clamp_normal(cursor);
+            undone += 1;
+        }
+        undone
+    }
+
+    /// Redoes up to `count` changes, returning how many were redone.
+    pub fn redo(&mut self, count: usize) -> usize {
+        let mut redone = 0;
+        while redone < count {
+            let Some(edits) = self.history.redo() else { break };
+            for edit in &edits {
+                self.apply(edit);
+            }
+            if let Some(edit) = edits.first() {
+                self.pos = self.clamp_normal(self.cursor_at(edit.at));
+            }
+            redone += 1;
+        }
+        redone
+    }
+
+    /// Moves to the text state after change number `seq`, on whichever branch it is.
+    pub fn goto_change(&mut self, seq: usize) -> bool {
+        let Some(edits) = self.history.goto(seq) else { return false };
+        for edit in &edits {
+            self.apply(edit);
+        }
+        if let Some(edit) = edits.last() {
+            self.pos = self.clamp_normal(self.cursor_at(edit.at));
+        }
+        true
+    }
+
+    pub fn insert_at_cursor(&mut self, text: &str) {
+        self.pos = self.insert(self.pos, text);
+    }
+
+    /// Insert mode Tab: a tab, or with `expandtab` or `softtabstop` whitespace
+    /// up to the next stop. Without `expandtab`, spaces before the cursor are
+    /// turned into tabs where they reach a tab stop.
+    pub fn insert_tab(&mut self) {
+        let indent = self.indent;
+        if !indent.expandtab && indent.softtabstop == 0 {
+            self.insert_at_cursor("\t");
+            return;
+        }
+        let pos = self.clamp(self.pos);
+        let line = self.line(pos.line);
+        let col = render::screen_col(&line, pos.col, indent.tabstop);
+        let stop = if indent.softtabstop > 0 { indent.softtabstop } else { indent.tabstop };
+        let to = (col / stop + 1) * stop;
+        let spaces = if indent.expandtab {
+            0
+        } else {
+            spaces_before(&line, pos.col)
+        };
+        let start = Cursor::new(pos.line, pos.col - spaces);
+        if spaces > 0 {
+            self.remove(start, pos);
+        }
+        self.pos = self.insert(start, &indent.fill(col - spaces, to));
+    }
+
+    /// Deletes the character before the cursor, joining lines at the start of
+    /// a line. With `softtabstop`, spaces back to the previous stop go at once.
+    pub fn backspace(&mut self) {
+        let idx = self.char_idx(self.pos);
+        if idx == 0 {
+            return;
+        }
+        let pos = self.clamp(self.pos);
+        let softtabstop = self.indent.softtabstop;
+        let line = self.line(pos.line);
+        // The screen column is 0 when the cursor is inside the first grapheme
+        // cluster, after a character that takes no column of its own.
+        let col = render::screen_col(&line, pos.col, self.indent.tabstop);
+        if softtabstop > 0 && col > 0 {
+            let spaces = spaces_before(&line, pos.col);
+            let count = spaces.min(col - (col - 1) / softtabstop * softtabstop);
+            if count > 1 {
+                self.remove_range(idx - count, idx);
+                self.pos = self.cursor_at(idx - count);
+                return;
+            }
+        }
+        let mut start = idx - 1;
+        if pos.col > 0 {
+            start = idx - pos.col + unicode::prev_grapheme(&line, pos.col);
+        } else if start > 0 && self.text.char(start) == '\n' && self.text.char(start - 1) == '\r' {
+            start -= 1;
+        }
+        self.remove_range(start, idx);
+        self.pos = self.cursor_at(start);
+    }
+
+    /// Deletes the character under the cursor, joining with the next line at end of line.
+    pub fn delete(&mut self) {
+        let idx = self.char_idx(self.pos);
+        if idx >= self.text.len_chars() {
+            return;
+        }
+        let pos = self.clamp(self.pos);
+        let mut end = idx + 1;
+        if pos.col < self.line_len(pos.line) {
+            end = idx - pos.col + unicode::next_grapheme(&self.line(pos.line), pos.col);
+        } else if self.text.char(idx) == '\r' && self.text.get_char(end) == Some('\n') {
+            end += 1;
+        }
+        self.remove_range(idx, end);
+        self.pos = self.cursor_at(idx);
+    }
+}
+
+/// The number of spaces just before char `col` of `line`.
+fn spaces_before(line: &str, col: usize) -> usize {
+    let before: String = line.chars().take(col).collect();
+    before.chars().rev().take_while(|&ch| ch == ' ').count()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn text_hash_ignores_chunk_boundaries() {
+        let text: String = (0..500).map(|i| format!("line {i}\n")).collect();
+        let loaded = Buffer { text: Rope::from_reader(text.as_bytes()).unwrap(), ..Buffer::new(None, "") };
+        let mut typed = Buffer::new(None, "");
+        for (i, ch) in text.chars().enumerate() {
+            typed.text.insert_char(i, ch);
+        }
+        assert_ne!(loaded.text.chunks().count(), typed.text.chunks().count());
+        assert_eq!(loaded.text_hash(), typed.text_hash());
+    }
+
+    #[test]
+    fn inserts_at_cursor() {
+        let mut buffer = Buffer::new(None, "hello\nworld\n");
+        buffer.pos = Cursor::new(1, 2);
+        buffer.insert_at_cursor("XY");
+        assert_eq!(buffer.text.to_string(), "hello\nwoXYrld\n");
+        assert_eq!(buffer.pos, Cursor::new(1, 4));
+
+        buffer.insert_at_cursor("\n");
+        assert_eq!(buffer.text.to_string(), "hello\nwoXY\nrld\n");
+        assert_eq!(buffer.pos, Cursor::new(2, 0));
+    }
+
+    #[test]
+    fn backspace_joins_lines() {
+        let mut buffer = Buffer::new(None, "ab\ncd");
+        buffer.pos = Cursor::new(1, 0);
+        buffer.backspace();
+        assert_eq!(buffer.text.to_string(), "abcd");
+        assert_eq!(buffer.pos, Cursor::new(0, 2));
+
+        buffer.pos = Cursor::new(0, 0);
+        buffer.backspace();
+        assert_eq!(buffer.text.to_string(), "abcd");
+    }
+
+    #[test]
+    fn delete_and_clamp() {
+        let mut buffer = Buffer::new(None, "ab\r\ncd");
+        assert_eq!(buffer.line_len(0), 2);
+        assert_eq!(buffer.clamp(Cursor::new(7, 9)), Cursor::new(1, 2));
+
+        buffer.pos = Cursor::new(0, 2);
+        buffer.delete();
+        assert_eq!(buffer.text.to_string(), "abcd");
+    }
+
+    #[test]
+    fn changes_shift_cursors_after_them() {
+        let mut buffer = Buffer::new(None, "one\ntwo\nthree\n");
+        buffer.insert(Cursor::new(1, 1), "X\nY");
+        buffer.remove(Cursor::new(0, 1), Cursor::new(0, 3));
+        let shift = |cursor| buffer.changes.iter().fold(cursor, |cursor, change| change.shift(cursor));
+        assert_eq!(shift(Cursor::new(0, 0)), Cursor::new(0, 0));
+        assert_eq!(shift(Cursor::new(0, 2)), Cursor::new(0, 1));
+        assert_eq!(shift(Cursor::new(1, 1)), Cursor::new(2, 1));
+        assert_eq!(shift(Cursor::new(1, 2)), Cursor::new(2, 2));
+        assert_eq!(shift(Cursor::new(2, 4)), Cursor::new(3, 4));
+        assert_eq!(buffer.text.to_string(), "o\ntX\nYwo\nthree\n");
+    }
+
+    #[test]
+    fn edits_whole_graphemes() {
+        let mut buffer = Buffer::new(None, "ae\u{301}b");
+        assert_eq!(buffer.clamp_normal(Cursor::new(0, 2)), Cursor::new(0, 1));
+        buffer.pos = Cursor::new(0, 3);
+        buffer.backspace();
+        assert_eq!(buffer.text.to_string(), "ab");
+        buffer.pos = Cursor::new(0, 0);
+        buffer.delete();
+        assert_eq!(buffer.text.to_string(), "b");
+    }
+
+    #[test]
+    fn tab_reaches_the_next_stop() {
+        let mut buffer = Buffer::new(None, "ab");
+        buffer.indent = Indent { tabstop: 8, shiftwidth: 4, expandtab: false, softtabstop: 4 };
+        buffer.pos = Cursor::new(0, 2);
+        buffer.insert_tab();
+        assert_eq!(buffer.text.to_string(), "ab  ");
+        buffer.insert_tab();
+        assert_eq!(buffer.text.to_string(), "ab\t");
+        buffer.insert_tab();
+        assert_eq!(buffer.text.to_string(), "ab\t    ");
+        buffer.backspace();
+        assert_eq!(buffer.text.to_string(), "ab\t");
+
+        buffer.indent.expandtab = true;
+        buffer.insert_tab();
+        assert_eq!((buffer.text.to_string(), buffer.pos), ("ab\t    ".to_string(), Cursor::new(0, 7)));
+
+        // Backspace inside the first cluster, at screen column 0, deletes one character.
+        let mut buffer = Buffer::new(None, "e\u{301}x");
+        buffer.indent.softtabstop = 4;
+        buffer.pos = Cursor::new(0, 1);
+        buffer.backspace();
+        assert_eq!((buffer.text.to_string(), buffer.pos), ("\u{301}x".to_string(), Cursor::new(0, 0)));
+    }
+
+    #[test]
+    fn undo_restores_text() {
+        let mut buffer = Buffer::new(None, "abc");
+        buffer.history.begin_group(buffer.pos);
+        buffer.insert_at_cursor("xy");
+        buffer.delete();
+        buffer.history.end_group();
+        buffer.insert_at_cursor("z");
+        assert_eq!(buffer.text.to_string(), "xyzbc");
+
+        assert_eq!(buffer.undo(5), 2);
+        assert_eq!(buffer.text.to_string(), "abc");
+        assert_eq!(buffer.redo(1), 1);
+        assert_eq!(buffer.text.to_string(), "xybc");
+        assert!(buffer.goto_change(2));
+        assert_eq!(buffer.text.to_string(), "xyzbc");
+    }
+}
@@ -0,0 +1,108 @@
+use std::path::Path;
+
+use crate::buffer::{Buffer, BufferId};
+
+/// Every open buffer, in the order they were opened. Buffers are numbered from
+/// 1 and keep their number until they are deleted.
+#[derive(Default)]
+pub struct BufferList {
+    buffers: Vec<Buffer>,
+    last_id: BufferId,
+}
+
+impl BufferList {
+    pub fn add(&mut self, mut buffer: Buffer) -> BufferId {
+        self.last_id += 1;
+        buffer.id = self.last_id;
+        self.buffers.push(buffer);
+        self.last_id
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Buffer> {
+        self.buffers.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Buffer> {
+        self.buffers.iter_mut()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffers.is_empty()
+    }
+
+    pub fn get(&self, id: BufferId) -> Option<&Buffer> {
+        self.buffers.iter().find(|buffer| buffer.id == id)
+    }
+
+    pub fn get_mut(&mut self, id: BufferId) -> Option<&mut Buffer> {
+        self.buffers.iter_mut().find(|buffer| buffer.id == id)
+    }
+
+    pub fn find_by_path(&self, path: &Path) -> Option<BufferId> {
+        self.buffers.iter().find(|buffer| buffer.path.as_deref() == Some(path)).map(|buffer| buffer.id)
+    }
+
+    /// Finds the buffer `:buffer` names, by number or by (part of) its name.
+    pub fn find(&self, arg: &str) -> Result<BufferId, String> {
+        if let Ok(id) = arg.parse::<BufferId>() {
+            return self.get(id).map(|buffer| buffer.id).ok_or_else(|| format!("Buffer {id} does not exist"));
+        }
+        if let Some(buffer) = self.buffers.iter().find(|buffer| buffer.name() == arg) {
+            return Ok(buffer.id);
+        }
+        let mut matches = self.buffers.iter().filter(|buffer| buffer.name().contains(arg));
+        match (matches.next(), matches.next()) {
+            (Some(buffer), None) => Ok(buffer.id),
+            (Some(_), Some(_)) => Err(format!("More than one match for {arg}")),
+            (None, _) => Err(format!("No matching buffer for {arg}")),
+        }
+    }
+
+    /// The buffer `offset` places after `id` in the list, wrapping around.
+    pub fn cycle(&self, id: BufferId, offset: isize) -> Option<BufferId> {
+        let index = self.buffers.iter().position(|buffer| buffer.id == id)? as isize;
+        let index = (index + offset).rem_euclid(self.buffers.len() as isize);
+        Some(self.buffers[index as usize].id)
+    }
+
+    /// Removes a buffer, returning the one that took its place in the list.
+    pub fn remove(&mut self, id: BufferId) -> Option<BufferId> {
+        let index = self.buffers.iter().position(|buffer| buffer.id == id)?;
+        self.buffers.remove(index);
+        self.buffers.get(index).or_else(|| self.buffers.last()).map(|buffer| buffer.id)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn list(names: &[&str]) -> BufferList {
+        let mut list = BufferList::default();
+        for name in names {
+            list.add(Buffer::new(Some(PathBuf::from(name)), ""));
+        }
+        list
+    }
+
+    #[test]
+    fn ids_are_stable() {
+        let mut list = list(&["a.rs", "b.rs", "c.rs"]);
+        assert_eq!(list.remove(2), Some(3));
+        assert_eq!(list.add(Buffer::default()), 4);
+        assert_eq!(list.cycle(4, 1), Some(1));
+        assert_eq!(list.cycle(1, -1), Some(4));
+        assert_eq!(list.remove(4), Some(3));
+    }
+
+    #[test]
+    fn finds_buffers_by_number_or_name() {
+        let list = list(&["/x/main.rs", "/x/buffer.rs", "/x/buflist.rs"]);
+        assert_eq!(list.find("2"), Ok(2));
+        assert_eq!(list.find("main"), Ok(1));
+        assert_eq!(list.find("/x/buffer.rs"), Ok(2));
+        assert_eq!(list.find("buf"), Err("More than one match for buf".to_string()));
+        assert_eq!(list.find("9"), Err("Buffer 9 does not exist".to_string()));
+    }
+}
@@ -0,0 +1,300 @@
+use std::path::Path;
+
+use crate::ex::{self, Complete};
+use crate::options;
+
+/// The candidates a Tab completion cycles through.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Completion {
+    pub candidates: Vec<String>,
+    pub selected: usize,
+    /// Where the completed word starts, in characters.
+    start: usize,
+}
+
+/// The `:` command line being edited.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct CommandLine {
+    pub text: String,
+    /// The cursor's position in `text`, in characters.
+    pub cursor: usize,
+    /// While browsing history: the entry shown and the prefix entries must match.
+    history: Option<(usize, String)>,
+    pub completion: Option<Completion>,
+}
+
+fn escape(word: &str) -> String {
+    word.replace('\\', "\\\\").replace(' ', "\\ ")
+}
+
+fn unescape(word: &str) -> String {
+    let mut text = String::new();
+    let mut chars = word.chars();
+    while let Some(ch) = chars.next() {
+        text.push(if ch == '\\' { chars.next().unwrap_or(ch) } else { ch });
+    }
+    text
+}
+
+/// Where the word before the cursor starts (in characters), skipping escaped spaces.
+fn word_start(text: &str) -> usize {
+    let mut start = 0;
+    let mut escaped = false;
+    for (i, ch) in text.chars().enumerate() {
+        if escaped {
+            escaped = false;
+        } else if ch == '\\' {
+            escaped = true;
+        } else if ch.is_whitespace() {
+            start = i + 1;
+        }
+    }
+    start
+}
+
+fn path_candidates(word: &str) -> Vec<String> {
+    let (dir, prefix) = match word.rfind('/') {
+        Some(i) => word.split_at(i + 1),
+        None => ("", word),
+    };
+    let entries = match std::fs::read_dir(if dir.is_empty() { Path::new(".") } else { Path::new(dir) }) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    let mut candidates: Vec<String> = entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let name = entry.file_name().into_string().ok()?;
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+            let slash = if entry.path().is_dir() { "/" } else { "" };
+            Some(format!("{dir}{name}{slash}"))
+        })
+        .collect();
+    candidates.sort();
+    candidates
+}
+
+fn option_candidates(word: &str) -> Vec<String> {
+    let negated = word.strip_prefix("no").map(|word| {
+        options::NAMES.iter().filter(move |name| name.starts_with(word)).map(|name| format!("no{name}"))
+    });
+    options::NAMES.iter()
+        .filter(|name| name.starts_with(word))
+        .map(|name| name.to_string())
+        .chain(negated.into_iter().flatten())
+        .collect()
+}
+
+/// The completions of the word that ends at the end of `before`, and where
+/// that word starts. `buffers` are the names of the open buffers.
+fn candidates(before: &str, buffers: &[String]) -> (usize, Vec<String>) {
+    let start = word_start(before);
+    let word = unescape(&before.chars().skip(start).collect::<String>());
+    let leading: String = before.chars().take(start).collect();
+
+    if !leading.contains(|ch: char| ch.is_ascii_alphabetic()) {
+        // Still typing the command name, perhaps after a range.
+        let offset = word.chars().take_while(|ch| !ch.is_ascii_alphabetic()).count();
+        let name: String = word.chars().skip(offset).collect();
+        let mut names: Vec<String> = ex::command_names()
+            .filter(|command| command.starts_with(&name))
+            .map(|command| command.to_string())
+            .collect();
+        names.sort();
+        names.dedup();
+        return (start + offset, names);
+    }
+
+    let name: String = leading
+        .chars()
+        .skip_while(|ch| !ch.is_ascii_alphabetic())
+        .take_while(|ch| ch.is_ascii_alphabetic())
+        .collect();
+    let candidates = match ex::argument_completion(&name) {
+        Complete::Nothing => vec![],
+        Complete::Option => option_candidates(&word),
+        Complete::File => path_candidates(&word),
+        Complete::Buffer => buffers.iter().filter(|buffer| buffer.contains(&word)).cloned().collect(),
+    };
+    (start, candidates)
+}
+
+impl CommandLine {
+    pub fn new(text: &str) -> Self {
+        CommandLine {
+            text: text.to_string(),
+            cursor: text.chars().count(),
+            ..CommandLine::default()
+        }
+    }
+
+    fn byte(&self, cursor: usize) -> usize {
+        self.text.char_indices().nth(cursor).map_or(self.text.len(), |(i, _ch)| i)
+    }
+
+    fn edited(&mut self) {
+        self.history = None;
+        self.completion = None;
+    }
+
+    /// Replaces the characters from `start` to the cursor with `text`.
+    fn replace_before_cursor(&mut self, start: usize, text: &str) {
+        let (start_byte, end_byte) = (self.byte(start), self.byte(self.cursor));
+        self.text.replace_range(start_byte..end_byte, text);
+        self.cursor = start + text.chars().count();
+    }
+
+    pub fn insert(&mut self, ch: char) {
+        self.edited();
+        let byte = self.byte(self.cursor);
+        self.text.insert(byte, ch);
+        self.cursor += 1;
+    }
+
+    /// Deletes the character before the cursor, returning false if there wasn't one.
+    pub fn backspace(&mut self) -> bool {
+        self.edited();
+        if self.cursor == 0 {
+            return false;
+        }
+        self.cursor -= 1;
+        let byte = self.byte(self.cursor);
+        self.text.remove(byte);
+        true
+    }
+
+    pub fn delete(&mut self) {
+        self.edited();
+        if self.cursor < self.text.chars().count() {
+            let byte = self.byte(self.cursor);
+            self.text.remove(byte);
+        }
+    }
+
+    pub fn move_to(&mut self, cursor: usize) {
+        self.completion = None;
+        self.cursor = cursor.min(self.text.chars().count());
+    }
+
+    fn show_history(&mut self, index: usize, prefix: String, text: &str) {
+        self.text = text.to_string();
+        self.cursor = self.text.chars().count();
+        self.history = Some((index, prefix));
+        self.completion = None;
+    }
+
+    /// Shows the previous history entry starting with what was typed before browsing.
+    pub fn history_prev(&mut self, history: &[String]) {
+        let (index, prefix) = self.history.clone().unwrap_or_else(|| (history.len(), self.text.clone()));
+        if let Some(i) = history[..index].iter().rposition(|entry| entry.starts_with(&prefix)) {
+            self.show_history(i, prefix, &history[i]);
+        }
+    }
+
+    /// Shows the next matching history entry, or once past the newest, what was typed.
+    pub fn history_next(&mut self, history: &[String]) {
+        let (index, prefix) = match self.history.clone() {
+            Some(browsing) => browsing,
+            None => return,
+        };
+        match history[index + 1..].iter().position(|entry| entry.starts_with(&prefix)) {
+            Some(offset) => self.show_history(index + 1 + offset, prefix, &history[index + 1 + offset]),
+            None => {
+                self.text = prefix;
+                self.cursor = self.text.chars().count();
+                self.history = None;
+            },
+        }
+    }
+
+    /// Completes the word before the cursor. Completing again cycles through the
+    /// candidates, backward when `forward` is false.
+    pub fn complete(&mut self, buffers: &[String], forward: bool) {
+        if let Some(completion) = &mut self.completion {
+            let count = completion.candidates.len();
+            completion.selected = if forward {
+                (completion.selected + 1) % count
+            } else {
+                (completion.selected + count - 1) % count
+            };
+            let (start, candidate) = (completion.start, escape(&completion.candidates[completion.selected]));
+            self.replace_before_cursor(start, &candidate);
+            return;
+        }
+
+        self.history = None;
+        let before: String = self.text.chars().take(self.cursor).collect();
+        let (start, candidates) = candidates(&before, buffers);
+        let selected = if forward { 0 } else { candidates.len().saturating_sub(1) };
+        if let Some(candidate) = candidates.get(selected) {
+            self.replace_before_cursor(start, &escape(candidate));
+        }
+        if candidates.len() > 1 {
+            self.completion = Some(Completion { candidates, selected, start });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn edits_at_the_cursor() {
+        let mut line = CommandLine::new("wq");
+        line.move_to(1);
+        line.insert('x');
+        assert_eq!((line.text.as_str(), line.cursor), ("wxq", 2));
+        line.delete();
+        assert!(line.backspace());
+        line.move_to(0);
+        assert!(!line.backspace());
+        assert_eq!(line.text, "w");
+    }
+
+    #[test]
+    fn history_is_filtered_by_prefix() {
+        let history: Vec<String> = ["set ic", "write", "set hls"].iter().map(|s| s.to_string()).collect();
+        let mut line = CommandLine::new("se");
+        line.history_prev(&history);
+        assert_eq!(line.text, "set hls");
+        line.history_prev(&history);
+        assert_eq!(line.text, "set ic");
+        line.history_prev(&history);
+        assert_eq!(line.text, "set ic");
+        line.history_next(&history);
+        assert_eq!(line.text, "set hls");
+        line.history_next(&history);
+        assert_eq!(line.text, "se");
+    }
+
+    #[test]
+    fn completes_commands_and_options() {
+        let mut line = CommandLine::new("%su");
+        line.complete(&[], true);
+        assert_eq!(line.text, "%substitute");
+
+        let mut line = CommandLine::new("set nohl");
+        line.complete(&[], true);
+        assert_eq!(line.text, "set nohlsearch");
+
+        let mut line = CommandLine::new("e");
+        line.complete(&[], true);
+        assert_eq!(line.text, "earlier");
+        line.complete(&[], true);
+        assert_eq!(line.text, "edit");
+        line.complete(&[], false);
+        assert_eq!(line.text, "earlier");
+        assert_eq!(line.completion.as_ref().map(|completion| completion.candidates.len()), Some(2));
+    }
+
+    #[test]
+    fn completes_buffer_names_with_escapes() {
+        let buffers = vec!["no such dir/my file.txt".to_string()];
+        let mut line = CommandLine::new("b no\\ such");
+        line.complete(&buffers, true);
+        assert_eq!(line.text, "b no\\ such\\ dir/my\\ file.txt");
+    }
+}
@@ -0,0 +1,329 @@
+use std::fmt;
+
+use crate::buffer::Buffer;
+use crate::range::{self, LineRange};
+
+/// The ex commands understood on the `:` command line.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Name {
+    /// A bare range, which jumps to its last line.
+    Goto,
+    Edit,
+    Write,
+    WriteQuit,
+    Quit,
+    Undo,
+    Redo,
+    Earlier,
+    Later,
+    Substitute,
+    Set,
+    SetLocal,
+    ColorScheme,
+    NoHighlight,
+    List,
+    /// Lists the connected clients.
+    Who,
+    Follow,
+    BufferNext,
+    BufferPrevious,
+    Buffer,
+    BufferDelete,
+    Split,
+    VerticalSplit,
+    /// Closes the window, as opposed to `:quit`, which closes the buffer along with the last window.
+    Close,
+    Only,
+    Resize,
+    TabNew,
+    TabNext,
+    TabPrevious,
+    TabClose,
+    TabOnly,
+}
+
+/// What a command accepts after its name.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Args {
+    None,
+    Optional,
+    Many,
+    /// Left unsplit for the command to parse itself.
+    Raw,
+}
+
+struct Spec {
+    name: &'static str,
+    /// The shortest abbreviation, e.g. 3 for `red[o]`.
+    min: usize,
+    command: Name,
+    bang: bool,
+    range: bool,
+    args: Args,
+}
+
+const fn spec(name: &'static str, min: usize, command: Name, bang: bool, range: bool, args: Args) -> Spec {
+    Spec { name, min, command, bang, range, args }
+}
+
+/// Checked in order, so a shorter abbreviation wins: `s` is `:substitute`, not `:set`.
+const COMMANDS: &[Spec] = &[
+    spec("edit", 1, Name::Edit, true, false, Args::Optional),
+    spec("open", 1, Name::Edit, true, false, Args::Optional),
+    spec("write", 1, Name::Write, true, false, Args::Optional),
+    spec("wq", 2, Name::WriteQuit, true, false, Args::Optional),
+    spec("xit", 1, Name::WriteQuit, true, false, Args::Optional),
+    spec("quit", 1, Name::Quit, true, false, Args::None),
+    spec("close", 3, Name::Close, true, false, Args::None),
+    spec("undo", 1, Name::Undo, false, false, Args::Optional),
+    spec("redo", 3, Name::Redo, false, false, Args::None),
+    spec("earlier", 2, Name::Earlier, false, false, Args::Optional),
+    spec("later", 3, Name::Later, false, false, Args::Optional),
+    spec("substitute", 1, Name::Substitute, false, true, Args::Raw),
+    spec("set", 2, Name::Set, false, false, Args::Many),
+    spec("setlocal", 4, Name::SetLocal, false, false, Args::Many),
+    spec("colorscheme", 4, Name::ColorScheme, false, false, Args::Optional),
+    spec("nohlsearch", 3, Name::NoHighlight, false, false, Args::None),
+    spec("ls", 2, Name::List, false, false, Args::None),
+    spec("files", 5, Name::List, false, false, Args::None),
+    spec("bnext", 2, Name::BufferNext, false, false, Args::Optional),
+    spec("bNext", 2, Name::BufferPrevious, false, false, Args::Optional),
+    spec("bprevious", 2, Name::BufferPrevious, false, false, Args::Optional),
+    spec("bdelete", 2, Name::BufferDelete, true, false, Args::Optional),
+    spec("buffer", 1, Name::Buffer, false, false, Args::Optional),
+    spec("buffers", 7, Name::List, false, false, Args::None),
+    spec("who", 3, Name::Who, false, false, Args::None),
+    spec("follow", 3, Name::Follow, true, false, Args::Optional),
+    spec("split", 2, Name::Split, false, false, Args::Optional),
+    spec("vsplit", 2, Name::VerticalSplit, false, false, Args::Optional),
+    spec("only", 2, Name::Only, true, false, Args::None),
+    spec("resize", 3, Name::Resize, false, false, Args::Optional),
+    spec("tabnext", 4, Name::TabNext, false, false, Args::Optional),
+    spec("tabNext", 4, Name::TabPrevious, false, false, Args::Optional),
+    spec("tabprevious", 4, Name::TabPrevious, false, false, Args::Optional),
+    spec("tabnew", 6, Name::TabNew, false, false, Args::Optional),
+    spec("tabedit", 4, Name::TabNew, false, false, Args::Optional),
+    spec("tabclose", 4, Name::TabClose, false, false, Args::None),
+    spec("tabonly", 4, Name::TabOnly, false, false, Args::None),
+];
+
+/// What the arguments of a command can be completed from.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Complete {
+    Nothing,
+    File,
+    Option,
+    Buffer,
+}
+
+/// The full names of every command, for completion.
+pub fn command_names() -> impl Iterator<Item = &'static str> {
+    COMMANDS.iter().map(|spec| spec.name)
+}
+
+/// What the arguments of the (possibly abbreviated) command `name` complete from.
+pub fn argument_completion(name: &str) -> Complete {
+    match lookup(name).map(|spec| spec.command) {
+        Some(Name::Edit | Name::Write | Name::WriteQuit | Name::Split | Name::VerticalSplit | Name::TabNew) => Complete::File,
+        Some(Name::Set | Name::SetLocal) => Complete::Option,
+        Some(Name::Buffer | Name::BufferDelete) => Complete::Buffer,
+        _ => Complete::Nothing,
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ExError {
+    NotACommand(String),
+    NoBangAllowed,
+    NoRangeAllowed,
+    TrailingCharacters(String),
+    MissingQuote,
+    Range(String),
+    NoFileName,
+    NoBuffer,
+    Modified,
+    FileExists,
+    ReadOnly,
+    Other(String),
+}
+
+impl fmt::Display for ExError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExError::NotACommand(command) => write!(f, "Not an editor command: {command}"),
+            ExError::NoBangAllowed => write!(f, "No ! allowed"),
+            ExError::NoRangeAllowed => write!(f, "No range allowed"),
+            ExError::TrailingCharacters(rest) => write!(f, "Trailing characters: {rest}"),
+            ExError::MissingQuote => write!(f, "Missing closing quote"),
+            ExError::Range(message) | ExError::Other(message) => write!(f, "{message}"),
+            ExError::NoFileName => write!(f, "No file name"),
+            ExError::NoBuffer => write!(f, "No buffer"),
+            ExError::Modified => write!(f, "No write since last change (add ! to override)"),
+            ExError::FileExists => write!(f, "File exists (add ! to override)"),
+            ExError::ReadOnly => write!(f, "Cannot make changes from a read-only client"),
+        }
+    }
+}
+
+/// A parsed command line such as `3,5s/a/b/` or `w! "my file.txt"`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ExCommand {
+    pub range: Option<LineRange>,
+    pub name: Name,
+    pub bang: bool,
+    /// The arguments with quotes and escapes removed, or for commands that
+    /// parse their own arguments, the unsplit text.
+    pub args: Vec<String>,
+}
+
+impl ExCommand {
+    pub fn arg(&self) -> Option<&str> {
+        self.args.first().map(|arg| arg.as_str())
+    }
+
+    /// Whether the command changes a buffer or a file, which read-only clients can't.
+    pub fn edits(&self) -> bool {
+        match self.name {
+            Name::Write | Name::WriteQuit | Name::Undo | Name::Redo | Name::Earlier | Name::Later | Name::Substitute
+                | Name::BufferDelete => true,
+            // Reloading a file throws away its changes.
+            Name::Edit => self.bang,
+            _ => false,
+        }
+    }
+}
+
+/// Splits arguments on whitespace. Double or single quotes group words
+/// together, and a backslash escapes the character after it.
+pub fn split_args(text: &str) -> Result<Vec<String>, ExError> {
+    let mut args = vec![];
+    let mut chars = text.chars().peekable();
+    loop {
+        while chars.next_if(|ch| ch.is_whitespace()).is_some() {}
+        if chars.peek().is_none() {
+            return Ok(args);
+        }
+        let mut arg = String::new();
+        let mut quote = None;
+        while let Some(ch) = chars.next() {
+            match (quote, ch) {
+                (None, ch) if ch.is_whitespace() => break,
+                (None, '"' | '\'') => quote = Some(ch),
+                (Some(open), ch) if ch == open => quote = None,
+                (Some('\''), ch) => arg.push(ch),
+                (_, '\\') => arg.push(chars.next().unwrap_or('\\')),
+                (_, ch) => arg.push(ch),
+            }
+        }
+        if quote.is_some() {
+            return Err(ExError::MissingQuote);
+        }
+        args.push(arg);
+    }
+}
+
+fn lookup(name: &str) -> Option<&'static Spec> {
+    COMMANDS.iter().find(|spec| name.len() >= spec.min && spec.name.starts_with(name))
+}
+
+/// Parses a command line. `buffer` resolves addresses like `.` and `$` in the range.
+pub fn parse(line: &str, buffer: Option<&Buffer>) -> Result<ExCommand, ExError> {
+    let empty = Buffer::default();
+    let line = line.trim_start_matches(|ch: char| ch == ':' || ch.is_whitespace());
+    let (range, rest) = range::parse(line, buffer.unwrap_or(&empty)).map_err(ExError::Range)?;
+    let rest = rest.trim_start();
+
+    let name_len = rest.len() - rest.trim_start_matches(|ch: char| ch.is_ascii_alphabetic()).len();
+    let (name, rest) = rest.split_at(name_len);
+    if name.is_empty() {
+        return match rest.trim() {
+            "" => Ok(ExCommand { range, name: Name::Goto, bang: false, args: vec![] }),
+            rest => Err(ExError::NotACommand(rest.to_string())),
+        };
+    }
+    let spec = lookup(name).ok_or_else(|| ExError::NotACommand(name.to_string()))?;
+
+    let (bang, rest) = match rest.strip_prefix('!') {
+        Some(rest) => (true, rest),
+        None => (false, rest),
+    };
+    if bang && !spec.bang {
+        return Err(ExError::NoBangAllowed);
+    }
+    if range.is_some() && !spec.range {
+        return Err(ExError::NoRangeAllowed);
+    }
+
+    let args = match spec.args {
+        Args::Raw => vec![rest.to_string()],
+        _ => split_args(rest)?,
+    };
+    let max_args = match spec.args {
+        Args::None => 0,
+        Args::Optional => 1,
+        Args::Many | Args::Raw => usize::MAX,
+    };
+    if args.len() > max_args {
+        return Err(ExError::TrailingCharacters(args[max_args..].join(" ")));
+    }
+
+    Ok(ExCommand { range, name: spec.command, bang, args })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_abbreviations_and_bang() {
+        let command = parse("w!", None).unwrap();
+        assert_eq!((command.name, command.bang), (Name::Write, true));
+        assert_eq!(parse("e foo", None).unwrap().args, vec!["foo"]);
+        assert_eq!(parse("s/a/b/", None).unwrap().name, Name::Substitute);
+        assert_eq!(parse("se ic", None).unwrap().name, Name::Set);
+        assert_eq!(parse("setl ts=4", None).unwrap().name, Name::SetLocal);
+        assert_eq!(parse("colo gruvbox", None).unwrap().name, Name::ColorScheme);
+        assert_eq!(parse("noh", None).unwrap().name, Name::NoHighlight);
+        assert_eq!(parse("who", None).unwrap().name, Name::Who);
+        assert!(parse("s/a/b/", None).unwrap().edits() && parse("e!", None).unwrap().edits());
+        assert!(!parse("e foo", None).unwrap().edits() && !parse("ls", None).unwrap().edits());
+        assert!(parse("bd", None).unwrap().edits() && !parse("q", None).unwrap().edits());
+        assert_eq!(parse("fol! 2", None).unwrap().name, Name::Follow);
+        assert_eq!(parse("b 3", None).unwrap().name, Name::Buffer);
+        assert_eq!(parse("bd!", None).unwrap().name, Name::BufferDelete);
+        assert_eq!(parse("bN", None).unwrap().name, Name::BufferPrevious);
+        assert_eq!(parse("sp", None).unwrap().name, Name::Split);
+        assert_eq!(parse("vs foo", None).unwrap().name, Name::VerticalSplit);
+        assert_eq!(parse("res +2", None).unwrap().args, vec!["+2"]);
+        assert_eq!(parse("tabn", None).unwrap().name, Name::TabNext);
+        assert_eq!(parse("tabnew", None).unwrap().name, Name::TabNew);
+        assert_eq!(parse("tabc", None).unwrap().name, Name::TabClose);
+        assert_eq!(parse("re", None), Err(ExError::NotACommand("re".to_string())));
+        assert_eq!(parse("set!", None), Err(ExError::NoBangAllowed));
+        assert_eq!(parse("1quit", None), Err(ExError::NoRangeAllowed));
+        assert_eq!(parse("q now", None), Err(ExError::TrailingCharacters("now".to_string())));
+    }
+
+    #[test]
+    fn parses_ranges() {
+        let buffer = Buffer::new(None, "a\nb\nc");
+        let command = parse(":%s/a/b/g", Some(&buffer)).unwrap();
+        assert_eq!(command.range, Some(LineRange::new(0, 2)));
+        assert_eq!(command.args, vec!["/a/b/g"]);
+        assert_eq!(parse("2", Some(&buffer)).unwrap().name, Name::Goto);
+        assert!(matches!(parse("9", Some(&buffer)), Err(ExError::Range(_))));
+    }
+
+    #[test]
+    fn splits_quoted_arguments() {
+        assert_eq!(split_args(r#" a "b c"  d\ e 'f\g' "h\"" "#), Ok(vec![
+            "a".to_string(),
+            "b c".to_string(),
+            "d e".to_string(),
+            "f\\g".to_string(),
+            "h\"".to_string(),
+        ]));
+        assert_eq!(split_args("\"open"), Err(ExError::MissingQuote));
+        assert_eq!(parse("open", None).unwrap().arg(), None);
+    }
+}
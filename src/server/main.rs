@@ -1,134 +1,612 @@
 use std::sync::{Arc, Mutex, MutexGuard};
 use std::sync::mpsc;
 use signal_hook::{consts::SIGTERM, consts::SIGINT, iterator::Signals};
-use std::io::{Write, Read};
+use std::io::Write;
 use simplelog::*;
-use anyhow;
 use log::*;
 use lazy_static::lazy_static;
 use serde::{Serialize, Deserialize};
 use std::time::Duration;
 use std::path::{PathBuf, Path};
+use std::collections::HashMap;
 
 use tt::connection::{Connection, Listener};
-use tt::message::{ClientMessage, ServerMessage, Size, Key, Position};
+use tt::message::{ClientMessage, ServerMessage, Key};
 
+pub mod buffer;
+pub mod buflist;
+pub mod cmdline;
+pub mod ex;
+pub mod motion;
+pub mod normal;
+pub mod operator;
+pub mod options;
+pub mod range;
+pub mod registers;
 pub mod render;
+pub mod screen;
+pub mod scroll;
+pub mod search;
+pub mod substitute;
+pub mod syntax;
+pub mod textobject;
+pub mod theme;
+pub mod undo;
+pub mod view;
+pub mod visual;
+pub mod window;
+pub mod wrap;
 
-const IPC_DIR: &'static str = "/home/tac-tics/projects/tt/ipc";
+use buffer::{Buffer, BufferId, Cursor};
+use buflist::BufferList;
+use cmdline::CommandLine;
+use ex::{ExCommand, ExError};
+use motion::Motion;
+use normal::{Action, NormalCommand, Parse};
+use operator::{Operator, Yank, YankKind};
+use options::Options;
+use range::LineRange;
+use registers::Registers;
+use screen::Screen;
+use scroll::{Scroll, Viewport};
+use search::Search;
+use substitute::Substitution;
+use syntax::Grammars;
+use theme::{Theme, Themes};
+use undo::Offset;
+use view::{ClientId, Follow, Session, View};
+use visual::{BlockInsert, Selection};
+use window::{Direction, Layout, Rect, Window, WindowCommand, WindowId};
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+const IPC_DIR: &str = "/home/tac-tics/projects/tt/ipc";
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy, Default)]
 pub enum BufferMode {
+    #[default]
     Normal,
+    OperatorPending,
     Insert,
     Command,
+    Visual,
+    VisualLine,
+    VisualBlock,
+    Search,
+    /// Asking whether to make each replacement of a `:s///c` command.
+    Confirm,
 }
 
-impl Default for BufferMode {
-    fn default() -> Self {
-        BufferMode::Normal
+impl BufferMode {
+    pub fn is_visual(&self) -> bool {
+        matches!(self, BufferMode::Visual | BufferMode::VisualLine | BufferMode::VisualBlock)
     }
 }
 
 
-#[derive(Default, Serialize, Deserialize)]
-pub struct Buffer {
-    pub path: Option<PathBuf>,
-    pub data: String,
-    pub pos: Position,
-}
-
-#[derive(Default, Serialize, Deserialize)]
+#[derive(Default)]
 pub struct TermTextState {
-    pub mode: BufferMode,
-    pub buffers: Vec<Buffer>,
-    pub command: Option<String>,
-    pub size: Size,
+    pub buffers: BufferList,
+    /// What each connected client sees.
+    pub views: HashMap<ClientId, View>,
+    pub grammars: Grammars,
+    pub themes: Themes,
+    /// The color scheme, set by `:colorscheme`.
+    pub theme: Theme,
+    /// The client whose input is being handled.
+    pub client: ClientId,
+    /// What the acting client is in the middle of.
+    pub session: Session,
+    pub command_history: Vec<String>,
+    pub options: Options,
+    pub registers: Registers,
+    pub last_search: Option<Search>,
+    /// Cleared by `:nohlsearch` until the next search.
+    pub highlight_search: bool,
 }
 
 impl TermTextState {
+    /// Adds an empty buffer, with the global buffer options.
+    pub fn new_buffer(&mut self, path: Option<PathBuf>) -> BufferId {
+        let mut buffer = Buffer::new(path, "");
+        buffer.indent = self.options.indent;
+        self.buffers.add(buffer)
+    }
+
+    /// The buffer for `path`, which is created (empty) if it isn't open yet.
     pub fn create_buffer(&mut self, path: &Path) -> &mut Buffer {
-        if self.buffer_exists_by_path(path) {
-            return self.buffer_by_path(path).unwrap();
+        let id = match self.buffers.find_by_path(path) {
+            Some(id) => id,
+            None => self.new_buffer(Some(path.to_path_buf())),
+        };
+        self.buffers.get_mut(id).unwrap()
+    }
+
+    pub fn buffer_by_path(&mut self, path: &Path) -> Option<&mut Buffer> {
+        let id = self.buffers.find_by_path(path)?;
+        self.buffers.get_mut(id)
+    }
+
+    /// The acting client's view.
+    pub fn view(&self) -> &View {
+        self.views.get(&self.client).expect("the acting client has a view")
+    }
+
+    pub fn view_mut(&mut self) -> &mut View {
+        self.views.get_mut(&self.client).expect("the acting client has a view")
+    }
+
+    /// The windows of the acting client's current tab page.
+    pub fn windows(&self) -> &Layout {
+        self.view().layout()
+    }
+
+    pub fn windows_mut(&mut self) -> &mut Layout {
+        self.view_mut().layout_mut()
+    }
+
+    /// Gives a newly connected client a view of the buffer the acting client is looking at.
+    pub fn add_client(&mut self, id: ClientId) {
+        let buffer = self.current_buffer().map(|buffer| buffer.id);
+        let view = View { name: format!("client {id}"), ..View::new(buffer) };
+        self.views.insert(id, view);
+    }
+
+    pub fn remove_client(&mut self, id: ClientId) {
+        self.views.remove(&id);
+        for buffer in self.buffers.iter_mut() {
+            if id == self.client {
+                buffer.history.end_group();
+            } else {
+                buffer.history.end_parked_group(id);
+            }
+        }
+        for view in self.views.values_mut() {
+            if view.following.is_some_and(|follow| follow.leader == id) {
+                view.following = None;
+            }
+        }
+    }
+
+    /// Whether the acting client is read-only, in which case it is told it
+    /// can't make changes.
+    pub fn refuses_edits(&mut self) -> bool {
+        let readonly = self.view().readonly;
+        if readonly {
+            self.session.message = Some(ExError::ReadOnly.to_string());
+        }
+        readonly
+    }
+
+    /// Names the acting client, adding its id if another client has the name
+    /// already, so that `:follow` can tell them apart.
+    pub fn set_name(&mut self, name: String) {
+        let client = self.client;
+        let taken = self.views.iter().any(|(&id, view)| id != client && view.name == name);
+        self.view_mut().name = if taken { format!("{name}#{client}") } else { name };
+    }
+
+    /// Whether the acting client may open `path`, with `reload` as for `:e!`.
+    /// Opening a file that doesn't exist creates it, and reloading an open one
+    /// throws away everyone's changes to it, which a read-only client is told
+    /// it can't do.
+    pub fn may_open(&mut self, path: &Path, reload: bool) -> bool {
+        let is_open = || path.canonicalize().ok().and_then(|path| self.buffers.find_by_path(&path)).is_some();
+        let edits = !path.exists() || (reload && is_open());
+        !edits || !self.refuses_edits()
+    }
+
+    /// Shows the file at `path` in the current window, reading it into a new
+    /// buffer if it isn't open yet. An open file's buffer is shown as it is,
+    /// unsaved changes and all, unless `reload` reads it from disk again.
+    pub fn open_file(&mut self, path: &Path, reload: bool) -> anyhow::Result<()> {
+        if !self.may_open(path, reload) {
+            return Ok(());
+        }
+        if !path.exists() {
+            std::fs::File::create(path)?;
+        }
+        let abs_path = path.canonicalize()?;
+        info!("Abspath: {:?}", abs_path);
+        if let Some(id) = self.buffers.find_by_path(&abs_path).filter(|_| !reload) {
+            self.switch_buffer(id);
+            return Ok(());
         }
+        let undofile = self.options.undofile;
+        let buffer = self.create_buffer(&abs_path);
+        buffer.load(path)?;
+        if undofile && buffer.load_history(&abs_path)? {
+            info!("Restored undo history for {abs_path:?}");
+        }
+        let (id, first_line) = (buffer.id, buffer.line(0));
+        let grammar = self.grammars.detect(Some(&abs_path), &first_line);
+        if let Some(buffer) = self.buffers.get_mut(id) {
+            buffer.highlights.set_grammar(grammar);
+        }
+        self.clamp_cursors(id);
+        self.switch_buffer(id);
+        Ok(())
+    }
+
+    /// The client with id or name `arg`.
+    pub fn find_client(&self, arg: &str) -> Option<ClientId> {
+        match arg.parse::<ClientId>() {
+            Ok(id) => self.views.contains_key(&id).then_some(id),
+            Err(_) => self.views.iter().find(|(_, view)| view.name == arg).map(|(&id, _)| id),
+        }
+    }
 
-        let buffer = Buffer {
-            path: Some(path.to_path_buf()),
-            pos: (0, 0),
-            data: "".to_string(),
+    /// Stops following, moving the current window to where the leader is.
+    pub fn take_over(&mut self) {
+        let leader = match self.view_mut().following.take().and_then(|follow| self.views.get(&follow.leader)) {
+            Some(leader) => leader.layout().current().clone(),
+            None => return,
         };
-        self.buffers.push(buffer);
-        &mut self.buffers[0]
+        if let Some(id) = leader.buffer {
+            self.switch_buffer(id);
+            let window = self.windows_mut().current_mut();
+            window.cursor = leader.cursor;
+            window.want_col = leader.want_col;
+            window.anchor = None;
+            window.top = leader.top;
+            window.left = leader.left;
+            self.restore_cursor();
+        }
     }
 
-    pub fn buffer_exists_by_path(&mut self, path: &Path) -> bool {
-        let pathbuf = path.to_path_buf();
-        for buffer in self.buffers.iter_mut() {
-            if buffer.path.as_ref() == Some(&pathbuf) {
-                return true;
+    /// Makes `id` the acting client, returning false if it isn't connected. The
+    /// acting client's cursor lives in its buffer, so it is swapped as when the
+    /// focus moves between windows, and its session is swapped out of its view.
+    /// Any undo group it has open, such as an insert's, is ended, and begun
+    /// again when it next acts.
+    pub fn set_client(&mut self, id: ClientId) -> bool {
+        if !self.views.contains_key(&id) {
+            return false;
+        }
+        self.follow_changes();
+        if id != self.client {
+            self.save_cursor();
+            for buffer in self.buffers.iter_mut() {
+                buffer.history.park_group(self.client);
+            }
+            if let Some(view) = self.views.get_mut(&self.client) {
+                view.session = std::mem::take(&mut self.session);
+            }
+            self.client = id;
+            self.session = std::mem::take(&mut self.view_mut().session);
+            self.restore_cursor();
+            for buffer in self.buffers.iter_mut() {
+                buffer.history.unpark_group(id, buffer.pos);
             }
         }
-        false
+        true
     }
 
-    pub fn buffer_by_path(&mut self, path: &Path) -> Option<&mut Buffer> {
-        let pathbuf = path.to_path_buf();
-        for buffer in self.buffers.iter_mut() {
-            if buffer.path.as_ref() == Some(&pathbuf) {
-                return Some(buffer);
+    /// What `client` is in the middle of.
+    pub fn session_of(&self, client: ClientId) -> Option<&Session> {
+        if client == self.client {
+            Some(&self.session)
+        } else {
+            self.views.get(&client).map(|view| &view.session)
+        }
+    }
+
+    /// Shows buffer `id` in the current window, remembering the previous buffer
+    /// as the alternate.
+    pub fn switch_buffer(&mut self, id: BufferId) {
+        let window = self.windows_mut().current_mut();
+        if window.buffer != Some(id) {
+            window.alternate = window.buffer;
+            window.buffer = Some(id);
+        }
+    }
+
+    /// Deletes buffer `id`. Windows showing it show their alternate buffer (or
+    /// else a neighbour in the list) in its place.
+    pub fn delete_buffer(&mut self, id: BufferId) -> bool {
+        let neighbour = match self.buffers.get(id) {
+            Some(_) => self.buffers.remove(id),
+            None => return false,
+        };
+        for window in self.views.values_mut().flat_map(|view| view.windows_mut()) {
+            if window.alternate == Some(id) {
+                window.alternate = None;
             }
+            if window.buffer == Some(id) {
+                window.buffer = window.alternate.take().or(neighbour);
+                window.top = 0;
+                window.left = 0;
+            }
+        }
+        true
+    }
+
+    /// Whether a window other than the acting client's current one shows buffer `id`.
+    fn shown_elsewhere(&self, id: BufferId) -> bool {
+        let current = (self.client, self.view().tab, self.windows().current);
+        self.views.iter().any(|(&client, view)| view.tabs.iter().enumerate().any(|(tab, layout)| {
+            layout.windows().any(|window| window.buffer == Some(id) && (client, tab, window.id) != current)
+        }))
+    }
+
+    /// Whether closing the current window deletes its buffer: it is the last
+    /// window, and no other window of any client shows the buffer.
+    pub fn close_deletes_buffer(&self) -> bool {
+        match self.windows().current().buffer {
+            Some(id) => !self.windows().is_split() && !self.shown_elsewhere(id),
+            None => false,
         }
-        None
     }
 
+    /// Stops showing the current window's buffer, deleting it unless some
+    /// other window still shows it.
     pub fn close_current_buffer(&mut self) -> bool {
-        if self.buffers.len() > 0 {
-            self.buffers.remove(0);
-            true
-        } else {
-            false
+        let window = self.windows().current();
+        let id = match window.buffer {
+            Some(id) => id,
+            None => return false,
+        };
+        if !self.shown_elsewhere(id) {
+            return self.delete_buffer(id);
+        }
+        let next = window.alternate
+            .filter(|&alternate| alternate != id)
+            .or_else(|| self.buffers.cycle(id, 1).filter(|&next| next != id));
+        match next {
+            Some(next) => {
+                self.switch_buffer(next);
+                true
+            },
+            None => false,
         }
     }
 
     pub fn current_buffer(&self) -> Option<&Buffer> {
-        if self.buffers.len() > 0 {
-            Some(&self.buffers[0])
-        } else {
-            None
-        }
+        let view = self.views.get(&self.client)?;
+        self.buffers.get(view.layout().current().buffer?)
     }
 
     pub fn current_buffer_mut(&mut self) -> Option<&mut Buffer> {
-        if self.buffers.len() > 0 {
-            Some(&mut self.buffers[0])
-        } else {
-            None
+        let view = self.views.get(&self.client)?;
+        self.buffers.get_mut(view.layout().current().buffer?)
+    }
+
+    /// The part of the acting client's screen the windows share.
+    pub fn screen_area(&self) -> Rect {
+        self.view().area()
+    }
+
+    /// Moves the focus to window `id`. The cursor of the focused window lives
+    /// in its buffer, so it is saved into the old window and restored from the new one.
+    pub fn focus_window(&mut self, id: WindowId) {
+        self.save_cursor();
+        self.windows_mut().current = id;
+        self.restore_cursor();
+    }
+
+    /// Shows tab page `index`, moving the focus to its current window.
+    pub fn switch_tab(&mut self, index: usize) {
+        self.save_cursor();
+        self.view_mut().tab = index;
+        self.restore_cursor();
+    }
+
+    fn save_cursor(&mut self) {
+        if let Some(buffer) = self.current_buffer() {
+            let (pos, want_col, anchor) = (buffer.pos, buffer.want_col, buffer.anchor);
+            let window = self.windows_mut().current_mut();
+            window.cursor = pos;
+            window.want_col = want_col;
+            window.anchor = anchor;
+        }
+    }
+
+    fn restore_cursor(&mut self) {
+        let window = self.windows().current().clone();
+        if let Some(buffer) = self.current_buffer_mut() {
+            buffer.pos = buffer.clamp_normal(window.cursor);
+            buffer.want_col = window.want_col;
+            buffer.anchor = window.anchor.map(|anchor| buffer.clamp(anchor));
+        }
+    }
+
+    /// Moves the cursors kept in windows, and the lines they show from, past
+    /// the changes made to their buffers, so that each stays on its text when
+    /// another client (or another window) edits it.
+    pub fn follow_changes(&mut self) {
+        for buffer in self.buffers.iter_mut() {
+            let changes = std::mem::take(&mut buffer.changes);
+            if changes.is_empty() {
+                continue;
+            }
+            for (&client, view) in self.views.iter_mut() {
+                let current_tab = view.tab;
+                for (tab, layout) in view.tabs.iter_mut().enumerate() {
+                    let current = layout.current;
+                    for window in layout.windows_mut().filter(|window| window.buffer == Some(buffer.id)) {
+                        // The acting client's cursor is the buffer's own, which the edits moved already.
+                        if client == self.client && tab == current_tab && window.id == current {
+                            continue;
+                        }
+                        for change in &changes {
+                            window.cursor = change.shift(window.cursor);
+                            window.anchor = window.anchor.map(|anchor| change.shift(anchor));
+                            window.top = change.shift(Cursor::new(window.top, 0)).line;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Clamps the cursors kept in windows showing buffer `id` to its text,
+    /// which unlike an edit's leaves them where they were when it's reloaded.
+    pub fn clamp_cursors(&mut self, id: BufferId) {
+        let buffer = match self.buffers.get(id) {
+            Some(buffer) => buffer,
+            None => return,
+        };
+        for window in self.views.values_mut().flat_map(|view| view.windows_mut()) {
+            if window.buffer == Some(id) {
+                window.cursor = buffer.clamp_normal(window.cursor);
+                window.anchor = window.anchor.map(|anchor| buffer.clamp(anchor));
+                window.top = window.top.min(buffer.line_count() - 1);
+            }
+        }
+    }
+
+    /// Closes window `id`, returning false if it is the last one.
+    pub fn close_window(&mut self, id: WindowId) -> bool {
+        let current = self.windows().current;
+        match self.windows_mut().close(id) {
+            Some(next) if id == current => {
+                self.windows_mut().current = next;
+                self.restore_cursor();
+                true
+            },
+            Some(_) => true,
+            None => false,
+        }
+    }
+
+    /// Closes the current window, as `:q` does. Quitting the last one closes
+    /// its buffer too, which a read-only client may not do if that deletes it.
+    pub fn close_current_window(&mut self) {
+        if self.close_window(self.windows().current) {
+            return;
+        }
+        if self.close_deletes_buffer() && self.refuses_edits() {
+            return;
+        }
+        self.close_current_buffer();
+    }
+
+    /// Opens a tab page after the current one, showing `buffer`.
+    pub fn new_tab(&mut self, buffer: Option<BufferId>) {
+        self.save_cursor();
+        let view = self.view_mut();
+        view.tab = view.add_tab(buffer);
+        self.restore_cursor();
+    }
+
+    /// Closes tab page `index`, returning false if it is the last one.
+    pub fn close_tab(&mut self, index: usize) -> bool {
+        let current = self.view().tab == index;
+        let closed = self.view_mut().close_tab(index);
+        if closed && current {
+            self.restore_cursor();
+        }
+        closed
+    }
+
+    /// The text area of the current window.
+    fn viewport(&self) -> Option<Viewport> {
+        let rect = self.windows().rect(self.screen_area(), self.windows().current)?;
+        Some(render::viewport(&self.options, rect))
+    }
+
+    /// Moves the cursor of the current window. Screen row motions need to know
+    /// how the window wraps lines.
+    pub fn move_cursor(&mut self, motion: Motion, count: Option<usize>) {
+        let wrap = self.viewport().and_then(|viewport| viewport.wrap);
+        let buffer = match self.current_buffer_mut() {
+            Some(buffer) => buffer,
+            None => return,
+        };
+        match (motion, wrap) {
+            (Motion::ScreenDown | Motion::ScreenUp, Some(wrap)) => {
+                wrap::move_by_rows(buffer, &wrap, motion == Motion::ScreenDown, count.unwrap_or(1));
+            },
+            _ => {
+                motion::move_cursor(buffer, motion, count);
+                buffer.pos = buffer.clamp_normal(buffer.pos);
+            },
+        }
+    }
+
+    /// The acting client's current window and the buffer it shows.
+    fn live_window(&mut self) -> Option<(&mut Window, &mut Buffer)> {
+        let window = self.views.get_mut(&self.client)?.layout_mut().current_mut();
+        let buffer = self.buffers.get_mut(window.buffer?)?;
+        Some((window, buffer))
+    }
+
+    /// Scrolls the current window so that the cursor is visible.
+    pub fn scroll_to_cursor(&mut self) {
+        let viewport = match self.views.get(&self.client).and_then(|_| self.viewport()) {
+            Some(viewport) => viewport,
+            None => return,
+        };
+        if let Some((window, buffer)) = self.live_window() {
+            let col = render::screen_col(&buffer.line(buffer.pos.line), buffer.pos.col, buffer.indent.tabstop);
+            scroll::follow_cursor(window, buffer, buffer.pos.line, col, viewport);
+        }
+    }
+
+    /// Highlights each buffer shown down to the last of its lines on screen.
+    pub fn highlight_windows(&mut self) {
+        let mut shown: HashMap<BufferId, usize> = HashMap::new();
+        for view in self.views.values() {
+            let layout = view.layout();
+            for (id, rect) in layout.rects(view.area()) {
+                if let Some((buffer, window)) = layout.get(id).and_then(|window| window.buffer.map(|buffer| (buffer, window))) {
+                    let last = shown.entry(buffer).or_default();
+                    *last = (*last).max(window.top + rect.height as usize);
+                }
+            }
+        }
+        for (id, last) in shown {
+            if let Some(buffer) = self.buffers.get_mut(id) {
+                buffer.highlights.update(&buffer.text, last);
+            }
+        }
+    }
+
+    /// Scrolls the current window, moving the cursor if it would leave the screen.
+    pub fn scroll(&mut self, scroll: Scroll, count: Option<usize>) {
+        if let (Some(viewport), Some((window, buffer))) = (self.viewport(), self.live_window()) {
+            scroll::scroll(window, buffer, scroll, count, viewport);
         }
     }
 }
 
 #[derive(Clone, PartialEq, Eq, Debug, Copy)]
 struct ConnectedClient {
+    id: ClientId,
     connection: Connection,
 }
 
 impl ConnectedClient {
-
+    fn send(&self, message: ServerMessage) -> anyhow::Result<()> {
+        let mut connection = self.connection;
+        connection.send(message)?;
+        Ok(())
+    }
 }
 
 struct Server {
     state: TermTextState,
     event_sender: mpsc::Sender<ServerEvent>,
     event_receiver: Option<mpsc::Receiver<ServerEvent>>,
+    last_client_id: ClientId,
+    /// What each client was last sent.
+    screens: HashMap<ClientId, Screen>,
 }
 
 impl Server {
     fn new() -> Self {
         let (event_sender, event_receiver) = mpsc::channel();
+        let themes = Themes::load();
+        let state = TermTextState {
+            grammars: Grammars::load(),
+            theme: themes.get("default").cloned().unwrap_or_default(),
+            themes,
+            ..TermTextState::default()
+        };
         Server {
-            state: TermTextState::default(),
+            state,
             event_sender,
             event_receiver: Some(event_receiver),
+            last_client_id: 0,
+            screens: HashMap::new(),
         }
     }
 
@@ -147,10 +625,18 @@ impl Server {
     }
 
     fn connect_client(connection: Connection) {
+        let id = {
+            let server = &mut Server::get();
+            server.last_client_id += 1;
+            let id = server.last_client_id;
+            server.state.add_client(id);
+            id
+        };
         let client = ConnectedClient {
+            id,
             connection,
         };
-        let client1 = client.clone();
+        let client1 = client;
         std::thread::Builder::new().name("client_message_thread".to_string()).spawn(move || {
             client_message_received_thread(client1).unwrap();
         }).unwrap();
@@ -163,22 +649,11 @@ impl Server {
         Ok(())
     }
 
-    fn broadcast(message: ServerMessage) -> anyhow::Result<()> {
-        for client in CLIENTS.lock().unwrap().iter_mut() {
-            client.connection.send(message.clone())?;
-        }
-        Ok(())
-    }
-
     fn disconnect_client(client: ConnectedClient) {
         let mut clients = CLIENTS.lock().unwrap();
-        let mut i = 0;
-        for cur_client in clients.iter() {
-            if *cur_client == client {
-                clients.swap_remove(i);
-                return;
-            }
-            i += 1;
+        if let Some(i) = clients.iter().position(|cur_client| *cur_client == client) {
+            clients.swap_remove(i);
+            return;
         }
         panic!("Tried to remove client from server, but client not found.");
     }
@@ -224,6 +699,23 @@ fn main() {
     info!("Exiting");
 }
 
+/// The JSON files in directory `name` of the user's configuration, which is
+/// `$XDG_CONFIG_HOME/tt` or `~/.config/tt`.
+pub fn config_files(name: &str) -> Vec<PathBuf> {
+    let dir = std::env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+        .map(|config| config.join("tt").join(name));
+    let mut files: Vec<PathBuf> = dir.and_then(|dir| std::fs::read_dir(dir).ok())
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    files.sort();
+    files
+}
+
 fn setup_logging() {
     WriteLogger::init(
         LevelFilter::Info,
@@ -233,9 +725,9 @@ fn setup_logging() {
 }
 
 fn trap_signals() {
-    let mut signals = Signals::new(&[SIGTERM, SIGINT]).unwrap();
+    let mut signals = Signals::new([SIGTERM, SIGINT]).unwrap();
     std::thread::spawn(move || {
-        for sig in signals.forever() {
+        if let Some(sig) = signals.forever().next() {
             debug!("Received signal: {sig}");
             let pid_file = &format!("{IPC_DIR}/tt.pid");
             let path = std::path::Path::new(pid_file);
@@ -260,10 +752,19 @@ fn trap_signals() {
 #[derive(Debug)]
 enum ServerEvent {
     ClientMessageReceived(ConnectedClient, ClientMessage),
-    OpenFile(PathBuf),
-    WriteFile(PathBuf),
-    CloseFile(),
-    IssueCommand(String),
+    OpenFile(ClientId, PathBuf),
+    CloseFile(ClientId),
+    IssueCommand(ClientId, String),
+}
+
+impl ServerEvent {
+    /// The client the event acts for.
+    fn client(&self) -> ClientId {
+        match self {
+            ServerEvent::ClientMessageReceived(client, _) => client.id,
+            ServerEvent::OpenFile(client, _) | ServerEvent::CloseFile(client) | ServerEvent::IssueCommand(client, _) => *client,
+        }
+    }
 }
 
 fn client_message_received_thread(mut client: ConnectedClient) -> anyhow::Result<()> {
@@ -286,162 +787,781 @@ fn client_message_received_thread(mut client: ConnectedClient) -> anyhow::Result
 fn send_update() -> anyhow::Result<()> {
     info!("send_update()");
 
-    for message in render::render(&Server::get().state) {
-        Server::broadcast(message)?;
+    let mut server = Server::get();
+    let server = &mut *server;
+    server.state.follow_changes();
+    server.state.scroll_to_cursor();
+    server.state.highlight_windows();
+    for client in CLIENTS.lock().unwrap().iter() {
+        let frame = render::render(&server.state, client.id);
+        let screen = server.screens.entry(client.id).or_default();
+        let size = server.state.views.get(&client.id).map_or((0, 0), |view| view.size);
+        for message in screen.update(size, frame) {
+            client.send(message)?;
+        }
     }
     Ok(())
 }
 
 fn handle_server_event(event: ServerEvent) -> anyhow::Result<()> {
+    let client_id = event.client();
+    if !Server::with_state(|state| state.set_client(client_id)) {
+        info!("Ignoring event for disconnected client: {event:?}");
+        return Ok(());
+    }
     match event {
         ServerEvent::ClientMessageReceived(client, message) => {
             info!("Received message: {message:?}");
             match message {
-                ClientMessage::Connect(args) => {
-                    if args.len() > 0 {
+                ClientMessage::Connect { args, color_depth, name, readonly } => {
+                    Server::with_state(|state| {
+                        let view = state.view_mut();
+                        view.colors = color_depth;
+                        view.readonly = readonly;
+                        if !name.is_empty() {
+                            state.set_name(name);
+                        }
+                    });
+                    if !args.is_empty() {
                         let filename = &args[0];
-                        Server::trigger(ServerEvent::OpenFile(PathBuf::from(filename)))?;
+                        Server::trigger(ServerEvent::OpenFile(client.id, PathBuf::from(filename)))?;
                     }
                     send_update()?;
                 },
                 ClientMessage::RequestRefresh => {
+                    Server::get().screens.remove(&client.id);
                     send_update()?;
                 },
                 ClientMessage::Disconnect => {
                     Server::with_state(|state| {
-                        state.mode = BufferMode::Normal;
-                        state.command = None;
+                        state.session = Session::default();
+                        state.remove_client(client.id);
                     });
+                    Server::get().screens.remove(&client.id);
 
                     Server::disconnect_client(client);
+                    send_update()?;
                 },
                 ClientMessage::SendInput(key) => {
-                    handle_input(key)?;
+                    handle_input(client, key)?;
+                    send_update()?;
+                },
+                ClientMessage::ClipboardContents(contents) => {
+                    clipboard_received(contents);
                     send_update()?;
                 },
                 ClientMessage::Resize(size) => {
                     Server::with_state(|state| {
-                        state.size = size;
+                        state.view_mut().size = size;
                     });
                     send_update()?;
                 },
             }
         },
-        ServerEvent::OpenFile(filepath) => {
+        ServerEvent::OpenFile(_, filepath) => {
             info!("Handling OpenFile({filepath:?})");
-            let abs_filepath = filepath.canonicalize()?;
-            info!("Abspath: {:?}", abs_filepath);
-            if !filepath.exists() {
-                std::fs::File::create(&filepath)?;
-            }
-            let mut file = std::fs::File::open(&filepath)?;
-            let mut data = String::new();
-            file.read_to_string(&mut data)?;
-            Server::with_state(|state| {
-                if let Some(buffer) = state.buffer_by_path(&abs_filepath) {
-                    buffer.data = data;
-                } else {
-                    state.create_buffer(&abs_filepath);
-                }
+            Server::with_state(|state| state.open_file(&filepath, false))?;
+            send_update()?;
+        },
+        ServerEvent::CloseFile(_) => {
+            info!("Handling CloseFile()");
+            Server::with_state(|state| state.close_current_window());
+            send_update()?;
+        },
+        ServerEvent::IssueCommand(_, command) => {
+            info!("COMMAND: {command:?}");
+            let result = Server::with_state(|state| ex::parse(&command, state.current_buffer()))
+                .and_then(run_ex_command);
+            if let Err(e) = result {
+                info!("Command failed: {e:?}");
+                Server::with_state(|state| state.session.message = Some(e.to_string()));
+            }
+            send_update()?;
+        },
+    }
+    Ok(())
+}
 
+fn run_ex_command(command: ExCommand) -> Result<(), ExError> {
+    let (client, readonly) = Server::with_state(|state| (state.client, state.view().readonly));
+    if readonly && command.edits() {
+        return Err(ExError::ReadOnly);
+    }
+    let trigger = |event| Server::trigger(event).map_err(|e| ExError::Other(e.to_string()));
+    match command.name {
+        ex::Name::Goto => Server::with_state(|state| {
+            if let (Some(range), Some(buffer)) = (command.range, state.current_buffer_mut()) {
+                buffer.pos = Cursor::new(range.end, motion::first_non_blank(buffer, range.end));
+                buffer.want_col = buffer.pos.col;
+            }
+        }),
+        ex::Name::Edit => {
+            let path = match command.arg() {
+                Some(arg) => PathBuf::from(arg),
+                None => Server::with_state(|state| state.current_buffer().and_then(|buffer| buffer.path.clone()))
+                    .ok_or(ExError::NoFileName)?,
+            };
+            let modified = Server::with_state(|state| {
+                let abs_path = path.canonicalize().ok()?;
+                state.buffer_by_path(&abs_path).map(|buffer| buffer.is_modified())
             });
-            send_update()?;
+            if modified == Some(true) && !command.bang {
+                return Err(ExError::Modified);
+            }
+            // Only `:e!` reads a file that is already open from disk again.
+            Server::with_state(|state| state.open_file(&path, command.bang))
+                .map_err(|e| ExError::Other(e.to_string()))?;
         },
-        ServerEvent::WriteFile(filepath) => {
-            info!("Handling WriteFile({filepath:?})");
-            let mut file = std::fs::File::options()
-                .write(true)
-                .truncate(true)
-                .create(true)
-                .open(filepath)?;
-
-            let state = &mut Server::get().state;
-            if let Some(buffer) = &mut state.current_buffer_mut() {
-                let data = buffer.data.clone();
-                file.write_all(&data.as_bytes())?;
-            } else {
-                error!("No buffer to write");
+        ex::Name::Write | ex::Name::WriteQuit => {
+            Server::with_state(|state| write_command(state, &command))?;
+            if command.name == ex::Name::WriteQuit {
+                trigger(ServerEvent::CloseFile(client))?;
             }
         },
-        ServerEvent::CloseFile() => {
-            info!("Handling CloseFile()");
-            Server::with_state(|state| {
-                state.close_current_buffer();
+        ex::Name::Quit => {
+            let modified = Server::with_state(|state| {
+                // Closing one of several windows, or one whose buffer another
+                // window shows, leaves the buffer open.
+                state.current_buffer().map(|buffer| buffer.is_modified() && state.close_deletes_buffer())
             });
-            send_update()?;
+            if modified == Some(true) && !command.bang {
+                return Err(ExError::Modified);
+            }
+            trigger(ServerEvent::CloseFile(client))?;
         },
-        ServerEvent::IssueCommand(command) => {
-            info!("COMMAND: {command:?}");
-            let command_parts: Vec<String> = command.split(' ').map(|s| s.to_owned()).collect();
-            if command_parts.len() > 0 {
-                if command_parts[0] == "open" {
-                    let filename = PathBuf::from(command_parts[1].to_owned());
-                    Server::trigger(ServerEvent::OpenFile(filename))?;
-                } else if command_parts[0] == "write" {
-                    if command_parts.len() > 1 {
-                        let filepath = PathBuf::from(command_parts[1].clone());
-                        let state = &mut Server::get().state;
-
-                        if let Some(buffer) = state.current_buffer_mut() {
-                            buffer.path = Some(filepath);
-                        }
+        ex::Name::Undo | ex::Name::Redo | ex::Name::Earlier | ex::Name::Later => Server::with_state(|state| {
+            state.session.message = Some(history_command(state, command.name, command.arg().unwrap_or("")));
+        }),
+        ex::Name::Substitute => Server::with_state(|state| {
+            let line = state.current_buffer().ok_or(ExError::NoBuffer)?.pos.line;
+            let range = command.range.unwrap_or(LineRange::new(line, line));
+            substitute_command(state, range, command.arg().unwrap_or("")).map_err(ExError::Other)
+        })?,
+        ex::Name::Set => Server::with_state(|state| {
+            for arg in &command.args {
+                state.options.set(arg).map_err(ExError::Other)?;
+                // The current buffer's copy of a buffer option changes too.
+                if let Some(buffer) = state.current_buffer_mut() {
+                    buffer.indent.set(arg).map_err(ExError::Other)?;
+                }
+            }
+            Ok(())
+        })?,
+        ex::Name::SetLocal => Server::with_state(|state| {
+            for arg in &command.args {
+                let local = match state.current_buffer_mut() {
+                    Some(buffer) => buffer.indent.set(arg).map_err(ExError::Other)?,
+                    None => false,
+                };
+                // Global options have no local value, so `:setlocal` sets them as `:set` does.
+                if !local {
+                    state.options.set(arg).map_err(ExError::Other)?;
+                }
+            }
+            Ok(())
+        })?,
+        ex::Name::ColorScheme => Server::with_state(|state| {
+            match command.arg() {
+                Some(name) => state.theme = state.themes.get(name).cloned().ok_or_else(|| ExError::Other(format!("Cannot find color scheme '{name}'")))?,
+                None => state.session.message = Some(state.theme.name.clone()),
+            }
+            Ok(())
+        })?,
+        ex::Name::NoHighlight => Server::with_state(|state| state.highlight_search = false),
+        ex::Name::List => Server::with_state(|state| state.session.message = Some(buffer_list(state))),
+        ex::Name::Who => Server::with_state(|state| state.session.message = Some(client_list(state))),
+        ex::Name::Follow => Server::with_state(|state| {
+            match command.arg() {
+                Some(arg) => {
+                    let leader = state.find_client(arg).ok_or_else(|| ExError::Other(format!("No such client: {arg}")))?;
+                    if leader == state.client {
+                        return Err(ExError::Other("Cannot follow yourself".to_string()));
                     }
-                    let state = &mut Server::get().state;
-                    if let Some(buffer) = state.current_buffer_mut() {
-                        let path = buffer.path.clone();
-
-                        if let Some(filename) = path {
-                            Server::trigger(ServerEvent::WriteFile(filename.clone()))?;
-                        }
+                    state.view_mut().following = Some(Follow { leader, takeover: command.bang });
+                },
+                None => {
+                    if state.view_mut().following.take().is_none() {
+                        state.session.message = Some("Not following anyone".to_string());
                     }
-                } else if command_parts[0] == "close" {
-                    Server::trigger(ServerEvent::CloseFile())?;
-                } else if command_parts[0] == "open" && command_parts.len() > 1 {
-                    let filename = PathBuf::from(command_parts[1].clone());
-                    Server::trigger(ServerEvent::WriteFile(filename))?;
+                },
+            }
+            Ok(())
+        })?,
+        ex::Name::BufferNext | ex::Name::BufferPrevious => Server::with_state(|state| {
+            let count = match command.arg() {
+                Some(arg) => arg.parse::<isize>().map_err(|_| ExError::Other(format!("Invalid argument: {arg}")))?,
+                None => 1,
+            };
+            let offset = if command.name == ex::Name::BufferNext { count } else { -count };
+            let id = state.windows().current().buffer.and_then(|id| state.buffers.cycle(id, offset)).ok_or(ExError::NoBuffer)?;
+            state.switch_buffer(id);
+            Ok(())
+        })?,
+        ex::Name::Buffer => Server::with_state(|state| {
+            if let Some(arg) = command.arg() {
+                let id = state.buffers.find(arg).map_err(ExError::Other)?;
+                state.switch_buffer(id);
+            }
+            Ok(())
+        })?,
+        ex::Name::BufferDelete => Server::with_state(|state| {
+            let id = match command.arg() {
+                Some(arg) => state.buffers.find(arg).map_err(ExError::Other)?,
+                None => state.windows().current().buffer.ok_or(ExError::NoBuffer)?,
+            };
+            if state.buffers.get(id).map(|buffer| buffer.is_modified()) == Some(true) && !command.bang {
+                return Err(ExError::Modified);
+            }
+            state.delete_buffer(id);
+            Ok(())
+        })?,
+        ex::Name::Split | ex::Name::VerticalSplit => {
+            let direction = if command.name == ex::Name::Split { Direction::Down } else { Direction::Right };
+            Server::with_state(|state| window_command(state, WindowCommand::Split(direction), None))
+                .map_err(ExError::Other)?;
+            if let Some(arg) = command.arg() {
+                trigger(ServerEvent::OpenFile(client, PathBuf::from(arg)))?;
+            }
+        },
+        ex::Name::Close => Server::with_state(|state| window_command(state, WindowCommand::Close, None))
+            .map_err(ExError::Other)?,
+        ex::Name::Only => Server::with_state(|state| window_command(state, WindowCommand::Only, None))
+            .map_err(ExError::Other)?,
+        ex::Name::Resize => {
+            let arg = command.arg().unwrap_or("");
+            let invalid = || ExError::Other(format!("Invalid argument: {arg}"));
+            let (action, count) = match arg.chars().next() {
+                None => (WindowCommand::SetSize(Direction::Down), None),
+                Some(sign @ ('+' | '-')) => {
+                    let amount = arg[1..].parse::<usize>().map_err(|_| invalid())?;
+                    (WindowCommand::Resize(Direction::Down, if sign == '+' { 1 } else { -1 }), Some(amount))
+                },
+                Some(_) => (WindowCommand::SetSize(Direction::Down), Some(arg.parse::<usize>().map_err(|_| invalid())?)),
+            };
+            Server::with_state(|state| window_command(state, action, count)).map_err(ExError::Other)?;
+        },
+        ex::Name::TabNew => {
+            Server::with_state(|state| {
+                let buffer = match command.arg() {
+                    // The file is opened into the new tab once it exists.
+                    Some(_) => state.windows().current().buffer,
+                    None => Some(state.new_buffer(None)),
+                };
+                state.new_tab(buffer);
+            });
+            if let Some(arg) = command.arg() {
+                trigger(ServerEvent::OpenFile(client, PathBuf::from(arg)))?;
+            }
+        },
+        ex::Name::TabNext | ex::Name::TabPrevious => Server::with_state(|state| {
+            let count = match command.arg() {
+                Some(arg) => Some(arg.parse::<usize>().map_err(|_| ExError::Other(format!("Invalid argument: {arg}")))?),
+                None => None,
+            };
+            switch_tab(state, count, command.name == ex::Name::TabPrevious).map_err(ExError::Other)
+        })?,
+        ex::Name::TabClose => Server::with_state(|state| {
+            let tab = state.view().tab;
+            if !state.close_tab(tab) {
+                return Err(ExError::Other("Cannot close last tab page".to_string()));
+            }
+            Ok(())
+        })?,
+        ex::Name::TabOnly => Server::with_state(|state| state.view_mut().only_tab()),
+    }
+    Ok(())
+}
+
+/// Goes to tab page `count` (counting from 1), or without a count, the next
+/// one. In `reverse`, goes `count` tab pages back instead.
+fn switch_tab(state: &mut TermTextState, count: Option<usize>, reverse: bool) -> Result<(), String> {
+    let view = state.view();
+    let index = match (reverse, count) {
+        (false, Some(n)) if (1..=view.tabs.len()).contains(&n) => n - 1,
+        (false, Some(n)) => return Err(format!("Tab page {n} does not exist")),
+        (false, None) => view.cycle_tab(1),
+        (true, count) => view.cycle_tab(-(count.unwrap_or(1) as isize)),
+    };
+    state.switch_tab(index);
+    Ok(())
+}
+
+/// Runs a `Ctrl-w` command, or the ex command that does the same.
+fn window_command(state: &mut TermTextState, command: WindowCommand, count: Option<usize>) -> Result<(), String> {
+    let area = state.screen_area();
+    let current = state.windows().current;
+    match command {
+        WindowCommand::Split(direction) => {
+            // The new window starts out at the same place as the one it was split from.
+            state.save_cursor();
+            let windows = state.windows_mut();
+            windows.current = windows.split(direction);
+        },
+        WindowCommand::Close | WindowCommand::Quit => {
+            if !state.close_window(current) {
+                return Err("Cannot close last window".to_string());
+            }
+        },
+        WindowCommand::Only => state.windows_mut().only(),
+        WindowCommand::Focus(side) => {
+            for _ in 0..count.unwrap_or(1) {
+                let windows = state.windows();
+                let rect = windows.rect(area, windows.current).unwrap_or(area);
+                let at = render::window_cursor(state, windows.current(), rect, true);
+                match windows.neighbour(area, windows.current, side, at) {
+                    Some(next) => state.focus_window(next),
+                    None => break,
                 }
+            }
+        },
+        WindowCommand::Next | WindowCommand::Previous => {
+            let offset = if command == WindowCommand::Next { 1 } else { -1 };
+            let next = state.windows().cycle(current, offset);
+            state.focus_window(next);
+        },
+        WindowCommand::Resize(direction, amount) => {
+            state.windows_mut().resize(area, current, direction, amount * count.unwrap_or(1) as isize);
+        },
+        WindowCommand::SetSize(direction) => {
+            state.windows_mut().set_size(area, current, direction, count.unwrap_or(u16::MAX as usize));
+        },
+        WindowCommand::Equalize => state.windows_mut().equalize(),
+    }
+    Ok(())
+}
+
+/// The `:ls` listing: each buffer's number, `%` for the current buffer and `#`
+/// for the alternate, `+` when modified, its name and the cursor's line.
+fn buffer_list(state: &TermTextState) -> String {
+    let lines: Vec<String> = state.buffers.iter()
+        .map(|buffer| {
+            let window = state.windows().current();
+            let shown = if window.buffer == Some(buffer.id) {
+                "%a"
+            } else if window.alternate == Some(buffer.id) {
+                "# "
             } else {
-                info!("No command matched");
+                "  "
+            };
+            let modified = if buffer.is_modified() { '+' } else { ' ' };
+            format!("{:3} {shown} {modified} {:?} line {}", buffer.id, buffer.name(), buffer.pos.line + 1)
+        })
+        .collect();
+    lines.join("\n")
+}
+
+/// The connected clients and where their cursors are, for `:who`.
+fn client_list(state: &TermTextState) -> String {
+    let mut ids: Vec<ClientId> = state.views.keys().copied().collect();
+    ids.sort();
+    let lines: Vec<String> = ids.into_iter()
+        .map(|id| {
+            let view = &state.views[&id];
+            let window = view.layout().current();
+            let buffer = window.buffer.and_then(|id| state.buffers.get(id));
+            let pos = match buffer {
+                Some(buffer) if id == state.client => buffer.pos,
+                _ => window.cursor,
+            };
+            let name = buffer.map_or_else(|| "[No Name]".to_string(), |buffer| buffer.name());
+            let current = if id == state.client { '%' } else { ' ' };
+            let readonly = if view.readonly { " [readonly]" } else { "" };
+            format!("{id:3} {current} {:12} {name:?} line {}{readonly}", view.name, pos.line + 1)
+        })
+        .collect();
+    lines.join("\n")
+}
+
+/// Runs `:write` or `:wq`, which with a file name writes there and renames the
+/// buffer, refusing to overwrite another existing file without `!`.
+fn write_command(state: &mut TermTextState, command: &ExCommand) -> Result<(), ExError> {
+    let undofile = state.options.undofile;
+    let buffer = state.current_buffer_mut().ok_or(ExError::NoBuffer)?;
+    let path = match command.arg() {
+        Some(arg) => {
+            let path = PathBuf::from(arg);
+            if path.exists() && path.canonicalize().ok() != buffer.path && !command.bang {
+                return Err(ExError::FileExists);
             }
+            path
         },
+        None => buffer.path.clone().ok_or(ExError::NoFileName)?,
+    };
+
+    info!("Writing {path:?}");
+    buffer.save(&path).map_err(|e| ExError::Other(format!("Can't write {path:?}: {e}")))?;
+    if undofile {
+        buffer.save_history(&path).map_err(|e| ExError::Other(format!("Can't write undo file: {e}")))?;
+    }
+    let lines = buffer.line_count();
+    buffer.path = Some(path.canonicalize().unwrap_or_else(|_| path.clone()));
+    state.session.message = Some(format!("{path:?} {lines}L written"));
+    Ok(())
+}
+
+fn substitute_command(state: &mut TermTextState, range: LineRange, args: &str) -> Result<(), String> {
+    let command = substitute::parse(args)?;
+    let pattern = if command.pattern.is_empty() {
+        match &state.last_search {
+            Some(search) => search.pattern.clone(),
+            None => return Err("No previous regular expression".to_string()),
+        }
+    } else {
+        command.pattern.clone()
+    };
+    let regex = match command.flags.ignore_case {
+        Some(ignore_case) => search::build(&pattern, ignore_case),
+        None => search::compile(&pattern, &state.options),
+    };
+    let regex = regex.map_err(|_| format!("Invalid pattern: {pattern}"))?;
+    state.last_search = Some(Search { pattern, forward: true });
+
+    let buffer = state.current_buffer_mut().ok_or_else(|| "No buffer".to_string())?;
+    let mut substitution = Substitution::new(regex, &command, range, buffer.line_count());
+    buffer.history.begin_group(buffer.pos);
+    if substitution.start(buffer) {
+        if let Some(pending) = &substitution.pending {
+            buffer.pos = pending.start;
+        }
+        state.session.substitution = Some(substitution);
+        state.session.mode = BufferMode::Confirm;
+    } else {
+        finish_substitution(state, substitution);
+    }
+    Ok(())
+}
+
+fn finish_substitution(state: &mut TermTextState, substitution: Substitution) {
+    state.session.mode = BufferMode::Normal;
+    state.session.message = Some(if substitution.count == 0 {
+        format!("Pattern not found: {}", substitution.regex.as_str())
+    } else {
+        substitution.report()
+    });
+    if let Some(buffer) = state.current_buffer_mut() {
+        buffer.history.end_group();
+        if let (Some(line), false) = (substitution.line, substitution.flags.count_only) {
+            buffer.pos = Cursor::new(line, motion::first_non_blank(buffer, line));
+            buffer.want_col = buffer.pos.col;
+        }
     }
+}
+
+fn handle_visual_command(client: ConnectedClient, command: NormalCommand) -> anyhow::Result<()> {
+    if command.action.edits() && Server::with_state(|state| state.refuses_edits()) {
+        return Ok(());
+    }
+    Server::with_state(|state| {
+        let mode = state.session.mode;
+        let mut new_mode = mode;
+        let mut yanked = None;
+        let mut block_insert = None;
+        if let Action::Move(motion) = command.action {
+            state.move_cursor(motion, command.count);
+            state.session.block_insert = None;
+            return;
+        }
+        let buffer = match state.current_buffer_mut() {
+            Some(buffer) => buffer,
+            None => return,
+        };
+
+        match command.action {
+            Action::Visual(mode) if mode == new_mode => {
+                buffer.end_visual();
+                new_mode = BufferMode::Normal;
+            },
+            Action::Visual(mode) => new_mode = mode,
+            Action::SwapAnchor => {
+                if let Some(anchor) = buffer.anchor.replace(buffer.pos) {
+                    buffer.pos = anchor;
+                    buffer.want_col = anchor.col;
+                }
+            },
+            Action::SelectObject(object) => {
+                if let Some(span) = textobject::select(buffer, buffer.pos, object) {
+                    buffer.anchor = Some(span.start);
+                    buffer.pos = if span.linewise {
+                        Cursor::new(span.end.line, 0)
+                    } else {
+                        buffer.cursor_at(buffer.char_idx(span.end).saturating_sub(1))
+                    };
+                    if span.linewise {
+                        new_mode = BufferMode::VisualLine;
+                    }
+                }
+            },
+            Action::EnterCommand => {
+                buffer.end_visual();
+                new_mode = BufferMode::Command;
+            },
+            Action::OperateSelection(op) => {
+                let selection = visual::selection(buffer, mode);
+                buffer.end_visual();
+                new_mode = if op == Operator::Change { BufferMode::Insert } else { BufferMode::Normal };
+                yanked = match selection {
+                    Some(Selection::Span(span)) => operator::apply(buffer, op, span),
+                    Some(Selection::Block(block)) => {
+                        if op == Operator::Change {
+                            block_insert = Some(BlockInsert { first: block.first, last: block.last, col: block.left, pad: false });
+                        }
+                        operator::apply_block(buffer, op, block)
+                    },
+                    None => None,
+                };
+            },
+            Action::InsertSelection { append } => {
+                let selection = visual::selection(buffer, mode);
+                buffer.end_visual();
+                buffer.history.begin_group(buffer.pos);
+                new_mode = BufferMode::Insert;
+                match selection {
+                    Some(Selection::Block(block)) => {
+                        let col = if append { block.right + 1 } else { block.left };
+                        let len = buffer.line_len(block.first);
+                        if len < col {
+                            buffer.insert(Cursor::new(block.first, len), &" ".repeat(col - len));
+                        }
+                        buffer.pos = Cursor::new(block.first, col);
+                        block_insert = Some(BlockInsert { first: block.first, last: block.last, col, pad: append });
+                    },
+                    Some(Selection::Span(span)) => {
+                        buffer.pos = match (append, span.linewise) {
+                            (false, false) => span.start,
+                            (false, true) => Cursor::new(span.start.line, 0),
+                            (true, false) => buffer.clamp(span.end),
+                            (true, true) => Cursor::new(span.end.line, buffer.line_len(span.end.line)),
+                        };
+                    },
+                    None => (),
+                }
+            },
+            action => info!("Ignoring Normal mode command in Visual mode: {action:?}"),
+        }
+
+        if let (Some(yank), Action::OperateSelection(op)) = (yanked, command.action) {
+            store_yank(state, client, op, command.register, yank);
+        }
+        if new_mode == BufferMode::Command {
+            state.session.command = Some(CommandLine::new("'<,'>"));
+        }
+        state.session.block_insert = block_insert;
+        state.session.mode = new_mode;
+    });
     Ok(())
 }
 
+/// Runs `:undo`, `:redo`, `:earlier` or `:later`, returning a message for the status line.
+fn history_command(state: &mut TermTextState, name: ex::Name, arg: &str) -> String {
+    let buffer = match state.current_buffer_mut() {
+        Some(buffer) => buffer,
+        None => return "No buffer".to_string(),
+    };
+    let before = buffer.history.seq();
+
+    match name {
+        ex::Name::Undo if !arg.is_empty() => match arg.parse::<usize>() {
+            Ok(seq) if buffer.goto_change(seq) => (),
+            _ => return format!("Undo number {arg} not found"),
+        },
+        ex::Name::Undo => {
+            buffer.undo(1);
+        },
+        ex::Name::Redo => {
+            buffer.redo(1);
+        },
+        _ => {
+            let forward = name == ex::Name::Later;
+            match undo::parse_offset(arg) {
+                Some(Offset::Steps(count)) if forward => {
+                    buffer.redo(count);
+                },
+                Some(Offset::Steps(count)) => {
+                    buffer.undo(count);
+                },
+                Some(Offset::Time(offset)) => {
+                    let seq = buffer.history.seq_at_time_offset(offset, forward);
+                    buffer.goto_change(seq);
+                },
+                None => return format!("Invalid argument: {arg}"),
+            }
+        },
+    }
+
+    let after = buffer.history.seq();
+    if before == after {
+        "Already at that change".to_string()
+    } else {
+        format!("Change #{after} of {}", buffer.history.max_seq())
+    }
+}
+
 fn server_event_loop_thread(event_receiver: mpsc::Receiver<ServerEvent>) -> anyhow::Result<()> {
     loop {
         let event = event_receiver.recv()?;
         if let Err(e) = handle_server_event(event) {
             error!("{e:?}");
+            Server::with_state(|state| state.session.message = Some(e.to_string()));
+            if let Err(e) = send_update() {
+                error!("{e:?}");
+            }
         }
     }
 }
 
 
-fn handle_input(key: Key) -> anyhow::Result<()> {
-    let mode = Server::get().state.mode;
+fn handle_input(client: ConnectedClient, key: Key) -> anyhow::Result<()> {
+    let mode = Server::get().state.session.mode;
 
     info!("Mode: {:?}    Key: {:?}", mode, key);
+    Server::with_state(|state| state.session.message = None);
+    // A follower can only use the command line, unless its first key takes over.
+    if mode == BufferMode::Normal && key != Key::Char(':') {
+        let refused = Server::with_state(|state| match state.view().following {
+            Some(follow) if follow.takeover => {
+                state.take_over();
+                false
+            },
+            Some(follow) => {
+                let name = state.views.get(&follow.leader).map_or("", |leader| leader.name.as_str());
+                state.session.message = Some(format!("Following {name}; :follow to stop"));
+                true
+            },
+            None => false,
+        });
+        if refused {
+            return Ok(());
+        }
+    }
     match (mode, key) {
-        (BufferMode::Normal, Key::Char('i')) => {
-            info!("Changing to insert mode");
-            Server::get().state.mode = BufferMode::Insert;
-        },
-        (BufferMode::Normal, Key::Char(':')) => {
-            Server::get().state.mode = BufferMode::Command;
-            Server::get().state.command = Some(String::new());
+        (BufferMode::Confirm, Key::Char(_) | Key::Esc) => {
+            Server::with_state(|state| {
+                let answer = match key {
+                    Key::Char(c) => c,
+                    _ => 'q',
+                };
+                let mut substitution = match state.session.substitution.take() {
+                    Some(substitution) => substitution,
+                    None => return,
+                };
+                let buffer = match state.current_buffer_mut() {
+                    Some(buffer) => buffer,
+                    None => return,
+                };
+                if substitution.confirm(buffer, answer) {
+                    if let Some(pending) = &substitution.pending {
+                        buffer.pos = pending.start;
+                    }
+                    state.session.substitution = Some(substitution);
+                } else {
+                    finish_substitution(state, substitution);
+                }
+            });
         },
         (_, Key::Esc) => {
-            Server::get().state.command = Some(String::new());
             Server::with_state(|state| {
-                state.mode = BufferMode::Normal;
-                state.command = None;
+                let block_insert = state.session.block_insert.take();
+                let insert = state.session.mode == BufferMode::Insert;
+                let search_prompt = state.session.search_prompt.take();
+                if let Some(buffer) = state.current_buffer_mut() {
+                    buffer.end_visual();
+                    if let Some(prompt) = search_prompt {
+                        buffer.pos = prompt.origin;
+                    }
+                    if insert {
+                        if let Some(block_insert) = block_insert {
+                            visual::finish_block_insert(buffer, block_insert);
+                        }
+                        buffer.history.end_group();
+                        buffer.pos.col = buffer.pos.col.saturating_sub(1);
+                        buffer.want_col = buffer.pos.col;
+                    }
+                }
+                state.session.mode = BufferMode::Normal;
+                state.session.command = None;
+                state.session.pending.clear();
+            });
+        },
+        (BufferMode::Normal | BufferMode::OperatorPending, key) => {
+            let parse = Server::with_state(|state| {
+                state.session.pending.push(key);
+                normal::parse(&state.session.pending)
+            });
+            match parse {
+                Parse::Pending => (),
+                Parse::OperatorPending => {
+                    Server::with_state(|state| state.session.mode = BufferMode::OperatorPending);
+                },
+                Parse::Invalid => {
+                    let pending = Server::with_state(|state| {
+                        state.session.mode = BufferMode::Normal;
+                        std::mem::take(&mut state.session.pending)
+                    });
+                    info!("Unknown keybind: {mode:?} {pending:?}");
+                },
+                Parse::Complete(command) => {
+                    Server::with_state(|state| {
+                        state.session.mode = BufferMode::Normal;
+                        state.session.pending.clear();
+                    });
+                    handle_normal_command(client, command)?;
+                },
+            }
+        },
+        (BufferMode::Visual | BufferMode::VisualLine | BufferMode::VisualBlock, key) => {
+            let parse = Server::with_state(|state| {
+                state.session.pending.push(key);
+                normal::parse_visual(&state.session.pending)
+            });
+            match parse {
+                Parse::Pending | Parse::OperatorPending => (),
+                Parse::Invalid => {
+                    let pending = Server::with_state(|state| std::mem::take(&mut state.session.pending));
+                    info!("Unknown keybind: {mode:?} {pending:?}");
+                },
+                Parse::Complete(command) => {
+                    Server::with_state(|state| state.session.pending.clear());
+                    handle_visual_command(client, command)?;
+                },
+            }
+        },
+        (BufferMode::Insert, Key::Left | Key::Right | Key::Up | Key::Down | Key::Home | Key::End) => {
+            let motion = match key {
+                Key::Left => Motion::Left,
+                Key::Right => Motion::Right,
+                Key::Up => Motion::Up,
+                Key::Down => Motion::Down,
+                Key::Home => Motion::LineStart,
+                _ => Motion::LineEnd,
+            };
+            Server::with_state(|state| {
+                if let Some(buffer) = state.current_buffer_mut() {
+                    motion::move_cursor(buffer, motion, None);
+                }
             });
         },
         (BufferMode::Insert, Key::Backspace) => {
             Server::with_state(|state| {
                 if let Some(buffer) = state.current_buffer_mut() {
-                    buffer.data.pop();
+                    buffer.backspace();
+                } else {
+                    error!("No current buffer");
+                }
+            });
+        },
+        (BufferMode::Insert, Key::Delete) => {
+            Server::with_state(|state| {
+                if let Some(buffer) = state.current_buffer_mut() {
+                    buffer.delete();
+                } else {
+                    error!("No current buffer");
+                }
+            });
+        },
+        (BufferMode::Insert, Key::Char('\t')) => {
+            Server::with_state(|state| {
+                if let Some(buffer) = state.current_buffer_mut() {
+                    buffer.insert_tab();
                 } else {
                     error!("No current buffer");
                 }
@@ -450,30 +1570,99 @@ fn handle_input(key: Key) -> anyhow::Result<()> {
         (BufferMode::Insert, Key::Char(c)) => {
             Server::with_state(|state| {
                 if let Some(buffer) = state.current_buffer_mut() {
-                    buffer.data.push(c);
+                    buffer.insert_at_cursor(c.encode_utf8(&mut [0; 4]));
                 } else {
                     error!("No current buffer");
                 }
             });
         },
-        (BufferMode::Command, Key::Char(c)) => {
-            if c == '\n' {
-                let command = Server::with_state(|state| {
-                    state.mode = BufferMode::Normal;
-                    state.command.take().unwrap()
-                });
-                Server::trigger(ServerEvent::IssueCommand(command))?;
-            } else if c == '\t' {
-                // do nothing
-            } else {
-                Server::with_state(|state| {
-                    state.command.as_mut().unwrap().push(c);
-                });
-            }
+        (BufferMode::Command, Key::Char('\n')) => {
+            let command = Server::with_state(|state| {
+                state.session.mode = BufferMode::Normal;
+                let command = state.session.command.take().unwrap_or_default().text;
+                if !command.trim().is_empty() {
+                    state.command_history.retain(|entry| *entry != command);
+                    state.command_history.push(command.clone());
+                }
+                command
+            });
+            Server::trigger(ServerEvent::IssueCommand(client.id, command))?;
+        },
+        (BufferMode::Command, key) => {
+            Server::with_state(|state| {
+                let buffers: Vec<String> = state.buffers.iter().map(|buffer| buffer.name()).collect();
+                let command = match state.session.command.as_mut() {
+                    Some(command) => command,
+                    None => return,
+                };
+                match key {
+                    Key::Char('\t') => command.complete(&buffers, true),
+                    Key::BackTab => command.complete(&buffers, false),
+                    Key::Char(c) => command.insert(c),
+                    Key::Backspace if command.text.is_empty() => {
+                        state.session.command = None;
+                        state.session.mode = BufferMode::Normal;
+                    },
+                    Key::Backspace => {
+                        command.backspace();
+                    },
+                    Key::Delete => command.delete(),
+                    Key::Left => command.move_to(command.cursor.saturating_sub(1)),
+                    Key::Right => command.move_to(command.cursor + 1),
+                    Key::Home | Key::Ctrl('b') => command.move_to(0),
+                    Key::End | Key::Ctrl('e') => command.move_to(usize::MAX),
+                    Key::Up => command.history_prev(&state.command_history),
+                    Key::Down => command.history_next(&state.command_history),
+                    key => info!("Unknown keybind: {mode:?} {key:?}"),
+                }
+            });
+        },
+        (BufferMode::Search, Key::Char('\n')) => {
+            Server::with_state(|state| {
+                state.session.mode = BufferMode::Normal;
+                let prompt = match state.session.search_prompt.take() {
+                    Some(prompt) => prompt,
+                    None => return,
+                };
+                if let Some(buffer) = state.current_buffer_mut() {
+                    buffer.pos = prompt.origin;
+                }
+                let pattern = if prompt.pattern.is_empty() {
+                    match &state.last_search {
+                        Some(search) => search.pattern.clone(),
+                        None => {
+                            state.session.message = Some("No previous search pattern".to_string());
+                            return;
+                        },
+                    }
+                } else {
+                    prompt.pattern
+                };
+                let search = Search { pattern, forward: prompt.forward };
+                search_jump(state, &search, prompt.count);
+                state.last_search = Some(search);
+            });
         },
-        (BufferMode::Command, Key::Backspace) => {
+        (BufferMode::Search, Key::Char(_) | Key::Backspace) => {
             Server::with_state(|state| {
-                state.command.as_mut().unwrap().pop();
+                let prompt = match state.session.search_prompt.as_mut() {
+                    Some(prompt) => prompt,
+                    None => return,
+                };
+                if let Key::Char(c) = key {
+                    prompt.pattern.push(c);
+                } else if prompt.pattern.pop().is_none() {
+                    let origin = prompt.origin;
+                    state.session.search_prompt = None;
+                    state.session.mode = BufferMode::Normal;
+                    if let Some(buffer) = state.current_buffer_mut() {
+                        buffer.pos = origin;
+                    }
+                    return;
+                }
+                if state.options.incsearch {
+                    incremental_search(state);
+                }
             });
         },
         (mode, key) => {
@@ -483,3 +1672,450 @@ fn handle_input(key: Key) -> anyhow::Result<()> {
     send_update()?;
     Ok(())
 }
+
+/// Saves what an operator deleted or yanked into the registers, copying it to
+/// the client's clipboard when a clipboard register was named.
+fn store_yank(state: &mut TermTextState, client: ConnectedClient, op: Operator, register: Option<char>, yank: Yank) {
+    if let Some(name) = register.filter(|&name| registers::is_clipboard(name)) {
+        let message = ServerMessage::SetClipboard(yank.text.clone());
+        if let Err(e) = client.send(message) {
+            error!("Could not send clipboard to client: {e:?}");
+        }
+        state.registers.set(if name == '+' { '*' } else { '+' }, yank.clone());
+    }
+    if op == Operator::Yank {
+        state.registers.yank(register, yank);
+    } else {
+        state.registers.delete(register, yank);
+    }
+}
+
+fn clipboard_received(contents: Option<String>) {
+    Server::with_state(|state| {
+        if let Some(text) = contents {
+            let kind = if text.ends_with('\n') { YankKind::Lines } else { YankKind::Chars };
+            let yank = Yank { text, kind };
+            state.registers.set('+', yank.clone());
+            state.registers.set('*', yank);
+        }
+        if let Some((after, count)) = state.session.clipboard_put.take() {
+            let yank = state.registers.get('+').cloned().unwrap_or_default();
+            if let Some(buffer) = state.current_buffer_mut() {
+                operator::put(buffer, &yank, after, count);
+            }
+        }
+    });
+}
+
+/// Moves the cursor to the first match of the pattern being typed, as long as it
+/// compiles, so that the user sees where the search will land.
+fn incremental_search(state: &mut TermTextState) {
+    let prompt = match &state.session.search_prompt {
+        Some(prompt) => prompt.clone(),
+        None => return,
+    };
+    let regex = search::compile(&prompt.pattern, &state.options).ok();
+    if let Some(buffer) = state.current_buffer_mut() {
+        buffer.pos = regex
+            .filter(|_| !prompt.pattern.is_empty())
+            .and_then(|regex| search::find(buffer, &regex, prompt.origin, prompt.forward))
+            .map(|(found, _wrapped)| found)
+            .unwrap_or(prompt.origin);
+    }
+}
+
+/// Moves the cursor to the `count`th match of `search`, reporting wrap-around
+/// and missing matches in the status line.
+fn search_jump(state: &mut TermTextState, search: &Search, count: usize) {
+    let regex = match search::compile(&search.pattern, &state.options) {
+        Ok(regex) => regex,
+        Err(_) => {
+            state.session.message = Some(format!("Invalid pattern: {}", search.pattern));
+            return;
+        },
+    };
+    state.highlight_search = true;
+    let buffer = match state.current_buffer_mut() {
+        Some(buffer) => buffer,
+        None => return,
+    };
+
+    let mut pos = buffer.pos;
+    let mut wrapped = false;
+    for _ in 0..count.max(1) {
+        match search::find(buffer, &regex, pos, search.forward) {
+            Some((found, wrap)) => {
+                pos = found;
+                wrapped |= wrap;
+            },
+            None => {
+                state.session.message = Some(format!("Pattern not found: {}", search.pattern));
+                return;
+            },
+        }
+    }
+    buffer.pos = pos;
+    buffer.want_col = pos.col;
+
+    if wrapped {
+        let message = if search.forward {
+            "search hit BOTTOM, continuing at TOP"
+        } else {
+            "search hit TOP, continuing at BOTTOM"
+        };
+        state.session.message = Some(message.to_string());
+    }
+}
+
+fn handle_normal_command(client: ConnectedClient, command: NormalCommand) -> anyhow::Result<()> {
+    if command.action.edits() && Server::with_state(|state| state.refuses_edits()) {
+        return Ok(());
+    }
+    match command.action {
+        Action::Move(motion) => {
+            Server::with_state(|state| state.move_cursor(motion, command.count));
+        },
+        Action::Insert => {
+            info!("Changing to insert mode");
+            Server::with_state(|state| {
+                state.session.mode = BufferMode::Insert;
+                if let Some(buffer) = state.current_buffer_mut() {
+                    buffer.history.begin_group(buffer.pos);
+                }
+            });
+        },
+        Action::Undo | Action::Redo => {
+            Server::with_state(|state| {
+                let count = command.count.unwrap_or(1);
+                if let Some(buffer) = state.current_buffer_mut() {
+                    let changed = if command.action == Action::Undo {
+                        buffer.undo(count)
+                    } else {
+                        buffer.redo(count)
+                    };
+                    if changed == 0 {
+                        let which = if command.action == Action::Undo { "oldest" } else { "newest" };
+                        state.session.message = Some(format!("Already at {which} change"));
+                    }
+                }
+            });
+        },
+        Action::EnterCommand => {
+            Server::get().state.session.mode = BufferMode::Command;
+            Server::get().state.session.command = Some(CommandLine::default());
+        },
+        Action::Operate(op, target) => {
+            Server::with_state(|state| {
+                let buffer = match state.current_buffer_mut() {
+                    Some(buffer) => buffer,
+                    None => return,
+                };
+                let span = match operator::span(buffer, op, target, command.count) {
+                    Some(span) => span,
+                    None => return,
+                };
+                if let Some(yank) = operator::apply(buffer, op, span) {
+                    store_yank(state, client, op, command.register, yank);
+                }
+                if op == Operator::Change {
+                    state.session.mode = BufferMode::Insert;
+                }
+            });
+        },
+        Action::Visual(mode) => {
+            Server::with_state(|state| {
+                if let Some(buffer) = state.current_buffer_mut() {
+                    buffer.anchor = Some(buffer.pos);
+                    state.session.mode = mode;
+                }
+            });
+        },
+        Action::Search { forward } => {
+            Server::with_state(|state| {
+                if let Some(buffer) = state.current_buffer() {
+                    state.session.search_prompt = Some(search::Prompt {
+                        forward,
+                        pattern: String::new(),
+                        origin: buffer.pos,
+                        count: command.count.unwrap_or(1),
+                    });
+                    state.session.mode = BufferMode::Search;
+                }
+            });
+        },
+        Action::SearchNext { reverse } => {
+            Server::with_state(|state| {
+                match state.last_search.clone() {
+                    Some(mut search) => {
+                        search.forward ^= reverse;
+                        search_jump(state, &search, command.count.unwrap_or(1));
+                    },
+                    None => state.session.message = Some("No previous search pattern".to_string()),
+                }
+            });
+        },
+        Action::SearchWord { forward } => {
+            Server::with_state(|state| {
+                let pattern = state.current_buffer().and_then(|buffer| search::word_pattern(buffer, buffer.pos));
+                match pattern {
+                    Some(pattern) => {
+                        let search = Search { pattern, forward };
+                        search_jump(state, &search, command.count.unwrap_or(1));
+                        state.last_search = Some(search);
+                    },
+                    None => state.session.message = Some("No string under cursor".to_string()),
+                }
+            });
+        },
+        Action::AlternateBuffer => {
+            Server::with_state(|state| {
+                let id = match command.count {
+                    Some(id) => id,
+                    None => match state.windows().current().alternate {
+                        Some(id) => id,
+                        None => {
+                            state.session.message = Some("No alternate file".to_string());
+                            return;
+                        },
+                    },
+                };
+                if state.buffers.get(id).is_some() {
+                    state.switch_buffer(id);
+                } else {
+                    state.session.message = Some(format!("Buffer {id} does not exist"));
+                }
+            });
+        },
+        Action::Scroll(scroll) => {
+            Server::with_state(|state| state.scroll(scroll, command.count));
+        },
+        Action::SwitchTab { reverse } => {
+            Server::with_state(|state| {
+                if let Err(message) = switch_tab(state, command.count, reverse) {
+                    state.session.message = Some(message);
+                }
+            });
+        },
+        Action::Window(WindowCommand::Quit) => {
+            Server::trigger(ServerEvent::IssueCommand(client.id, "quit".to_string()))?;
+        },
+        Action::Window(window) => {
+            Server::with_state(|state| {
+                if let Err(message) = window_command(state, window, command.count) {
+                    state.session.message = Some(message);
+                }
+            });
+        },
+        Action::SwapAnchor | Action::SelectObject(_) | Action::OperateSelection(_) | Action::InsertSelection { .. } => {
+            info!("Ignoring Visual mode command in Normal mode: {command:?}");
+        },
+        Action::Put { after } => {
+            let register = command.register.unwrap_or('"');
+            let count = command.count.unwrap_or(1);
+            if registers::is_clipboard(register) {
+                Server::with_state(|state| state.session.clipboard_put = Some((after, count)));
+                client.send(ServerMessage::RequestClipboard)?;
+                return Ok(());
+            }
+            Server::with_state(|state| {
+                let yank = state.registers.get(register).cloned().unwrap_or_default();
+                if let Some(buffer) = state.current_buffer_mut() {
+                    operator::put(buffer, &yank, after, count);
+                }
+            });
+        },
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A state with clients 1 and 2 both showing a buffer of `text`.
+    fn shared_buffer(text: &str) -> (TermTextState, BufferId) {
+        let mut state = TermTextState::default();
+        let id = state.buffers.add(Buffer::new(None, text));
+        for client in [1, 2] {
+            state.add_client(client);
+            state.set_client(client);
+            state.switch_buffer(id);
+        }
+        (state, id)
+    }
+
+    #[test]
+    fn open_undo_groups_belong_to_their_client() {
+        let (mut state, id) = shared_buffer("hello world\n");
+        state.set_client(1);
+        let buffer = state.current_buffer_mut().unwrap();
+        buffer.pos = Cursor::new(0, 5);
+        buffer.history.begin_group(buffer.pos);
+        buffer.insert_at_cursor("AB");
+
+        // Client 2's edit before client 1's unfinished insert stays in place.
+        state.set_client(2);
+        state.current_buffer_mut().unwrap().insert(Cursor::new(0, 0), "XYZ");
+
+        state.set_client(1);
+        let buffer = state.buffers.get_mut(id).unwrap();
+        buffer.insert_at_cursor("C");
+        buffer.history.end_group();
+        assert_eq!(buffer.text.to_string(), "XYZhelloABC world\n");
+        assert_eq!(buffer.undo(1), 1);
+        assert_eq!(buffer.text.to_string(), "XYZhelloAB world\n");
+        assert_eq!(buffer.undo(1), 1);
+        assert_eq!(buffer.text.to_string(), "helloAB world\n");
+        assert_eq!(buffer.undo(1), 1);
+        assert_eq!(buffer.text.to_string(), "hello world\n");
+    }
+
+    #[test]
+    fn read_only_clients_cannot_delete_buffers_or_create_files() {
+        let mut state = TermTextState::default();
+        let id = state.buffers.add(Buffer::new(None, "text\n"));
+        state.add_client(1);
+        state.set_client(1);
+        state.switch_buffer(id);
+        state.view_mut().readonly = true;
+
+        state.close_current_window();
+        assert!(state.buffers.get(id).is_some());
+        assert_eq!(state.session.message, Some(ExError::ReadOnly.to_string()));
+
+        state.session.message = None;
+        assert!(state.may_open(Path::new(env!("CARGO_MANIFEST_DIR")), false));
+        assert_eq!(state.session.message, None);
+        assert!(!state.may_open(Path::new("no such file"), false));
+        assert_eq!(state.session.message, Some(ExError::ReadOnly.to_string()));
+    }
+
+    #[test]
+    fn reloading_keeps_other_cursors_where_they_were() {
+        let path = temp_file("reload", "one\ntwo\n");
+        let (mut state, id) = shared_buffer("one\ntwo\nthree\n");
+        state.set_client(1);
+        state.current_buffer_mut().unwrap().pos = Cursor::new(1, 2);
+        state.set_client(2);
+        state.current_buffer_mut().unwrap().pos = Cursor::new(2, 4);
+
+        state.current_buffer_mut().unwrap().load(&path).unwrap();
+        state.clamp_cursors(id);
+        std::fs::remove_file(&path).unwrap();
+        state.set_client(1);
+        assert_eq!(state.current_buffer().unwrap().pos, Cursor::new(1, 2));
+        state.set_client(2);
+        assert_eq!(state.current_buffer().unwrap().pos, Cursor::new(2, 0));
+    }
+
+    /// Writes `text` to a file in the temporary directory named for `name`.
+    fn temp_file(name: &str, text: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("tt-{name}-{}", std::process::id()));
+        std::fs::write(&path, text).unwrap();
+        path
+    }
+
+    #[test]
+    fn opening_an_open_file_keeps_its_changes() {
+        let path = temp_file("split", "on disk\n");
+        let mut state = TermTextState::default();
+        state.add_client(1);
+        state.set_client(1);
+        state.open_file(&path, false).unwrap();
+        state.current_buffer_mut().unwrap().insert_at_cursor("unsaved ");
+
+        // As :split file does.
+        window_command(&mut state, WindowCommand::Split(Direction::Down), None).unwrap();
+        state.open_file(&path, false).unwrap();
+        let buffer = state.current_buffer().unwrap();
+        assert_eq!(buffer.text.to_string(), "unsaved on disk\n");
+        assert!(buffer.is_modified());
+
+        // Only :e! reads it again.
+        state.open_file(&path, true).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let buffer = state.current_buffer().unwrap();
+        assert_eq!(buffer.text.to_string(), "on disk\n");
+        assert!(!buffer.is_modified());
+    }
+
+    #[test]
+    fn tabnew_on_an_open_file_keeps_its_changes() {
+        let path = temp_file("tabnew", "on disk\n");
+        let mut state = TermTextState::default();
+        state.add_client(1);
+        state.set_client(1);
+        state.open_file(&path, false).unwrap();
+        state.current_buffer_mut().unwrap().insert_at_cursor("unsaved ");
+
+        // As :tabnew file does.
+        let buffer = state.windows().current().buffer;
+        state.new_tab(buffer);
+        state.open_file(&path, false).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(state.view().tab, 1);
+        assert_eq!(state.current_buffer().unwrap().text.to_string(), "unsaved on disk\n");
+        assert_eq!(state.buffers.iter().count(), 1);
+    }
+
+    #[test]
+    fn connecting_with_an_open_file_attaches_to_its_buffer() {
+        let path = temp_file("connect", "on disk\n");
+        let mut state = TermTextState::default();
+        state.add_client(1);
+        state.set_client(1);
+        state.open_file(&path, false).unwrap();
+        state.current_buffer_mut().unwrap().insert_at_cursor("unsaved ");
+
+        // As a client connecting with the file as its argument does.
+        state.add_client(2);
+        state.set_client(2);
+        state.open_file(&path, false).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let buffer = state.current_buffer_mut().unwrap();
+        assert_eq!(buffer.text.to_string(), "unsaved on disk\n");
+        assert_eq!(buffer.undo(1), 1);
+        assert_eq!(buffer.text.to_string(), "on disk\n");
+    }
+
+    #[test]
+    fn read_only_clients_cannot_reload_open_files() {
+        let path = temp_file("readonly", "on disk\n");
+        let mut state = TermTextState::default();
+        state.add_client(1);
+        state.set_client(1);
+        state.open_file(&path, false).unwrap();
+        state.current_buffer_mut().unwrap().insert_at_cursor("unsaved ");
+
+        // :sp, :vs, :tabnew and :e on the open file, and connecting with it, just show it.
+        state.add_client(2);
+        state.set_client(2);
+        state.view_mut().readonly = true;
+        state.open_file(&path, false).unwrap();
+        assert_eq!(state.session.message, None);
+        assert_eq!(state.current_buffer().unwrap().text.to_string(), "unsaved on disk\n");
+
+        // :e! is refused.
+        assert!(ex::parse("e!", None).unwrap().edits());
+        state.open_file(&path, true).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(state.session.message, Some(ExError::ReadOnly.to_string()));
+        assert_eq!(state.current_buffer().unwrap().text.to_string(), "unsaved on disk\n");
+    }
+
+    #[test]
+    fn buffers_shown_by_another_client_are_not_deleted() {
+        let (mut state, id) = shared_buffer("shared\n");
+        let other = state.buffers.add(Buffer::new(None, "other\n"));
+        state.set_client(1);
+        assert!(!state.close_deletes_buffer());
+        assert!(state.close_current_buffer());
+        assert!(state.buffers.get(id).is_some());
+        assert_eq!(state.windows().current().buffer, Some(other));
+
+        state.set_client(2);
+        assert!(state.close_deletes_buffer());
+        assert!(state.close_current_buffer());
+        assert!(state.buffers.get(id).is_none());
+    }
+}
@@ -0,0 +1,293 @@
+use crate::buffer::{Buffer, Cursor};
+use tt::unicode;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Motion {
+    Left,
+    Right,
+    Up,
+    Down,
+    /// `gk` and `gj`, which move by screen rows when lines are wrapped and are
+    /// otherwise the same as `k` and `j`.
+    ScreenUp,
+    ScreenDown,
+    WordForward,
+    WordBackward,
+    WordEnd,
+    BigWordForward,
+    BigWordBackward,
+    BigWordEnd,
+    LineStart,
+    FirstNonBlank,
+    LineEnd,
+    ParagraphForward,
+    ParagraphBackward,
+    FileStart,
+    FileEnd,
+}
+
+impl Motion {
+    /// Vertical motions keep the remembered column instead of resetting it.
+    pub fn is_vertical(&self) -> bool {
+        matches!(self, Motion::Up | Motion::Down | Motion::ScreenUp | Motion::ScreenDown)
+    }
+
+    /// Operators act on whole lines when given a linewise motion.
+    pub fn is_linewise(&self) -> bool {
+        matches!(self, Motion::Up | Motion::Down | Motion::FileStart | Motion::FileEnd)
+    }
+
+    /// Inclusive motions include the character they land on in an operator's range.
+    pub fn is_inclusive(&self) -> bool {
+        matches!(self, Motion::WordEnd | Motion::BigWordEnd | Motion::LineEnd)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum CharClass {
+    Blank,
+    Word,
+    Punctuation,
+}
+
+fn char_class(ch: char, big: bool) -> CharClass {
+    if ch.is_whitespace() {
+        CharClass::Blank
+    } else if big || ch.is_alphanumeric() || ch == '_' {
+        CharClass::Word
+    } else {
+        CharClass::Punctuation
+    }
+}
+
+fn next_word_start(buffer: &Buffer, mut idx: usize, big: bool) -> usize {
+    let text = &buffer.text;
+    let len = text.len_chars();
+    if idx >= len {
+        return len;
+    }
+    let class = char_class(text.char(idx), big);
+    if class != CharClass::Blank {
+        while idx < len && char_class(text.char(idx), big) == class {
+            idx += 1;
+        }
+    }
+    while idx < len {
+        let ch = text.char(idx);
+        if ch == '\n' {
+            idx += 1;
+            // An empty line counts as a word of its own.
+            if idx < len && text.char(idx) == '\n' {
+                return idx;
+            }
+        } else if char_class(ch, big) == CharClass::Blank {
+            idx += 1;
+        } else {
+            break;
+        }
+    }
+    idx
+}
+
+fn prev_word_start(buffer: &Buffer, mut idx: usize, big: bool) -> usize {
+    let text = &buffer.text;
+    if idx == 0 {
+        return 0;
+    }
+    idx -= 1;
+    while idx > 0 && char_class(text.char(idx), big) == CharClass::Blank {
+        if text.char(idx) == '\n' && text.char(idx - 1) == '\n' {
+            return idx;
+        }
+        idx -= 1;
+    }
+    let class = char_class(text.char(idx), big);
+    while idx > 0 && char_class(text.char(idx - 1), big) == class {
+        idx -= 1;
+    }
+    idx
+}
+
+fn word_end(buffer: &Buffer, mut idx: usize, big: bool) -> usize {
+    let text = &buffer.text;
+    let len = text.len_chars();
+    idx += 1;
+    while idx < len && char_class(text.char(idx), big) == CharClass::Blank {
+        idx += 1;
+    }
+    if idx >= len {
+        return len.saturating_sub(1);
+    }
+    let class = char_class(text.char(idx), big);
+    while idx + 1 < len && char_class(text.char(idx + 1), big) == class {
+        idx += 1;
+    }
+    idx
+}
+
+pub fn first_non_blank(buffer: &Buffer, line: usize) -> usize {
+    buffer.line(line)
+        .chars()
+        .position(|ch| !ch.is_whitespace())
+        .unwrap_or_else(|| buffer.line_len(line))
+}
+
+fn is_blank_line(buffer: &Buffer, line: usize) -> bool {
+    buffer.line_len(line) == 0
+}
+
+fn paragraph_forward(buffer: &Buffer, mut line: usize) -> usize {
+    let last = buffer.line_count() - 1;
+    while line < last && is_blank_line(buffer, line) {
+        line += 1;
+    }
+    while line < last && !is_blank_line(buffer, line) {
+        line += 1;
+    }
+    line
+}
+
+fn paragraph_backward(buffer: &Buffer, mut line: usize) -> usize {
+    while line > 0 && is_blank_line(buffer, line) {
+        line -= 1;
+    }
+    while line > 0 && !is_blank_line(buffer, line) {
+        line -= 1;
+    }
+    line
+}
+
+/// Where `motion` repeated `count` times would take a cursor starting at `from`.
+/// `want_col` is the column vertical motions try to return to.
+pub fn target(buffer: &Buffer, from: Cursor, want_col: usize, motion: Motion, count: Option<usize>) -> Cursor {
+    let n = count.unwrap_or(1).max(1);
+    let last_line = buffer.line_count() - 1;
+
+    match motion {
+        Motion::Left => {
+            let text = buffer.line(from.line);
+            Cursor::new(from.line, (0..n).fold(from.col, |col, _| unicode::prev_grapheme(&text, col)))
+        },
+        Motion::Right => {
+            let text = buffer.line(from.line);
+            Cursor::new(from.line, (0..n).fold(from.col, |col, _| unicode::next_grapheme(&text, col)))
+        },
+        Motion::Up | Motion::ScreenUp => {
+            let line = from.line.saturating_sub(n);
+            Cursor::new(line, unicode::grapheme_start(&buffer.line(line), want_col))
+        },
+        Motion::Down | Motion::ScreenDown => {
+            let line = (from.line + n).min(last_line);
+            Cursor::new(line, unicode::grapheme_start(&buffer.line(line), want_col))
+        },
+        Motion::WordForward | Motion::BigWordForward => {
+            let big = motion == Motion::BigWordForward;
+            let mut idx = buffer.char_idx(from);
+            for _ in 0..n {
+                idx = next_word_start(buffer, idx, big);
+            }
+            buffer.cursor_at(idx)
+        },
+        Motion::WordBackward | Motion::BigWordBackward => {
+            let big = motion == Motion::BigWordBackward;
+            let mut idx = buffer.char_idx(from);
+            for _ in 0..n {
+                idx = prev_word_start(buffer, idx, big);
+            }
+            buffer.cursor_at(idx)
+        },
+        Motion::WordEnd | Motion::BigWordEnd => {
+            let big = motion == Motion::BigWordEnd;
+            let mut idx = buffer.char_idx(from);
+            for _ in 0..n {
+                idx = word_end(buffer, idx, big);
+            }
+            buffer.cursor_at(idx)
+        },
+        Motion::LineStart => Cursor::new(from.line, 0),
+        Motion::FirstNonBlank => Cursor::new(from.line, first_non_blank(buffer, from.line)),
+        Motion::LineEnd => {
+            let line = (from.line + n - 1).min(last_line);
+            Cursor::new(line, buffer.line_len(line))
+        },
+        Motion::ParagraphForward => {
+            let mut line = from.line;
+            for _ in 0..n {
+                line = paragraph_forward(buffer, line);
+            }
+            let col = if line == last_line && !is_blank_line(buffer, line) { buffer.line_len(line) } else { 0 };
+            Cursor::new(line, col)
+        },
+        Motion::ParagraphBackward => {
+            let mut line = from.line;
+            for _ in 0..n {
+                line = paragraph_backward(buffer, line);
+            }
+            Cursor::new(line, 0)
+        },
+        Motion::FileStart | Motion::FileEnd => {
+            let line = match count {
+                Some(count) => count.saturating_sub(1).min(last_line),
+                None if motion == Motion::FileStart => 0,
+                None => last_line,
+            };
+            Cursor::new(line, first_non_blank(buffer, line))
+        },
+    }
+}
+
+/// Moves the buffer's cursor and updates its remembered column.
+pub fn move_cursor(buffer: &mut Buffer, motion: Motion, count: Option<usize>) {
+    let pos = target(buffer, buffer.pos, buffer.want_col, motion, count);
+    buffer.pos = pos;
+    if motion == Motion::LineEnd {
+        buffer.want_col = usize::MAX;
+    } else if !motion.is_vertical() {
+        buffer.want_col = pos.col;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn buffer(data: &str) -> Buffer {
+        Buffer::new(None, data)
+    }
+
+    #[test]
+    fn word_motions() {
+        let buffer = buffer("foo.bar  baz\n\nqux");
+        let at = |col| Cursor::new(0, col);
+
+        assert_eq!(target(&buffer, at(0), 0, Motion::WordForward, None), at(3));
+        assert_eq!(target(&buffer, at(0), 0, Motion::BigWordForward, None), at(9));
+        assert_eq!(target(&buffer, at(9), 0, Motion::WordForward, None), Cursor::new(1, 0));
+        assert_eq!(target(&buffer, at(0), 0, Motion::WordEnd, Some(2)), at(3));
+        assert_eq!(target(&buffer, Cursor::new(2, 1), 0, Motion::WordBackward, None), Cursor::new(2, 0));
+        assert_eq!(target(&buffer, Cursor::new(2, 0), 0, Motion::WordBackward, None), Cursor::new(1, 0));
+    }
+
+    #[test]
+    fn vertical_motions_keep_column() {
+        let mut buffer = buffer("long line\nab\nanother line");
+        buffer.pos = Cursor::new(0, 6);
+        buffer.want_col = 6;
+        move_cursor(&mut buffer, Motion::Down, None);
+        assert_eq!(buffer.pos, Cursor::new(1, 2));
+        move_cursor(&mut buffer, Motion::Down, None);
+        assert_eq!(buffer.pos, Cursor::new(2, 6));
+        move_cursor(&mut buffer, Motion::FileStart, None);
+        assert_eq!(buffer.pos, Cursor::new(0, 0));
+        move_cursor(&mut buffer, Motion::FileEnd, Some(2));
+        assert_eq!(buffer.pos, Cursor::new(1, 0));
+    }
+
+    #[test]
+    fn paragraph_motions() {
+        let buffer = buffer("a\nb\n\nc\nd");
+        assert_eq!(target(&buffer, Cursor::new(0, 0), 0, Motion::ParagraphForward, None), Cursor::new(2, 0));
+        assert_eq!(target(&buffer, Cursor::new(2, 0), 0, Motion::ParagraphForward, None), Cursor::new(4, 1));
+        assert_eq!(target(&buffer, Cursor::new(4, 0), 0, Motion::ParagraphBackward, None), Cursor::new(2, 0));
+    }
+}
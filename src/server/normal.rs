@@ -0,0 +1,439 @@
+use tt::message::Key;
+use crate::BufferMode;
+use crate::motion::Motion;
+use crate::operator::{Operator, Target};
+use crate::registers;
+use crate::scroll::Scroll;
+use crate::textobject::TextObject;
+use crate::window::{Direction, Side, WindowCommand};
+
+/// A fully parsed Normal mode command, e.g. `3w` or `gg`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct NormalCommand {
+    pub count: Option<usize>,
+    /// The register named with a `"x` prefix.
+    pub register: Option<char>,
+    pub action: Action,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Action {
+    Move(Motion),
+    Insert,
+    EnterCommand,
+    Undo,
+    Redo,
+    Operate(Operator, Target),
+    Put { after: bool },
+    /// Starts, switches or (when already in that mode) leaves a Visual mode.
+    Visual(BufferMode),
+    SwapAnchor,
+    SelectObject(TextObject),
+    OperateSelection(Operator),
+    /// `I` or `A` on a selection.
+    InsertSelection { append: bool },
+    /// Opens the `/` or `?` search prompt.
+    Search { forward: bool },
+    /// `n`, or `N` when reversed.
+    SearchNext { reverse: bool },
+    /// `*` or `#`.
+    SearchWord { forward: bool },
+    /// Ctrl-^: the alternate buffer, or with a count, that buffer.
+    AlternateBuffer,
+    /// A `Ctrl-w` command.
+    Window(WindowCommand),
+    /// `gt`, or `gT` when reversed.
+    SwitchTab { reverse: bool },
+    Scroll(Scroll),
+}
+
+impl Action {
+    /// Whether the action changes the buffer, or starts Insert mode to.
+    pub fn edits(&self) -> bool {
+        match self {
+            Action::Insert | Action::Undo | Action::Redo | Action::Put { .. } | Action::InsertSelection { .. } => true,
+            Action::Operate(operator, _) | Action::OperateSelection(operator) => *operator != Operator::Yank,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Parse {
+    Complete(NormalCommand),
+    Pending,
+    /// An operator has been typed and is waiting for its motion or text object.
+    OperatorPending,
+    Invalid,
+}
+
+fn parse_count(keys: &[Key], i: &mut usize) -> Option<usize> {
+    let mut count: Option<usize> = None;
+    while let Some(Key::Char(ch)) = keys.get(*i) {
+        match ch.to_digit(10) {
+            Some(0) if count.is_none() => break,
+            Some(digit) => {
+                count = Some(count.unwrap_or(0).saturating_mul(10).saturating_add(digit as usize));
+                *i += 1;
+            },
+            None => break,
+        }
+    }
+    count
+}
+
+fn parse_register(keys: &[Key], i: &mut usize) -> Result<Option<char>, Parse> {
+    if keys.get(*i) != Some(&Key::Char('"')) {
+        return Ok(None);
+    }
+    match keys.get(*i + 1) {
+        None => Err(Parse::Pending),
+        Some(Key::Char(name)) if registers::is_register(*name) => {
+            *i += 2;
+            Ok(Some(*name))
+        },
+        Some(_) => Err(Parse::Invalid),
+    }
+}
+
+/// Parses the `[count]["x][count]` prefix shared by Normal and Visual mode commands.
+fn parse_prefix(keys: &[Key], i: &mut usize) -> Result<(Option<usize>, Option<char>), Parse> {
+    let count = parse_count(keys, i);
+    let register = parse_register(keys, i)?;
+    let count = if register.is_some() { multiply(count, parse_count(keys, i)) } else { count };
+    Ok((count, register))
+}
+
+/// Parses a motion starting at `keys[*i]`, or reports why it could not.
+pub fn parse_motion(keys: &[Key], i: &mut usize) -> Result<Motion, Parse> {
+    let key = *keys.get(*i).ok_or(Parse::Pending)?;
+    *i += 1;
+    let motion = match key {
+        Key::Char('h') | Key::Left | Key::Backspace => Motion::Left,
+        Key::Char('l') | Key::Right | Key::Char(' ') => Motion::Right,
+        Key::Char('k') | Key::Up => Motion::Up,
+        Key::Char('j') | Key::Down => Motion::Down,
+        Key::Char('w') => Motion::WordForward,
+        Key::Char('b') => Motion::WordBackward,
+        Key::Char('e') => Motion::WordEnd,
+        Key::Char('W') => Motion::BigWordForward,
+        Key::Char('B') => Motion::BigWordBackward,
+        Key::Char('E') => Motion::BigWordEnd,
+        Key::Char('0') | Key::Home => Motion::LineStart,
+        Key::Char('^') => Motion::FirstNonBlank,
+        Key::Char('$') | Key::End => Motion::LineEnd,
+        Key::Char('}') => Motion::ParagraphForward,
+        Key::Char('{') => Motion::ParagraphBackward,
+        Key::Char('G') => Motion::FileEnd,
+        Key::Char('g') => {
+            let key = *keys.get(*i).ok_or(Parse::Pending)?;
+            *i += 1;
+            match key {
+                Key::Char('g') => Motion::FileStart,
+                Key::Char('j') | Key::Down => Motion::ScreenDown,
+                Key::Char('k') | Key::Up => Motion::ScreenUp,
+                _ => return Err(Parse::Invalid),
+            }
+        },
+        _ => return Err(Parse::Invalid),
+    };
+    Ok(motion)
+}
+
+/// Parses an operator starting at `keys[*i]`, returning it with the keys that name it.
+fn parse_operator(keys: &[Key], i: &mut usize) -> Result<Option<(Operator, &'static str)>, Parse> {
+    let operator = match keys.get(*i) {
+        Some(Key::Char('d')) => (Operator::Delete, "d"),
+        Some(Key::Char('c')) => (Operator::Change, "c"),
+        Some(Key::Char('y')) => (Operator::Yank, "y"),
+        Some(Key::Char('>')) => (Operator::Indent, ">"),
+        Some(Key::Char('<')) => (Operator::Outdent, "<"),
+        Some(Key::Char('g')) => match keys.get(*i + 1) {
+            None => return Err(Parse::Pending),
+            Some(Key::Char('u')) => (Operator::Lowercase, "gu"),
+            Some(Key::Char('U')) => (Operator::Uppercase, "gU"),
+            Some(_) => return Ok(None),
+        },
+        _ => return Ok(None),
+    };
+    *i += operator.1.len();
+    Ok(Some(operator))
+}
+
+fn matches_keys(keys: &[Key], at: usize, name: &str) -> bool {
+    name.chars().enumerate().all(|(j, ch)| keys.get(at + j) == Some(&Key::Char(ch)))
+}
+
+/// Parses what follows an operator: a repeat of the operator for whole lines, a text object or a motion.
+fn parse_target(keys: &[Key], i: &mut usize, name: &str) -> Result<Target, Parse> {
+    let pending = |parse: Parse| if parse == Parse::Pending { Parse::OperatorPending } else { parse };
+    let key = *keys.get(*i).ok_or(Parse::OperatorPending)?;
+
+    // `dd`, `>>`, `gUU` and `gUgU` all act on lines.
+    if matches_keys(keys, *i, name) {
+        *i += name.len();
+        return Ok(Target::Lines);
+    }
+    if name.len() == 2 {
+        if matches_keys(keys, *i, &name[1..]) {
+            *i += 1;
+            return Ok(Target::Lines);
+        }
+        if matches_keys(keys, *i, &name[..1]) && *i + 1 == keys.len() {
+            return Err(Parse::OperatorPending);
+        }
+    }
+
+    match key {
+        Key::Char(scope @ ('i' | 'a')) => {
+            let object = match keys.get(*i + 1) {
+                None => return Err(Parse::OperatorPending),
+                Some(Key::Char(ch)) => TextObject::from_char(scope == 'i', *ch),
+                Some(_) => None,
+            };
+            *i += 2;
+            object.map(Target::Object).ok_or(Parse::Invalid)
+        },
+        _ => parse_motion(keys, i).map(Target::Motion).map_err(pending),
+    }
+}
+
+/// The window command for the key typed after `Ctrl-w`.
+fn parse_window_command(key: Key) -> Option<WindowCommand> {
+    let command = match key {
+        Key::Char('s' | 'S') | Key::Ctrl('s') => WindowCommand::Split(Direction::Down),
+        Key::Char('v') | Key::Ctrl('v') => WindowCommand::Split(Direction::Right),
+        Key::Char('c') => WindowCommand::Close,
+        Key::Char('q') | Key::Ctrl('q') => WindowCommand::Quit,
+        Key::Char('o') | Key::Ctrl('o') => WindowCommand::Only,
+        Key::Char('h') | Key::Ctrl('h') | Key::Left | Key::Backspace => WindowCommand::Focus(Side::Left),
+        Key::Char('j') | Key::Ctrl('j') | Key::Down => WindowCommand::Focus(Side::Down),
+        Key::Char('k') | Key::Ctrl('k') | Key::Up => WindowCommand::Focus(Side::Up),
+        Key::Char('l') | Key::Ctrl('l') | Key::Right => WindowCommand::Focus(Side::Right),
+        Key::Char('w') | Key::Ctrl('w') => WindowCommand::Next,
+        Key::Char('W') => WindowCommand::Previous,
+        Key::Char('+') => WindowCommand::Resize(Direction::Down, 1),
+        Key::Char('-') => WindowCommand::Resize(Direction::Down, -1),
+        Key::Char('>') => WindowCommand::Resize(Direction::Right, 1),
+        Key::Char('<') => WindowCommand::Resize(Direction::Right, -1),
+        Key::Char('_') | Key::Ctrl('_') => WindowCommand::SetSize(Direction::Down),
+        Key::Char('|') => WindowCommand::SetSize(Direction::Right),
+        Key::Char('=') => WindowCommand::Equalize,
+        _ => return None,
+    };
+    Some(command)
+}
+
+fn multiply(a: Option<usize>, b: Option<usize>) -> Option<usize> {
+    match (a, b) {
+        (None, None) => None,
+        (a, b) => Some(a.unwrap_or(1).saturating_mul(b.unwrap_or(1))),
+    }
+}
+
+pub fn parse(keys: &[Key]) -> Parse {
+    let mut i = 0;
+    let (count, register) = match parse_prefix(keys, &mut i) {
+        Ok(prefix) => prefix,
+        Err(parse) => return parse,
+    };
+
+    match parse_operator(keys, &mut i) {
+        Err(parse) => return parse,
+        Ok(Some((operator, name))) => {
+            let target_count = parse_count(keys, &mut i);
+            return match parse_target(keys, &mut i, name) {
+                Ok(target) => Parse::Complete(NormalCommand {
+                    count: multiply(count, target_count),
+                    register,
+                    action: Action::Operate(operator, target),
+                }),
+                Err(parse) => parse,
+            };
+        },
+        Ok(None) => (),
+    }
+
+    let shorthand = |operator, target| Action::Operate(operator, target);
+    let action = match keys.get(i) {
+        None => return Parse::Pending,
+        Some(Key::Char('x')) => shorthand(Operator::Delete, Target::Motion(Motion::Right)),
+        Some(Key::Char('X')) => shorthand(Operator::Delete, Target::Motion(Motion::Left)),
+        Some(Key::Char('D')) => shorthand(Operator::Delete, Target::Motion(Motion::LineEnd)),
+        Some(Key::Char('C')) => shorthand(Operator::Change, Target::Motion(Motion::LineEnd)),
+        Some(Key::Char('s')) => shorthand(Operator::Change, Target::Motion(Motion::Right)),
+        Some(Key::Char('S')) => shorthand(Operator::Change, Target::Lines),
+        Some(Key::Char('Y')) => shorthand(Operator::Yank, Target::Lines),
+        Some(Key::Char('p')) => Action::Put { after: true },
+        Some(Key::Char('P')) => Action::Put { after: false },
+        Some(Key::Char('v')) => Action::Visual(BufferMode::Visual),
+        Some(Key::Char('V')) => Action::Visual(BufferMode::VisualLine),
+        Some(Key::Ctrl('v')) => Action::Visual(BufferMode::VisualBlock),
+        Some(Key::Char('i')) => Action::Insert,
+        Some(Key::Char(':')) => Action::EnterCommand,
+        Some(Key::Char('/')) => Action::Search { forward: true },
+        Some(Key::Char('?')) => Action::Search { forward: false },
+        Some(Key::Char('n')) => Action::SearchNext { reverse: false },
+        Some(Key::Char('N')) => Action::SearchNext { reverse: true },
+        Some(Key::Char('*')) => Action::SearchWord { forward: true },
+        Some(Key::Char('#')) => Action::SearchWord { forward: false },
+        Some(Key::Char('u')) => Action::Undo,
+        Some(Key::Ctrl('r')) => Action::Redo,
+        Some(Key::Ctrl('^' | '6')) => Action::AlternateBuffer,
+        Some(Key::Ctrl('e')) => Action::Scroll(Scroll::LinesDown),
+        Some(Key::Ctrl('y')) => Action::Scroll(Scroll::LinesUp),
+        Some(Key::Ctrl('d')) => Action::Scroll(Scroll::HalfPageDown),
+        Some(Key::Ctrl('u')) => Action::Scroll(Scroll::HalfPageUp),
+        Some(Key::Ctrl('f')) | Some(Key::PageDown) => Action::Scroll(Scroll::PageDown),
+        Some(Key::Ctrl('b')) | Some(Key::PageUp) => Action::Scroll(Scroll::PageUp),
+        Some(Key::Char('z')) => match keys.get(i + 1) {
+            None => return Parse::Pending,
+            Some(Key::Char('t')) => Action::Scroll(Scroll::CursorToTop),
+            Some(Key::Char('z')) => Action::Scroll(Scroll::CursorToMiddle),
+            Some(Key::Char('b')) => Action::Scroll(Scroll::CursorToBottom),
+            Some(_) => return Parse::Invalid,
+        },
+        Some(Key::Char('g')) if matches!(keys.get(i + 1), Some(Key::Char('t' | 'T'))) => {
+            Action::SwitchTab { reverse: keys.get(i + 1) == Some(&Key::Char('T')) }
+        },
+        Some(Key::Ctrl('w')) => match keys.get(i + 1).copied().map(parse_window_command) {
+            None => return Parse::Pending,
+            Some(Some(command)) => Action::Window(command),
+            Some(None) => return Parse::Invalid,
+        },
+        Some(_) => match parse_motion(keys, &mut i) {
+            Ok(motion) => Action::Move(motion),
+            Err(parse) => return parse,
+        },
+    };
+    Parse::Complete(NormalCommand { count, register, action })
+}
+
+/// Parses a command typed while a Visual mode selection is active.
+pub fn parse_visual(keys: &[Key]) -> Parse {
+    let mut i = 0;
+    let (count, register) = match parse_prefix(keys, &mut i) {
+        Ok(prefix) => prefix,
+        Err(parse) => return parse,
+    };
+
+    let action = match keys.get(i) {
+        None => return Parse::Pending,
+        Some(Key::Char('v')) => Action::Visual(BufferMode::Visual),
+        Some(Key::Char('V')) => Action::Visual(BufferMode::VisualLine),
+        Some(Key::Ctrl('v')) => Action::Visual(BufferMode::VisualBlock),
+        Some(Key::Char('o')) => Action::SwapAnchor,
+        Some(Key::Char(':')) => Action::EnterCommand,
+        Some(Key::Char('d' | 'x') | Key::Delete) => Action::OperateSelection(Operator::Delete),
+        Some(Key::Char('c' | 's')) => Action::OperateSelection(Operator::Change),
+        Some(Key::Char('y')) => Action::OperateSelection(Operator::Yank),
+        Some(Key::Char('>')) => Action::OperateSelection(Operator::Indent),
+        Some(Key::Char('<')) => Action::OperateSelection(Operator::Outdent),
+        Some(Key::Char('u')) => Action::OperateSelection(Operator::Lowercase),
+        Some(Key::Char('U')) => Action::OperateSelection(Operator::Uppercase),
+        Some(Key::Char('I')) => Action::InsertSelection { append: false },
+        Some(Key::Char('A')) => Action::InsertSelection { append: true },
+        Some(Key::Char(scope @ ('i' | 'a'))) => match keys.get(i + 1) {
+            None => return Parse::Pending,
+            Some(Key::Char(ch)) => match TextObject::from_char(*scope == 'i', *ch) {
+                Some(object) => Action::SelectObject(object),
+                None => return Parse::Invalid,
+            },
+            Some(_) => return Parse::Invalid,
+        },
+        Some(_) => match parse_motion(keys, &mut i) {
+            Ok(motion) => Action::Move(motion),
+            Err(parse) => return parse,
+        },
+    };
+    Parse::Complete(NormalCommand { count, register, action })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn keys(s: &str) -> Vec<Key> {
+        s.chars().map(Key::Char).collect()
+    }
+
+    fn complete(count: Option<usize>, action: Action) -> Parse {
+        Parse::Complete(NormalCommand { count, register: None, action })
+    }
+
+    #[test]
+    fn parses_counts_and_motions() {
+        assert_eq!(parse(&keys("12j")), complete(Some(12), Action::Move(Motion::Down)));
+        assert_eq!(parse(&keys("0")), complete(None, Action::Move(Motion::LineStart)));
+        assert_eq!(parse(&keys("10G")), complete(Some(10), Action::Move(Motion::FileEnd)));
+        assert_eq!(parse(&keys("g")), Parse::Pending);
+        assert_eq!(parse(&keys("3")), Parse::Pending);
+        assert_eq!(parse(&keys("gx")), Parse::Invalid);
+        assert_eq!(parse(&keys("3N")), complete(Some(3), Action::SearchNext { reverse: true }));
+        assert_eq!(parse(&keys("gj")), complete(None, Action::Move(Motion::ScreenDown)));
+    }
+
+    #[test]
+    fn tells_edits_from_navigation() {
+        let edits = |keys: &str| match parse(&self::keys(keys)) {
+            Parse::Complete(command) => command.action.edits(),
+            parse => panic!("{keys} parsed as {parse:?}"),
+        };
+        assert!(edits("dw") && edits("x") && edits("p") && edits("i") && edits("u"));
+        assert!(!edits("yy") && !edits("3j") && !edits("v") && !edits("n"));
+        assert!(Action::OperateSelection(Operator::Uppercase).edits());
+        assert!(!Action::OperateSelection(Operator::Yank).edits());
+    }
+
+    #[test]
+    fn parses_window_commands() {
+        let window = |count, command| complete(count, Action::Window(command));
+
+        assert_eq!(parse(&[Key::Ctrl('w')]), Parse::Pending);
+        assert_eq!(parse(&[Key::Ctrl('w'), Key::Char('v')]), window(None, WindowCommand::Split(Direction::Right)));
+        assert_eq!(parse(&[Key::Char('5'), Key::Ctrl('w'), Key::Char('-')]), window(Some(5), WindowCommand::Resize(Direction::Down, -1)));
+        assert_eq!(parse(&[Key::Ctrl('w'), Key::Ctrl('l')]), window(None, WindowCommand::Focus(Side::Right)));
+        assert_eq!(parse(&[Key::Ctrl('w'), Key::Char('x')]), Parse::Invalid);
+        assert_eq!(parse(&keys("2gt")), complete(Some(2), Action::SwitchTab { reverse: false }));
+        assert_eq!(parse(&keys("gT")), complete(None, Action::SwitchTab { reverse: true }));
+    }
+
+    #[test]
+    fn parses_scrolling() {
+        assert_eq!(parse(&[Key::Char('4'), Key::Ctrl('e')]), complete(Some(4), Action::Scroll(Scroll::LinesDown)));
+        assert_eq!(parse(&keys("z")), Parse::Pending);
+        assert_eq!(parse(&keys("zz")), complete(None, Action::Scroll(Scroll::CursorToMiddle)));
+        assert_eq!(parse(&keys("zx")), Parse::Invalid);
+    }
+
+    #[test]
+    fn parses_operators() {
+        let operate = |count, operator, target| complete(count, Action::Operate(operator, target));
+
+        assert_eq!(parse(&keys("d")), Parse::OperatorPending);
+        assert_eq!(parse(&keys("2d3w")), operate(Some(6), Operator::Delete, Target::Motion(Motion::WordForward)));
+        assert_eq!(parse(&keys("dd")), operate(None, Operator::Delete, Target::Lines));
+        assert_eq!(parse(&keys("3>>")), operate(Some(3), Operator::Indent, Target::Lines));
+        assert_eq!(parse(&keys("gUU")), operate(None, Operator::Uppercase, Target::Lines));
+        assert_eq!(parse(&keys("gugu")), operate(None, Operator::Lowercase, Target::Lines));
+        assert_eq!(parse(&keys("gug")), Parse::OperatorPending);
+        assert_eq!(parse(&keys("gugg")), operate(None, Operator::Lowercase, Target::Motion(Motion::FileStart)));
+        assert_eq!(parse(&keys("ci")), Parse::OperatorPending);
+        assert_eq!(parse(&keys("ci\"")), operate(None, Operator::Change, Target::Object(TextObject::from_char(true, '"').unwrap())));
+        assert_eq!(parse(&keys("diz")), Parse::Invalid);
+        assert_eq!(parse(&keys("yq")), Parse::Invalid);
+        assert_eq!(parse(&keys("gg")), complete(None, Action::Move(Motion::FileStart)));
+    }
+
+    #[test]
+    fn parses_registers() {
+        let command = |count, register, action| Parse::Complete(NormalCommand { count, register, action });
+
+        assert_eq!(parse(&keys("\"")), Parse::Pending);
+        assert_eq!(parse(&keys("\"a")), Parse::Pending);
+        assert_eq!(parse(&keys("\"!")), Parse::Invalid);
+        assert_eq!(parse(&keys("2\"A3yy")), command(Some(6), Some('A'), Action::Operate(Operator::Yank, Target::Lines)));
+        assert_eq!(parse(&keys("\"+p")), command(None, Some('+'), Action::Put { after: true }));
+        assert_eq!(parse_visual(&keys("\"_d")), command(None, Some('_'), Action::OperateSelection(Operator::Delete)));
+    }
+}
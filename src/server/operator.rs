@@ -0,0 +1,394 @@
+use crate::buffer::{Buffer, Cursor};
+use crate::motion::{self, Motion};
+use crate::options::Indent;
+use crate::render;
+use crate::textobject::{self, Span, TextObject};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Operator {
+    Delete,
+    Change,
+    Yank,
+    Indent,
+    Outdent,
+    Lowercase,
+    Uppercase,
+}
+
+/// What an operator acts on: a motion, a text object, or `count` whole lines (`dd`, `>>`).
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Target {
+    Motion(Motion),
+    Object(TextObject),
+    Lines,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum YankKind {
+    #[default]
+    Chars,
+    Lines,
+    /// One line of text per row of a Visual-Block selection.
+    Block,
+}
+
+/// Text removed or copied by an operator, ready to be put back.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Yank {
+    pub text: String,
+    pub kind: YankKind,
+}
+
+/// A Visual-Block selection: lines `first..=last`, character columns `left..=right`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Block {
+    pub first: usize,
+    pub last: usize,
+    pub left: usize,
+    pub right: usize,
+}
+
+/// Works out the span an operator covers when applied to `target` from the cursor.
+pub fn span(buffer: &Buffer, operator: Operator, target: Target, count: Option<usize>) -> Option<Span> {
+    let from = buffer.pos;
+    let motion = match target {
+        Target::Object(object) => return textobject::select(buffer, from, object),
+        Target::Lines => {
+            let last = buffer.line_count() - 1;
+            let end = (from.line + count.unwrap_or(1).max(1) - 1).min(last);
+            return Some(Span {
+                start: Cursor::new(from.line, 0),
+                end: Cursor::new(end, 0),
+                linewise: true,
+            });
+        },
+        Target::Motion(motion) => motion,
+    };
+
+    // `cw` on a word changes to the end of the word, like `ce`.
+    let on_word = buffer.char_at(from).map(|ch| !ch.is_whitespace()).unwrap_or(false);
+    let motion = match motion {
+        Motion::WordForward if operator == Operator::Change && on_word => Motion::WordEnd,
+        Motion::BigWordForward if operator == Operator::Change && on_word => Motion::BigWordEnd,
+        motion => motion,
+    };
+
+    let to = motion::target(buffer, from, buffer.want_col, motion, count);
+    let (start, mut end) = if to < from { (to, from) } else { (from, to) };
+
+    if motion.is_linewise() {
+        return Some(Span { start, end, linewise: true });
+    }
+    if motion.is_inclusive() {
+        end.col += 1;
+    } else if end.line > start.line && end.col == 0 {
+        // An exclusive motion that ends at the start of a later line stops at the end of the line before.
+        end = Cursor::new(end.line - 1, buffer.line_len(end.line - 1));
+    }
+    if start == buffer.clamp(end) {
+        return None;
+    }
+    Some(Span { start, end, linewise: false })
+}
+
+/// Character range covering whole lines `first..=last`, including their line endings.
+fn line_range(buffer: &Buffer, first: usize, last: usize) -> (usize, usize) {
+    let text = &buffer.text;
+    let start = text.line_to_char(first);
+    let end = if last + 1 < buffer.line_count() {
+        text.line_to_char(last + 1)
+    } else {
+        text.len_chars()
+    };
+    (start, end)
+}
+
+fn span_text(buffer: &Buffer, span: Span) -> String {
+    if span.linewise {
+        let (start, end) = line_range(buffer, span.start.line, span.end.line);
+        let mut text = buffer.text.slice(start..end).to_string();
+        if !text.ends_with('\n') {
+            text.push('\n');
+        }
+        text
+    } else {
+        let start = buffer.char_idx(span.start);
+        let end = buffer.char_idx(span.end);
+        buffer.text.slice(start..end).to_string()
+    }
+}
+
+fn delete_lines(buffer: &mut Buffer, first: usize, last: usize) {
+    let (mut start, end) = line_range(buffer, first, last);
+    if end == buffer.text.len_chars() && first > 0 {
+        // Deleting the final lines also takes the line break before them.
+        start = buffer.char_idx(Cursor::new(first - 1, buffer.line_len(first - 1)));
+    }
+    let (start, end) = (buffer.cursor_at(start), buffer.cursor_at(end));
+    buffer.remove(start, end);
+    let line = first.min(buffer.line_count() - 1);
+    buffer.pos = Cursor::new(line, motion::first_non_blank(buffer, line));
+}
+
+fn map_lines(buffer: &mut Buffer, first: usize, last: usize, f: impl Fn(&str) -> String) {
+    for line in first..=last {
+        let old = buffer.line(line);
+        let new = f(&old);
+        if new != old {
+            let len = buffer.line_len(line);
+            buffer.remove(Cursor::new(line, 0), Cursor::new(line, len));
+            buffer.insert(Cursor::new(line, 0), &new);
+        }
+    }
+}
+
+/// Shifts a line's indentation right or left by `shiftwidth` columns,
+/// rebuilding it with tabs unless `expandtab` is set. Empty lines aren't indented.
+fn shift(line: &str, indent: &Indent, right: bool) -> String {
+    if right && line.is_empty() {
+        return String::new();
+    }
+    let text = line.trim_start_matches([' ', '\t']);
+    let blank = line.len() - text.len();
+    let width = render::screen_col(line, blank, indent.tabstop);
+    let width = if right { width + indent.shift() } else { width.saturating_sub(indent.shift()) };
+    format!("{}{text}", indent.fill(0, width))
+}
+
+/// Applies `operator` to `span`, returning the text it deleted or yanked.
+///
+/// Each application is one undo group. `Change` leaves its group open so that
+/// the Insert session that follows is undone together with the deletion.
+pub fn apply(buffer: &mut Buffer, operator: Operator, span: Span) -> Option<Yank> {
+    buffer.history.begin_group(buffer.pos);
+    let yank = match operator {
+        Operator::Delete | Operator::Change | Operator::Yank => Some(Yank {
+            text: span_text(buffer, span),
+            kind: if span.linewise { YankKind::Lines } else { YankKind::Chars },
+        }),
+        _ => None,
+    };
+
+    let (first, last) = (span.start.line, span.end.line);
+    match operator {
+        Operator::Delete if span.linewise => delete_lines(buffer, first, last),
+        Operator::Change if span.linewise => {
+            let start = Cursor::new(first, 0);
+            let end = Cursor::new(last, buffer.line_len(last));
+            buffer.remove(start, end);
+            buffer.pos = start;
+        },
+        Operator::Delete | Operator::Change => {
+            buffer.remove(span.start, span.end);
+            buffer.pos = span.start;
+        },
+        Operator::Yank => {
+            buffer.pos = if span.linewise { Cursor::new(first, buffer.pos.col) } else { span.start };
+        },
+        Operator::Indent | Operator::Outdent => {
+            let indent = buffer.indent;
+            map_lines(buffer, first, last, |line| shift(line, &indent, operator == Operator::Indent));
+            buffer.pos = Cursor::new(first, motion::first_non_blank(buffer, first));
+        },
+        Operator::Lowercase | Operator::Uppercase => {
+            let convert = |text: &str| if operator == Operator::Lowercase {
+                text.to_lowercase()
+            } else {
+                text.to_uppercase()
+            };
+            if span.linewise {
+                map_lines(buffer, first, last, convert);
+                buffer.pos = Cursor::new(first, buffer.pos.col);
+            } else {
+                let text = convert(&span_text(buffer, span));
+                buffer.remove(span.start, span.end);
+                buffer.insert(span.start, &text);
+                buffer.pos = span.start;
+            }
+        },
+    }
+
+    if operator != Operator::Change {
+        buffer.history.end_group();
+        buffer.pos = buffer.clamp_normal(buffer.pos);
+    }
+    buffer.want_col = buffer.pos.col;
+    yank
+}
+
+/// Applies `operator` to each row of a block, returning the text it deleted or yanked.
+/// Like `apply`, `Change` leaves its undo group open for the Insert session.
+pub fn apply_block(buffer: &mut Buffer, operator: Operator, block: Block) -> Option<Yank> {
+    buffer.history.begin_group(buffer.pos);
+    let last = block.last.min(buffer.line_count() - 1);
+    let row = |buffer: &Buffer, line: usize| {
+        let len = buffer.line_len(line);
+        (Cursor::new(line, block.left.min(len)), Cursor::new(line, (block.right + 1).min(len)))
+    };
+
+    let yank = match operator {
+        Operator::Delete | Operator::Change | Operator::Yank => {
+            let rows: Vec<String> = (block.first..=last).map(|line| {
+                let (start, end) = row(buffer, line);
+                let (start, end) = (buffer.char_idx(start), buffer.char_idx(end));
+                buffer.text.slice(start..end).to_string()
+            }).collect();
+            Some(Yank { text: rows.join("\n"), kind: YankKind::Block })
+        },
+        _ => None,
+    };
+
+    match operator {
+        Operator::Delete | Operator::Change | Operator::Lowercase | Operator::Uppercase => {
+            for line in block.first..=last {
+                let (start, end) = row(buffer, line);
+                let text = buffer.remove(start, end);
+                match operator {
+                    Operator::Lowercase => { buffer.insert(start, &text.to_lowercase()); },
+                    Operator::Uppercase => { buffer.insert(start, &text.to_uppercase()); },
+                    _ => (),
+                }
+            }
+        },
+        Operator::Indent | Operator::Outdent => {
+            let indent = buffer.indent;
+            map_lines(buffer, block.first, last, |line| shift(line, &indent, operator == Operator::Indent));
+        },
+        Operator::Yank => (),
+    }
+
+    buffer.pos = Cursor::new(block.first, block.left);
+    if operator != Operator::Change {
+        buffer.history.end_group();
+        buffer.pos = buffer.clamp_normal(buffer.pos);
+    }
+    buffer.want_col = buffer.pos.col;
+    yank
+}
+
+/// Puts `yank` after (`p`) or before (`P`) the cursor `count` times.
+pub fn put(buffer: &mut Buffer, yank: &Yank, after: bool, count: usize) {
+    if yank.text.is_empty() {
+        return;
+    }
+    let count = count.max(1);
+    let text = yank.text.repeat(count);
+    buffer.history.begin_group(buffer.pos);
+    if yank.kind == YankKind::Block {
+        let start = buffer.pos;
+        let col = if after && buffer.line_len(start.line) > 0 { start.col + 1 } else { start.col };
+        for (i, piece) in yank.text.split('\n').enumerate() {
+            let line = start.line + i;
+            if line >= buffer.line_count() {
+                let end = buffer.cursor_at(buffer.text.len_chars());
+                buffer.insert(end, "\n");
+            }
+            let len = buffer.line_len(line);
+            if len < col {
+                buffer.insert(Cursor::new(line, len), &" ".repeat(col - len));
+            }
+            buffer.insert(Cursor::new(line, col), &piece.repeat(count));
+        }
+        buffer.pos = Cursor::new(start.line, col);
+    } else if yank.kind == YankKind::Lines {
+        let line = if after { buffer.pos.line + 1 } else { buffer.pos.line };
+        if line >= buffer.line_count() {
+            let end = buffer.cursor_at(buffer.text.len_chars());
+            buffer.insert(end, &format!("\n{}", text.strip_suffix('\n').unwrap_or(&text)));
+        } else {
+            buffer.insert(Cursor::new(line, 0), &text);
+        }
+        buffer.pos = Cursor::new(line, motion::first_non_blank(buffer, line));
+    } else {
+        let mut at = buffer.pos;
+        if after && buffer.line_len(at.line) > 0 {
+            at.col += 1;
+        }
+        let end = buffer.insert(at, &text);
+        buffer.pos = buffer.cursor_at(buffer.char_idx(end).saturating_sub(1));
+    }
+    buffer.history.end_group();
+    buffer.pos = buffer.clamp_normal(buffer.pos);
+    buffer.want_col = buffer.pos.col;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn run(data: &str, at: Cursor, operator: Operator, target: Target, count: Option<usize>) -> (Buffer, Option<Yank>) {
+        let mut buffer = Buffer::new(None, data);
+        buffer.pos = at;
+        let span = span(&buffer, operator, target, count).unwrap();
+        let yank = apply(&mut buffer, operator, span);
+        (buffer, yank)
+    }
+
+    #[test]
+    fn deletes_with_motions_and_lines() {
+        let (buffer, yank) = run("foo bar baz", Cursor::new(0, 0), Operator::Delete, Target::Motion(Motion::WordForward), Some(2));
+        assert_eq!(buffer.text.to_string(), "baz");
+        assert_eq!(yank.unwrap().text, "foo bar ");
+
+        let (buffer, _) = run("foo bar\nbaz", Cursor::new(0, 4), Operator::Delete, Target::Motion(Motion::WordForward), None);
+        assert_eq!(buffer.text.to_string(), "foo \nbaz");
+
+        let (buffer, yank) = run("a\nb\nc", Cursor::new(1, 0), Operator::Delete, Target::Lines, Some(5));
+        assert_eq!(buffer.text.to_string(), "a");
+        assert_eq!(yank.unwrap(), Yank { text: "b\nc\n".to_string(), kind: YankKind::Lines });
+
+        let (buffer, _) = run("a\nb\nc", Cursor::new(0, 0), Operator::Delete, Target::Motion(Motion::Down), None);
+        assert_eq!(buffer.text.to_string(), "c");
+    }
+
+    #[test]
+    fn changes_and_cases() {
+        let (buffer, _) = run("foo bar", Cursor::new(0, 0), Operator::Change, Target::Motion(Motion::WordForward), None);
+        assert_eq!(buffer.text.to_string(), " bar");
+
+        let object = TextObject::from_char(true, '(').unwrap();
+        let (buffer, _) = run("f(abc)", Cursor::new(0, 3), Operator::Uppercase, Target::Object(object), None);
+        assert_eq!(buffer.text.to_string(), "f(ABC)");
+
+        let (buffer, _) = run("a\n\n  b", Cursor::new(0, 0), Operator::Indent, Target::Lines, Some(3));
+        assert_eq!(buffer.text.to_string(), "    a\n\n      b");
+        let (buffer, _) = run("      b", Cursor::new(0, 0), Operator::Outdent, Target::Lines, None);
+        assert_eq!(buffer.text.to_string(), "  b");
+        let (buffer, _) = run("\t b", Cursor::new(0, 0), Operator::Outdent, Target::Lines, None);
+        assert_eq!(buffer.text.to_string(), "     b");
+    }
+
+    #[test]
+    fn block_operations() {
+        let mut buffer = Buffer::new(None, "abcd
+ef
+ghij");
+        let block = Block { first: 0, last: 2, left: 1, right: 2 };
+        let yank = apply_block(&mut buffer, Operator::Delete, block).unwrap();
+        assert_eq!(buffer.text.to_string(), "ad
+e
+gj");
+        assert_eq!(yank, Yank { text: "bc\nf\nhi".to_string(), kind: YankKind::Block });
+
+        buffer.pos = Cursor::new(0, 0);
+        put(&mut buffer, &yank, true, 1);
+        assert_eq!(buffer.text.to_string(), "abcd
+ef
+ghij");
+    }
+
+    #[test]
+    fn puts_text() {
+        let mut buffer = Buffer::new(None, "ab\ncd");
+        put(&mut buffer, &Yank { text: "x".to_string(), kind: YankKind::Chars }, true, 2);
+        assert_eq!(buffer.text.to_string(), "axxb\ncd");
+
+        buffer.pos = Cursor::new(1, 0);
+        put(&mut buffer, &Yank { text: "new\n".to_string(), kind: YankKind::Lines }, true, 1);
+        assert_eq!(buffer.text.to_string(), "axxb\ncd\nnew");
+        assert_eq!(buffer.pos, Cursor::new(2, 0));
+
+        buffer.undo(2);
+        assert_eq!(buffer.text.to_string(), "ab\ncd");
+    }
+}
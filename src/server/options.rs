@@ -0,0 +1,189 @@
+/// Editor settings changed with `:set`.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Persist each buffer's undo history next to its file on write.
+    pub undofile: bool,
+    /// Searches ignore case...
+    pub ignorecase: bool,
+    /// ...unless the pattern has an uppercase letter.
+    pub smartcase: bool,
+    /// Highlight every match of the last search.
+    pub hlsearch: bool,
+    /// Jump to and highlight matches while a search is being typed.
+    pub incsearch: bool,
+    /// Lines kept visible above and below the cursor.
+    pub scrolloff: usize,
+    /// Columns kept visible left and right of the cursor.
+    pub sidescrolloff: usize,
+    /// Wrap long lines onto the next screen row instead of scrolling sideways.
+    pub wrap: bool,
+    /// Wrap at whitespace rather than at the last character that fits.
+    pub linebreak: bool,
+    /// Shown at the start of each wrapped continuation row.
+    pub showbreak: String,
+    /// The indentation settings new buffers start with.
+    pub indent: Indent,
+}
+
+/// The indentation settings. Each buffer has its own copy, which `:set`
+/// changes along with the global one and `:setlocal` changes alone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Indent {
+    /// Tabs advance to the next multiple of this many columns.
+    pub tabstop: usize,
+    /// The columns `>>` and `<<` shift by, or 0 to use `tabstop`.
+    pub shiftwidth: usize,
+    /// Indent with spaces rather than tabs.
+    pub expandtab: bool,
+    /// The columns Insert mode Tab and Backspace move by, or 0 to insert tabs.
+    pub softtabstop: usize,
+}
+
+/// Every option's full name, for completion.
+pub const NAMES: &[&str] = &[
+    "undofile", "ignorecase", "smartcase", "hlsearch", "incsearch", "scrolloff", "sidescrolloff", "wrap", "linebreak", "showbreak",
+    "tabstop", "shiftwidth", "expandtab", "softtabstop",
+];
+
+impl Default for Indent {
+    fn default() -> Self {
+        Indent {
+            tabstop: 8,
+            shiftwidth: 4,
+            expandtab: true,
+            softtabstop: 0,
+        }
+    }
+}
+
+impl Indent {
+    /// Applies a `:set` argument if it names an indentation option, returning
+    /// whether it did.
+    pub fn set(&mut self, arg: &str) -> Result<bool, String> {
+        if let Some((name, value)) = arg.split_once('=') {
+            let value = || value.parse().map_err(|_| format!("Number required after =: {arg}"));
+            match name {
+                "tabstop" | "ts" => match value()? {
+                    0 => return Err(format!("Argument must be positive: {arg}")),
+                    value => self.tabstop = value,
+                },
+                "shiftwidth" | "sw" => self.shiftwidth = value()?,
+                "softtabstop" | "sts" => self.softtabstop = value()?,
+                _ => return Ok(false),
+            }
+            return Ok(true);
+        }
+        match parse_flag(arg) {
+            ("expandtab" | "et", value) => self.expandtab = value.unwrap_or(!self.expandtab),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// The columns `>>` and `<<` shift by.
+    pub fn shift(&self) -> usize {
+        if self.shiftwidth == 0 { self.tabstop } else { self.shiftwidth }
+    }
+
+    /// The columns a tab drawn at screen column `col` takes.
+    pub fn tab_width(&self, col: usize) -> usize {
+        self.tabstop - col % self.tabstop
+    }
+
+    /// Whitespace filling screen columns `from` up to `to`: tabs to each tab
+    /// stop on the way unless `expandtab` is set, then spaces.
+    pub fn fill(&self, mut from: usize, to: usize) -> String {
+        let mut text = String::new();
+        while !self.expandtab && from + self.tab_width(from) <= to {
+            from += self.tab_width(from);
+            text.push('\t');
+        }
+        text.push_str(&" ".repeat(to.saturating_sub(from)));
+        text
+    }
+}
+
+/// Splits a boolean `:set` argument into the option's name and the value to
+/// give it, or `None` to toggle it.
+fn parse_flag(arg: &str) -> (&str, Option<bool>) {
+    if let Some(name) = arg.strip_suffix('!') {
+        (name, None)
+    } else if let Some(name) = arg.strip_prefix("no") {
+        (name, Some(false))
+    } else {
+        (arg, Some(true))
+    }
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            undofile: false,
+            ignorecase: true,
+            smartcase: true,
+            hlsearch: true,
+            incsearch: true,
+            scrolloff: 5,
+            sidescrolloff: 0,
+            wrap: false,
+            linebreak: false,
+            showbreak: String::new(),
+            indent: Indent::default(),
+        }
+    }
+}
+
+impl Options {
+    /// Applies a single `:set` argument such as `undofile`, `noundofile`,
+    /// `undofile!`, `scrolloff=3` or `showbreak=>>`.
+    pub fn set(&mut self, arg: &str) -> Result<(), String> {
+        if self.indent.set(arg)? {
+            return Ok(());
+        }
+        if let Some((name, value)) = arg.split_once('=') {
+            if let "showbreak" | "sbr" = name {
+                self.showbreak = value.to_string();
+                return Ok(());
+            }
+            let option = match name {
+                "scrolloff" | "so" => &mut self.scrolloff,
+                "sidescrolloff" | "siso" => &mut self.sidescrolloff,
+                _ => return Err(format!("Unknown option: {name}")),
+            };
+            *option = value.parse().map_err(|_| format!("Number required after =: {arg}"))?;
+            return Ok(());
+        }
+
+        let (name, value) = parse_flag(arg);
+        let option = match name {
+            "undofile" | "udf" => &mut self.undofile,
+            "ignorecase" | "ic" => &mut self.ignorecase,
+            "smartcase" | "scs" => &mut self.smartcase,
+            "hlsearch" | "hls" => &mut self.hlsearch,
+            "incsearch" | "is" => &mut self.incsearch,
+            "wrap" => &mut self.wrap,
+            "linebreak" | "lbr" => &mut self.linebreak,
+            _ => return Err(format!("Unknown option: {arg}")),
+        };
+        *option = value.unwrap_or(!*option);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sets_indentation() {
+        let mut options = Options::default();
+        options.set("ts=4").unwrap();
+        options.set("noet").unwrap();
+        assert_eq!((options.indent.tabstop, options.indent.expandtab), (4, false));
+        assert!(options.set("tabstop=0").is_err());
+        assert_eq!(options.indent.fill(2, 11), "\t\t   ");
+
+        options.set("et").unwrap();
+        assert_eq!(options.indent.fill(2, 11), " ".repeat(9));
+    }
+}
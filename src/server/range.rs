@@ -0,0 +1,109 @@
+use crate::buffer::Buffer;
+
+/// An inclusive range of zero-based line numbers given to an ex command.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct LineRange {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl LineRange {
+    pub fn new(start: usize, end: usize) -> Self {
+        LineRange { start, end }
+    }
+}
+
+fn parse_number(text: &str) -> (Option<usize>, &str) {
+    let digits = text.len() - text.trim_start_matches(|ch: char| ch.is_ascii_digit()).len();
+    (text[..digits].parse().ok(), &text[digits..])
+}
+
+/// Parses one address such as `12`, `.`, `$`, `'<` or `.+3` into a zero-based
+/// line, which may be past the end of the buffer.
+fn parse_address<'a>(text: &'a str, buffer: &Buffer) -> Result<(Option<isize>, &'a str), String> {
+    let mut chars = text.chars();
+    let (mut line, mut rest) = match chars.next() {
+        Some('.') => (Some(buffer.pos.line as isize), chars.as_str()),
+        Some('$') => (Some(buffer.line_count() as isize - 1), chars.as_str()),
+        Some('\'') => {
+            let marks = buffer.visual_marks.ok_or_else(|| "Mark not set".to_string())?;
+            match chars.next() {
+                Some('<') => (Some(marks.0 as isize), chars.as_str()),
+                Some('>') => (Some(marks.1 as isize), chars.as_str()),
+                _ => return Err("Invalid mark".to_string()),
+            }
+        },
+        Some(ch) if ch.is_ascii_digit() => {
+            let (number, rest) = parse_number(text);
+            (number.map(|n| n as isize - 1), rest)
+        },
+        _ => (None, text),
+    };
+
+    while let Some(sign @ ('+' | '-')) = rest.chars().next() {
+        let (number, after) = parse_number(&rest[1..]);
+        let offset = number.unwrap_or(1) as isize;
+        let base = line.unwrap_or(buffer.pos.line as isize);
+        line = Some(if sign == '+' { base + offset } else { base - offset });
+        rest = after;
+    }
+    Ok((line, rest))
+}
+
+/// Parses the line range at the start of an ex command, returning it (if one
+/// was given) and the rest of the command.
+pub fn parse<'a>(text: &'a str, buffer: &Buffer) -> Result<(Option<LineRange>, &'a str), String> {
+    if let Some(rest) = text.strip_prefix('%') {
+        return Ok((Some(LineRange::new(0, buffer.line_count() - 1)), rest));
+    }
+
+    let (start, rest) = parse_address(text, buffer)?;
+    let (end, rest) = match rest.strip_prefix(',') {
+        Some(rest) => {
+            let (end, rest) = parse_address(rest, buffer)?;
+            (Some(end.unwrap_or(buffer.pos.line as isize)), rest)
+        },
+        None => (start, rest),
+    };
+    let start = match (start, end) {
+        (None, None) => return Ok((None, rest)),
+        (start, _) => start.unwrap_or(buffer.pos.line as isize),
+    };
+    let end = end.unwrap_or(start);
+
+    let last = buffer.line_count() as isize - 1;
+    // Line 0 is accepted as an alias for the first line.
+    let start = if start == -1 { 0 } else { start };
+    let end = if end == -1 { 0 } else { end };
+    if start < 0 || end < 0 || start > last || end > last {
+        return Err("Invalid range".to_string());
+    }
+    let (start, end) = (start.min(end) as usize, start.max(end) as usize);
+    Ok((Some(LineRange::new(start, end)), rest))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::buffer::Cursor;
+
+    #[test]
+    fn parses_ranges() {
+        let mut buffer = Buffer::new(None, "a\nb\nc\nd\ne");
+        buffer.pos = Cursor::new(1, 0);
+        let lines = |start, end| Some(LineRange::new(start, end));
+
+        assert_eq!(parse("s/a/b/", &buffer), Ok((None, "s/a/b/")));
+        assert_eq!(parse("%s", &buffer), Ok((lines(0, 4), "s")));
+        assert_eq!(parse(".,$s", &buffer), Ok((lines(1, 4), "s")));
+        assert_eq!(parse("2,4d", &buffer), Ok((lines(1, 3), "d")));
+        assert_eq!(parse("4,2", &buffer), Ok((lines(1, 3), "")));
+        assert_eq!(parse(".+2", &buffer), Ok((lines(3, 3), "")));
+        assert_eq!(parse(",+1", &buffer), Ok((lines(1, 2), "")));
+        assert_eq!(parse("9", &buffer), Err("Invalid range".to_string()));
+        assert_eq!(parse("'<,'>", &buffer), Err("Mark not set".to_string()));
+
+        buffer.visual_marks = Some((2, 3));
+        assert_eq!(parse("'<,'>s", &buffer), Ok((lines(2, 3), "s")));
+    }
+}
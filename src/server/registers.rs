@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+
+use crate::operator::{Yank, YankKind};
+
+/// The register store shared by every connected client.
+///
+/// Registers follow vi: `"` always holds the most recent yank or delete, `0`
+/// the last yank, `1`–`9` a history of multi-line deletes, `-` the last small
+/// delete, `a`–`z` are named (written through `A`–`Z` to append), `_` discards,
+/// and `+`/`*` mirror the client's system clipboard.
+#[derive(Debug, Default)]
+pub struct Registers {
+    registers: HashMap<char, Yank>,
+}
+
+pub fn is_register(name: char) -> bool {
+    name.is_ascii_alphanumeric() || matches!(name, '"' | '-' | '_' | '+' | '*')
+}
+
+pub fn is_clipboard(name: char) -> bool {
+    matches!(name, '+' | '*')
+}
+
+impl Registers {
+    pub fn get(&self, name: char) -> Option<&Yank> {
+        self.registers.get(&name.to_ascii_lowercase())
+    }
+
+    pub fn set(&mut self, name: char, yank: Yank) {
+        self.registers.insert(name, yank);
+    }
+
+    fn write_named(&mut self, name: char, yank: Yank) {
+        let yank = if name.is_ascii_uppercase() {
+            let name = name.to_ascii_lowercase();
+            let appended = match self.registers.remove(&name) {
+                Some(old) if old.kind == YankKind::Lines || yank.kind == YankKind::Lines => {
+                    let mut text = old.text;
+                    if !text.ends_with('\n') {
+                        text.push('\n');
+                    }
+                    text.push_str(&yank.text);
+                    Yank { text, kind: YankKind::Lines }
+                },
+                Some(old) => Yank { text: old.text + &yank.text, kind: old.kind },
+                None => yank,
+            };
+            self.registers.insert(name, appended.clone());
+            appended
+        } else {
+            self.registers.insert(name, yank.clone());
+            yank
+        };
+        self.registers.insert('"', yank);
+    }
+
+    /// Stores yanked text in `name`, or in `0` when no register was given.
+    pub fn yank(&mut self, name: Option<char>, yank: Yank) {
+        match name {
+            Some('_') => (),
+            Some('"') | None => {
+                self.registers.insert('0', yank.clone());
+                self.registers.insert('"', yank);
+            },
+            Some(name) => self.write_named(name, yank),
+        }
+    }
+
+    /// Stores deleted text in `name`, or in `1`/`-` when no register was given.
+    pub fn delete(&mut self, name: Option<char>, yank: Yank) {
+        match name {
+            Some('_') => (),
+            Some('"') | None => {
+                if yank.kind != YankKind::Chars || yank.text.contains('\n') {
+                    for n in (1..9).rev() {
+                        let from = char::from_digit(n, 10).unwrap();
+                        let to = char::from_digit(n + 1, 10).unwrap();
+                        if let Some(old) = self.registers.remove(&from) {
+                            self.registers.insert(to, old);
+                        }
+                    }
+                    self.registers.insert('1', yank.clone());
+                } else {
+                    self.registers.insert('-', yank.clone());
+                }
+                self.registers.insert('"', yank);
+            },
+            Some(name) => self.write_named(name, yank),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn chars(text: &str) -> Yank {
+        Yank { text: text.to_string(), kind: YankKind::Chars }
+    }
+
+    fn lines(text: &str) -> Yank {
+        Yank { text: text.to_string(), kind: YankKind::Lines }
+    }
+
+    #[test]
+    fn yanks_and_deletes_fill_the_right_registers() {
+        let mut registers = Registers::default();
+        registers.yank(None, chars("y"));
+        registers.delete(None, chars("small"));
+        registers.delete(None, lines("one\n"));
+        registers.delete(None, lines("two\n"));
+
+        assert_eq!(registers.get('0'), Some(&chars("y")));
+        assert_eq!(registers.get('-'), Some(&chars("small")));
+        assert_eq!(registers.get('1'), Some(&lines("two\n")));
+        assert_eq!(registers.get('2'), Some(&lines("one\n")));
+        assert_eq!(registers.get('"'), Some(&lines("two\n")));
+    }
+
+    #[test]
+    fn named_registers_append_and_black_hole_discards() {
+        let mut registers = Registers::default();
+        registers.yank(Some('a'), chars("foo"));
+        registers.yank(Some('A'), chars("bar"));
+        assert_eq!(registers.get('a'), Some(&chars("foobar")));
+
+        registers.yank(Some('A'), lines("baz\n"));
+        assert_eq!(registers.get('a'), Some(&lines("foobar\nbaz\n")));
+
+        registers.delete(Some('_'), chars("gone"));
+        assert_eq!(registers.get('"'), Some(&lines("foobar\nbaz\n")));
+    }
+}
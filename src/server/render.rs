@@ -1,76 +1,710 @@
-use crate::TermTextState;
-use tt::message::ServerMessage;
-use log::*;
+use crate::{BufferMode, TermTextState};
+use crate::buffer::{Buffer, BufferId, Cursor};
+use crate::options::Options;
+use crate::cmdline::Completion;
+use crate::scroll::{self, Viewport};
+use crate::search;
+use crate::visual;
+use crate::view::{ClientId, Session, View};
+use crate::window::{Rect, Window};
+use crate::wrap::{self, Wrap};
+use crate::theme::{self, Group, Theme};
+use tt::message::{Color, ColorDepth, Position, ServerMessage, Size, Span, Style};
+use tt::unicode;
+use unicode_segmentation::UnicodeSegmentation;
 
+pub const GUTTER_WIDTH: usize = 9;
 
-pub fn render(state: &TermTextState) -> Vec<ServerMessage> {
-    let mut messages = Vec::new();
+/// The screen columns a grapheme cluster of a buffer line drawn at column
+/// `col` takes. Tabs reach the next tab stop.
+fn grapheme_cols(grapheme: &str, col: usize, tabstop: usize) -> usize {
+    if grapheme == "\t" { tabstop - col % tabstop } else { unicode::grapheme_width(grapheme) }
+}
+
+/// The screen column where character `col` of `line` is drawn. A column inside
+/// a grapheme cluster is drawn where the cluster starts.
+pub fn screen_col(line: &str, col: usize, tabstop: usize) -> usize {
+    let mut width = 0;
+    let mut chars = 0;
+    for grapheme in line.graphemes(true) {
+        chars += grapheme.chars().count();
+        if chars > col {
+            break;
+        }
+        width += grapheme_cols(grapheme, width, tabstop);
+    }
+    width
+}
+
+/// The character of `line` drawn at screen column `col`: the start of the
+/// grapheme cluster covering it.
+pub fn char_col(line: &str, col: usize, tabstop: usize) -> usize {
+    let mut width = 0;
+    let mut chars = 0;
+    for grapheme in line.graphemes(true) {
+        width += grapheme_cols(grapheme, width, tabstop);
+        if width > col {
+            return chars;
+        }
+        chars += grapheme.chars().count();
+    }
+    chars
+}
+
+/// A line as it is drawn, with tabs expanded to spaces.
+pub fn expand_tabs(line: &str, tabstop: usize) -> String {
+    let mut text = String::new();
+    let mut width = 0;
+    for grapheme in line.graphemes(true) {
+        let cols = grapheme_cols(grapheme, width, tabstop);
+        if grapheme == "\t" {
+            text.push_str(&" ".repeat(cols));
+        } else {
+            text.push_str(grapheme);
+        }
+        width += cols;
+    }
+    text
+}
+
+/// The part of a drawn line between screen columns `start` and `end`. Wide
+/// characters cut by either edge are drawn as spaces.
+fn slice_columns(text: &str, start: usize, end: usize) -> String {
+    let mut slice = String::new();
+    let mut col = 0;
+    for grapheme in text.graphemes(true) {
+        if col >= end {
+            break;
+        }
+        let width = unicode::grapheme_width(grapheme);
+        if col >= start && col + width <= end {
+            slice.push_str(grapheme);
+        } else {
+            let cut = (col + width).min(end).saturating_sub(col.max(start));
+            slice.push_str(&" ".repeat(cut));
+        }
+        col += width;
+    }
+    slice
+}
 
-    let size = state.size;
+/// `line` with `text` drawn over it from screen column `col`.
+fn overlay(line: &str, col: usize, text: &str) -> String {
+    let mut drawn = slice_columns(line, 0, col);
+    drawn.push_str(&" ".repeat(col.saturating_sub(unicode::width(&drawn))));
+    drawn.push_str(text);
+    drawn.push_str(&slice_columns(line, col + unicode::width(text), usize::MAX));
+    drawn
+}
 
-    info!("send_update()");
-    let pos = (0, 0);
-    let mut cursor_pos = pos;
+/// How lines are wrapped in a window `width` columns wide, if they are.
+pub fn text_wrap(options: &Options, width: u16) -> Option<Wrap> {
+    options.wrap.then(|| Wrap {
+        width: (width as usize).saturating_sub(GUTTER_WIDTH),
+        showbreak: unicode::width(&options.showbreak),
+        linebreak: options.linebreak,
+    })
+}
 
-    let mut lines = Vec::new();
-    let show_line_numbers = true;
+/// The highlight group of text of each syntax scope. A scope takes the group of
+/// the longest entry it starts with, so `comment.line` is drawn as `comment`.
+const SCOPE_GROUPS: &[(&str, Group)] = &[
+    ("comment", Group::Comment),
+    ("string", Group::String),
+    ("constant", Group::Constant),
+    ("constant.character.escape", Group::Special),
+    ("keyword", Group::Keyword),
+    ("storage", Group::Keyword),
+    ("entity.name.function", Group::Function),
+    ("support.function", Group::Function),
+    ("entity.name.type", Group::Type),
+    ("support.type", Group::Type),
+    ("variable", Group::Identifier),
+    ("meta.attribute", Group::PreProc),
+    ("entity.name.section", Group::Title),
+    ("markup.heading", Group::Title),
+    ("markup.bold", Group::Bold),
+    ("markup.italic", Group::Italic),
+    ("markup.raw", Group::String),
+    ("markup.quote", Group::Comment),
+    ("markup.list", Group::Keyword),
+    ("markup.underline.link", Group::Underlined),
+];
 
-    if let Some(buffer) = state.current_buffer() {
-        let data = buffer.data.clone();
+fn scope_group(scope: &str) -> Option<Group> {
+    SCOPE_GROUPS.iter()
+        .filter(|(prefix, _)| scope.strip_prefix(prefix).is_some_and(|rest| rest.is_empty() || rest.starts_with('.')))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map(|(_, group)| *group)
+}
+
+/// What a window filling `rect` has room to show.
+pub fn viewport(options: &Options, rect: Rect) -> Viewport {
+    Viewport {
+        rows: rect.height.saturating_sub(1) as usize,
+        cols: (rect.width as usize).saturating_sub(GUTTER_WIDTH),
+        scrolloff: options.scrolloff,
+        sidescrolloff: options.sidescrolloff,
+        wrap: text_wrap(options, rect.width),
+    }
+}
 
-        let mut line = if show_line_numbers {
-            "     1 | ".to_string()
+/// The colors other clients' cursors are drawn in, picked by client id.
+const CLIENT_COLORS: &[Color] = &[Color::Ansi(1), Color::Ansi(2), Color::Ansi(4), Color::Ansi(5), Color::Ansi(6), Color::Ansi(3)];
+
+/// Where another client is: its cursor and selection in the buffer of its current window.
+struct Remote<'a> {
+    name: &'a str,
+    color: Color,
+    buffer: BufferId,
+    pos: Cursor,
+    anchor: Option<Cursor>,
+    want_col: usize,
+    mode: BufferMode,
+}
+
+/// Every client other than `client`, where it is.
+fn remotes(state: &TermTextState, client: ClientId) -> Vec<Remote<'_>> {
+    let mut remotes = vec![];
+    for (&id, view) in state.views.iter().filter(|(&id, _)| id != client) {
+        let window = view.layout().current();
+        let (Some(buffer), Some(session)) = (window.buffer.and_then(|id| state.buffers.get(id)), state.session_of(id)) else { continue };
+        // The acting client's cursor is the buffer's own.
+        let (pos, anchor, want_col) = if id == state.client {
+            (buffer.pos, buffer.anchor, buffer.want_col)
         } else {
-             String::new()
+            (window.cursor, window.anchor, window.want_col)
+        };
+        remotes.push(Remote {
+            name: &view.name,
+            color: CLIENT_COLORS[id % CLIENT_COLORS.len()],
+            buffer: buffer.id,
+            pos: buffer.clamp(pos),
+            anchor: anchor.map(|anchor| buffer.clamp(anchor)),
+            want_col,
+            mode: session.mode,
+        });
+    }
+    remotes.sort_by_key(|remote| remote.name);
+    remotes
+}
+
+/// A rectangle of the screen: its text, and the styles laid over it as
+/// (row, column, width, style), later ones winning where they overlap.
+struct Region {
+    pos: Position,
+    size: Size,
+    lines: Vec<String>,
+    styles: Vec<(usize, usize, usize, Style)>,
+}
+
+impl Region {
+    fn new(pos: Position, size: Size, lines: Vec<String>) -> Self {
+        Region { pos, size, lines, styles: vec![] }
+    }
+
+    fn style(&mut self, row: usize, col: usize, width: usize, style: Style) {
+        self.styles.push((row, col, width, style));
+    }
+
+    /// The update drawing the region in the colors a terminal of `depth` has,
+    /// each line split into spans where its style changes. Cells without a style
+    /// of their own are Normal, and styles reaching past the end of a line are
+    /// padded out.
+    fn into_message(self, theme: &Theme, depth: ColorDepth) -> ServerMessage {
+        let width = self.size.0 as usize;
+        let normal = theme.style(Group::Normal);
+        let lines = self.lines.iter().enumerate().map(|(row, line)| {
+            let mut cells = vec![normal; width];
+            for &(_, col, len, style) in self.styles.iter().filter(|(r, ..)| *r == row) {
+                for cell in cells.iter_mut().skip(col).take(len) {
+                    *cell = style;
+                }
+            }
+            let mut spans: Vec<Span> = vec![];
+            let mut col = 0;
+            let mut push = |text: &str, style: Style| {
+                let style = theme::downgrade(style, depth);
+                match spans.last_mut() {
+                    Some(span) if span.style == style => span.text.push_str(text),
+                    _ => spans.push(Span::new(text, style)),
+                }
+            };
+            for grapheme in line.graphemes(true) {
+                push(grapheme, cells.get(col).copied().unwrap_or(normal));
+                col += unicode::grapheme_width(grapheme);
+            }
+            let styled = cells.iter().rposition(|style| *style != Style::PLAIN).map_or(0, |last| last + 1);
+            while col < styled {
+                push(" ", cells[col]);
+                col += 1;
+            }
+            spans
+        }).collect();
+        ServerMessage::Update(self.pos, self.size, lines)
+    }
+}
+
+/// A row of a window: the screen columns it shows of a buffer line.
+struct Row {
+    line: usize,
+    /// Which of the line's rows this is, when it is wrapped.
+    row: usize,
+    start: usize,
+    end: usize,
+}
+
+/// The rows a window of `rect` shows, from its top line down.
+fn window_rows(state: &TermTextState, window: &Window, buffer: &Buffer, rect: Rect) -> Vec<Row> {
+    let height = rect.height.saturating_sub(1) as usize;
+    let wrap = text_wrap(&state.options, rect.width);
+    let mut rows = vec![];
+    for line in window.top..buffer.line_count() {
+        let spans = match &wrap {
+            Some(wrap) => wrap::line_rows(buffer, line, wrap),
+            None => vec![(window.left, usize::MAX)],
         };
+        for (row, (start, end)) in spans.into_iter().enumerate() {
+            if rows.len() == height {
+                return rows;
+            }
+            rows.push(Row { line, row, start, end });
+        }
+    }
+    rows
+}
 
-        for ch in data.chars() {
-            if ch == '\n' {
-                lines.push(line);
-                line = if show_line_numbers {
-                    format!("{:6} | ", cursor_pos.1 + 2)
-                } else {
-                     String::new()
-                };
-                cursor_pos.0 = 0;
-                cursor_pos.1 += 1;
-            } else if ch == '\t' {
-                line.push_str("    ");
-                cursor_pos.0 += 4;
+/// The columns a wrapped row gives to the `showbreak` text.
+fn row_prefix(state: &TermTextState, row: &Row) -> usize {
+    if row.row > 0 { unicode::width(&state.options.showbreak) } else { 0 }
+}
+
+/// The Tab completion candidates, shown on one line above the status line with
+/// the selected one highlighted. The line scrolls to keep the selection visible.
+fn render_completion(theme: &Theme, completion: &Completion, pos: Position, width: u16) -> Region {
+    let mut line = String::new();
+    let mut selected = (0, 0);
+    for (i, candidate) in completion.candidates.iter().enumerate() {
+        if i == completion.selected {
+            selected = (unicode::width(&line), unicode::width(candidate));
+        }
+        line.push_str(candidate);
+        line.push_str("  ");
+    }
+    // A candidate wider than the line is shown from its start.
+    let skip = (selected.0 + selected.1).saturating_sub(width as usize).min(selected.0);
+    let line = slice_columns(&line, skip, skip + width as usize);
+    let mut region = Region::new(pos, (width, 1), vec![line]);
+    let col = selected.0.saturating_sub(skip).min(width as usize);
+    region.style(0, col, selected.1, theme.style(Group::WildMenu));
+    region
+}
+
+/// The pattern whose matches should be highlighted: the one being typed, or
+/// else the last search.
+fn search_highlight_pattern<'a>(state: &'a TermTextState, session: &'a Session) -> Option<&'a str> {
+    match &session.search_prompt {
+        Some(prompt) if session.mode == BufferMode::Search && state.options.incsearch => Some(prompt.pattern.as_str()),
+        _ if state.options.hlsearch && state.highlight_search => state.last_search.as_ref().map(|search| search.pattern.as_str()),
+        _ => None,
+    }.filter(|pattern| !pattern.is_empty())
+}
+
+/// The row showing column `col` (on screen) of buffer line `line`.
+fn row_of(rows: &[Row], line: usize, col: usize) -> Option<usize> {
+    rows.iter()
+        .position(|row| row.line == line && col < row.end)
+        .or_else(|| rows.iter().rposition(|row| row.line == line))
+}
+
+/// Where the cursor of `window` is drawn when it fills `rect`. The cursor of a
+/// `live` window, the acting client's current one, is in its buffer.
+pub fn window_cursor(state: &TermTextState, window: &Window, rect: Rect, live: bool) -> Position {
+    let buffer = match window.buffer.and_then(|id| state.buffers.get(id)) {
+        Some(buffer) => buffer,
+        None => return (rect.x, rect.y),
+    };
+    let pos = if live { buffer.pos } else { window.cursor };
+    let line = pos.line.min(buffer.line_count() - 1);
+    let col = screen_col(&buffer.line(line), pos.col, buffer.indent.tabstop);
+    let rows = window_rows(state, window, buffer, rect);
+    let y = row_of(&rows, line, col).unwrap_or(0);
+    let x = match rows.get(y) {
+        Some(row) => GUTTER_WIDTH + row_prefix(state, row) + col.saturating_sub(row.start),
+        None => GUTTER_WIDTH,
+    };
+    (rect.x + x.min(rect.width.saturating_sub(1) as usize) as u16, rect.y + y as u16)
+}
+
+/// Draws a window's lines and its status line, for a client in `session`,
+/// with the cursors of the `others` in the same buffer.
+fn render_window(state: &TermTextState, session: &Session, window: &Window, rect: Rect, current: bool, live: bool, others: &[Remote]) -> Vec<Region> {
+    let rows = rect.height.saturating_sub(1) as usize;
+    let buffer = window.buffer.and_then(|id| state.buffers.get(id));
+    let mut region = Region::new((rect.x, rect.y), (rect.width, rows as u16), vec![]);
+
+    let theme = &state.theme;
+    let mut status = String::new();
+    if let Some(buffer) = buffer {
+        let screen_rows = window_rows(state, window, buffer, rect);
+        for (y, row) in screen_rows.iter().enumerate() {
+            let mut line = if row.row == 0 {
+                format!("{:6} | ", row.line + 1)
             } else {
-                line.push(ch);
-                cursor_pos.0 += 1;
+                // Continuation rows leave the line number blank.
+                format!("{:6} | {}", "", state.options.showbreak)
+            };
+            region.style(y, 0, GUTTER_WIDTH, theme.style(Group::LineNr));
+            region.style(y, GUTTER_WIDTH, row_prefix(state, row), theme.style(Group::NonText));
+            let text = expand_tabs(&buffer.line(row.line), buffer.indent.tabstop);
+            line.push_str(&slice_columns(&text, row.start, row.end));
+            region.lines.push(line);
+        }
+        let visible = window.top..screen_rows.last().map_or(window.top, |row| row.line + 1);
+        let lines: Vec<String> = visible.clone().map(|line| buffer.line(line)).collect();
+
+        let mut ranges = vec![];
+        for line_no in visible.clone() {
+            for (start, end, scope) in buffer.highlights.tokens(line_no) {
+                if let Some(group) = scope_group(scope) {
+                    ranges.push((line_no, start, end, theme.style(group)));
+                }
+            }
+        }
+        if let Some(regex) = search_highlight_pattern(state, session).and_then(|pattern| search::compile(pattern, &state.options).ok()) {
+            for line_no in visible.clone() {
+                for (start, end) in search::line_matches(&regex, &lines[line_no - visible.start]) {
+                    ranges.push((line_no, start, end, theme.style(Group::Search)));
+                }
+            }
+        }
+        let others: Vec<&Remote> = others.iter().filter(|remote| remote.buffer == buffer.id).collect();
+        for remote in &others {
+            if let Some(anchor) = remote.anchor {
+                let style = Style { fg: remote.color, underline: true, ..theme.style(Group::Normal) };
+                for (line, start, end) in visual::highlights_between(buffer, anchor, remote.pos, remote.want_col, remote.mode) {
+                    ranges.push((line, start, end, style));
+                }
+            }
+            let style = Style { fg: Color::Ansi(0), bg: remote.color, ..Style::PLAIN };
+            ranges.push((remote.pos.line, remote.pos.col, remote.pos.col + 1, style));
+        }
+        if live {
+            let selection = theme.style(Group::Visual);
+            ranges.extend(visual::highlights(buffer, session.mode).into_iter().map(|(line, start, end)| (line, start, end, selection)));
+            if let Some(pending) = session.substitution.as_ref().and_then(|substitution| substitution.pending.as_ref()) {
+                ranges.push((pending.start.line, pending.start.col, pending.end.col, selection));
+            }
+        }
+        for (line_no, start, end, style) in ranges {
+            if !visible.contains(&line_no) {
+                continue;
+            }
+            let line = &lines[line_no - visible.start];
+            let start_col = screen_col(line, start, buffer.indent.tabstop);
+            let end_col = screen_col(line, end, buffer.indent.tabstop).max(start_col + 1);
+            for (y, row) in screen_rows.iter().enumerate().filter(|(_, row)| row.line == line_no) {
+                let (start_col, end_col) = (start_col.max(row.start), end_col.min(row.end));
+                if start_col < end_col {
+                    let x = GUTTER_WIDTH + row_prefix(state, row) + start_col - row.start;
+                    region.style(y, x, end_col - start_col, style);
+                }
             }
         }
 
-        lines.push(line);
+        // Each cursor is labelled with its client's name, after the text of its row.
+        for remote in &others {
+            let line = buffer.line(remote.pos.line);
+            let col = screen_col(&line, remote.pos.col, buffer.indent.tabstop);
+            if let Some(y) = row_of(&screen_rows, remote.pos.line, col) {
+                let label = format!(" {} ", remote.name);
+                let (width, label_width) = (rect.width as usize, unicode::width(&label));
+                let x = (unicode::width(&region.lines[y]) + 1).min(width.saturating_sub(label_width));
+                region.lines[y] = overlay(&region.lines[y], x, &label);
+                region.style(y, x, label_width, Style { fg: Color::Ansi(0), bg: remote.color, bold: true, ..Style::PLAIN });
+            }
+        }
+
+        status.push_str(&buffer.name());
+        if buffer.is_modified() {
+            status.push_str(" [+]");
+        }
+        let pos = if live { buffer.pos } else { window.cursor };
+        let position = format!("{},{}", pos.line + 1, pos.col + 1);
+        let padding = (rect.width as usize).saturating_sub(unicode::width(&status) + position.len() + 1);
+        status.push_str(&" ".repeat(padding));
+        status.push_str(&position);
+    }
+    while region.lines.len() < rows {
+        region.style(region.lines.len(), 0, 1, theme.style(Group::NonText));
+        region.lines.push("~".to_string());
+    }
 
-        messages.push(ServerMessage::Update(pos, size, lines));
+    let mut regions = vec![region];
+    if rect.height > 0 {
+        let mut status_line = Region::new((rect.x, rect.y + rect.height - 1), (rect.width, 1), vec![status]);
+        // The focused window's status line stands out.
+        status_line.style(0, 0, rect.width as usize, theme.style(if current { Group::StatusLine } else { Group::StatusLineNC }));
+        regions.push(status_line);
     }
+    regions
+}
 
-    let status_pos = if size.1 > 0 {
-        (0, size.1 - 1 as u16)
-    } else {
-        (0, 0)
+/// The tab line: a label for each tab page naming the buffer in its current window.
+fn render_tab_line(state: &TermTextState, view: &View, width: u16) -> Region {
+    let mut line = String::new();
+    let mut selected = (0, 0);
+    for (i, layout) in view.tabs.iter().enumerate() {
+        let buffer = layout.current().buffer.and_then(|id| state.buffers.get(id));
+        let name = buffer.map_or_else(|| "[No Name]".to_string(), |buffer| buffer.name());
+        let modified = if buffer.is_some_and(|buffer| buffer.is_modified()) { " +" } else { "" };
+        let label = format!(" {} {name}{modified} ", i + 1);
+        if i == view.tab {
+            selected = (unicode::width(&line), unicode::width(&label));
+        }
+        line.push_str(&label);
+    }
+    let mut region = Region::new((0, 0), (width, 1), vec![line]);
+    region.style(0, 0, width as usize, state.theme.style(Group::TabLine));
+    region.style(0, selected.0, selected.1, state.theme.style(Group::TabLineSel));
+    region
+}
+
+/// What a client following another is shown in place of its windows: a copy
+/// of the leader's current window, scrolled to show its cursor in `rect`.
+fn leader_window(state: &TermTextState, view: &View, rect: Rect) -> Option<Window> {
+    let leader = view.following?.leader;
+    let mut window = state.views.get(&leader)?.layout().current().clone();
+    let buffer = state.buffers.get(window.buffer?)?;
+    // The acting client's cursor is the buffer's own.
+    if leader == state.client {
+        window.cursor = buffer.pos;
+    }
+    let pos = buffer.clamp(window.cursor);
+    let col = screen_col(&buffer.line(pos.line), pos.col, buffer.indent.tabstop);
+    scroll::follow_cursor(&mut window, buffer, pos.line, col, viewport(&state.options, rect));
+    Some(window)
+}
+
+/// Draws the screen of `client`.
+pub fn render(state: &TermTextState, client: ClientId) -> Vec<ServerMessage> {
+    let (view, session) = match (state.views.get(&client), state.session_of(client)) {
+        (Some(view), Some(session)) => (view, session),
+        _ => return vec![],
     };
+    let layout = view.layout();
+    let others = remotes(state, client);
+
+    let size = view.size;
+    let area = view.area();
+
+    let mut regions = Vec::new();
+    let mut cursor_pos = (0, 0);
+
+    if view.shows_tab_line() {
+        regions.push(render_tab_line(state, view, size.0));
+    }
+    if let Some(window) = leader_window(state, view, area) {
+        regions.extend(render_window(state, session, &window, area, true, false, &others));
+        cursor_pos = window_cursor(state, &window, area, false);
+    } else {
+        for (id, rect) in layout.rects(area) {
+            let window = match layout.get(id) {
+                Some(window) => window,
+                None => continue,
+            };
+            let current = id == layout.current;
+            let live = current && client == state.client;
+            regions.extend(render_window(state, session, window, rect, current, live, &others));
+            if current {
+                cursor_pos = window_cursor(state, window, rect, live);
+            }
+        }
+        for separator in layout.separators(area) {
+            let lines = vec!["|".to_string(); separator.height as usize];
+            let mut region = Region::new((separator.x, separator.y), (1, separator.height), lines);
+            for row in 0..separator.height as usize {
+                region.style(row, 0, 1, state.theme.style(Group::VertSplit));
+            }
+            regions.push(region);
+        }
+    }
+
+    let status_pos = (0, area.y + area.height);
     let status_size = (size.0, 1);
     let mut status_line = "tt: ".to_string();
-    status_line.push_str(&format!("{:?}", state.mode));
-    if let Some(command) = &state.command {
-        status_line.push_str(&format!(" {}", command));
+    status_line.push_str(&format!("{:?}", session.mode));
+    if let Some(leader) = view.following.and_then(|follow| state.views.get(&follow.leader)) {
+        status_line.push_str(&format!(" following {}", leader.name));
+    }
+    if let Some(command) = &session.command {
+        status_line.push(' ');
+        let before: String = command.text.chars().take(command.cursor).collect();
+        cursor_pos = ((unicode::width(&status_line) + unicode::width(&before)) as u16, status_pos.1);
+        status_line.push_str(&command.text);
+    }
+    if let Some(prompt) = session.search_prompt.as_ref().filter(|_| session.mode == BufferMode::Search) {
+        let prefix = if prompt.forward { '/' } else { '?' };
+        status_line.push_str(&format!(" {prefix}{}", prompt.pattern));
+        cursor_pos = (unicode::width(&status_line) as u16, status_pos.1);
+    }
+    if let Some(substitution) = &session.substitution {
+        status_line.push_str(&format!(" replace with {} (y/n/a/q/l)?", substitution.replacement));
     }
 
-    if let Some(buffer) = state.current_buffer() {
-        if let Some(path) = &buffer.path {
-            status_line.push_str(&format!(" {path:?}"));
-        }
+    match &session.message {
+        Some(message) if message.contains('\n') => {
+            // Longer output, such as `:ls`, goes in rows above the status line.
+            let lines: Vec<String> = message.lines().map(|line| line.to_string()).collect();
+            let row = status_pos.1.saturating_sub(lines.len() as u16);
+            regions.push(Region::new((0, row), (size.0, lines.len() as u16), lines));
+        },
+        Some(message) => status_line.push_str(&format!(" {message}")),
+        None => (),
     }
-    messages.push(ServerMessage::Update(status_pos, status_size, vec![status_line]));
+    regions.push(Region::new(status_pos, status_size, vec![status_line]));
 
-    if show_line_numbers {
-        cursor_pos.0 += 9;
+    if let Some(completion) = session.command.as_ref().and_then(|command| command.completion.as_ref()) {
+        if size.1 > 1 {
+            regions.push(render_completion(&state.theme, completion, (0, size.1 - 2), size.0));
+        }
     }
-    messages.push(ServerMessage::Cursor(cursor_pos));
 
+    let mut messages: Vec<ServerMessage> = regions.into_iter().map(|region| region.into_message(&state.theme, view.colors)).collect();
+    messages.push(ServerMessage::Cursor(cursor_pos));
     messages
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cmdline::CommandLine;
+    use crate::view::Follow;
+
+    #[test]
+    fn regions_split_lines_where_the_style_changes() {
+        let theme = Theme::default();
+        let (selection, status_line) = (theme.style(Group::Visual), theme.style(Group::StatusLine));
+        let mut region = Region::new((0, 0), (10, 2), vec!["ab日c".to_string(), "x".to_string()]);
+        region.style(0, 1, 3, selection);
+        region.style(1, 0, 4, status_line);
+        let lines = match region.into_message(&theme, ColorDepth::Ansi16) {
+            ServerMessage::Update(_, _, lines) => lines,
+            message => panic!("unexpected {message:?}"),
+        };
+        assert_eq!(lines[0], vec![Span::new("a", Style::PLAIN), Span::new("b日", selection), Span::new("c", Style::PLAIN)]);
+        assert_eq!(lines[1], vec![Span::new("x   ", status_line)]);
+    }
+
+    #[test]
+    fn scopes_take_the_most_specific_group() {
+        assert_eq!(scope_group("comment.line"), Some(Group::Comment));
+        assert_eq!(scope_group("entity.name.function.macro"), Some(Group::Function));
+        assert_eq!(scope_group("constant.character.escape.rust"), Some(Group::Special));
+        assert_eq!(scope_group("constant.numeric"), Some(Group::Constant));
+        assert_eq!(scope_group("commentary"), None);
+    }
+
+    #[test]
+    fn completions_wider_than_the_terminal_are_cut_off() {
+        let theme = Theme::default();
+        let mut command = CommandLine::default();
+        command.text = "b ".to_string();
+        command.cursor = 2;
+        let buffers = ["a_very_long_buffer_name".to_string(), "another_long_buffer_name".to_string()];
+        command.complete(&buffers, true);
+        command.complete(&buffers, true);
+        let lines = match render_completion(&theme, command.completion.as_ref().unwrap(), (0, 0), 10).into_message(&theme, ColorDepth::Ansi16) {
+            ServerMessage::Update(_, _, lines) => lines,
+            message => panic!("unexpected {message:?}"),
+        };
+        assert_eq!(lines[0], vec![Span::new("another_lo", theme.style(Group::WildMenu))]);
+    }
+
+    #[test]
+    fn each_client_is_drawn_at_its_own_size_and_mode() {
+        let mut state = TermTextState::default();
+        state.add_client(1);
+        state.add_client(2);
+        state.set_client(1);
+        state.view_mut().size = (20, 5);
+        state.session.mode = BufferMode::Insert;
+        state.set_client(2);
+        state.view_mut().size = (30, 4);
+
+        let status_line = |client| render(&state, client).into_iter().rev().find_map(|message| match message {
+            ServerMessage::Update(pos, size, lines) if size.1 == 1 => Some((pos, size, lines[0][0].text.clone())),
+            _ => None,
+        });
+        assert_eq!(status_line(1), Some(((0, 4), (20, 1), "tt: Insert".to_string())));
+        assert_eq!(status_line(2), Some(((0, 3), (30, 1), "tt: Normal".to_string())));
+    }
+
+    #[test]
+    fn other_clients_cursors_are_drawn_with_their_names() {
+        let mut state = TermTextState::default();
+        let id = state.buffers.add(Buffer::new(None, "hello\nworld\n"));
+        for client in [1, 2] {
+            state.add_client(client);
+            state.set_client(client);
+            state.switch_buffer(id);
+            state.view_mut().size = (30, 5);
+            state.view_mut().name = format!("user{client}");
+        }
+        state.buffers.get_mut(id).unwrap().pos = Cursor::new(1, 2);
+
+        let lines = match &render(&state, 1)[0] {
+            ServerMessage::Update(_, _, lines) => lines.clone(),
+            message => panic!("unexpected {message:?}"),
+        };
+        let text: String = lines[1].iter().map(|span| span.text.as_str()).collect();
+        assert_eq!(text, "     2 | world  user2 ");
+        let cursor = Style { fg: Color::Ansi(0), bg: CLIENT_COLORS[2], ..Style::PLAIN };
+        assert!(lines[1].contains(&Span::new("r", cursor)));
+        // A client sees the others' cursors, but not its own labelled.
+        let lines = match &render(&state, 2)[0] {
+            ServerMessage::Update(_, _, lines) => lines.clone(),
+            message => panic!("unexpected {message:?}"),
+        };
+        let text: String = lines.iter().flatten().map(|span| span.text.as_str()).collect();
+        assert!(text.contains("user1") && !text.contains("user2"));
+    }
+
+    #[test]
+    fn clients_with_the_same_name_are_told_apart() {
+        let mut state = TermTextState::default();
+        for client in [1, 2] {
+            state.add_client(client);
+            state.set_client(client);
+            state.set_name("alice".to_string());
+        }
+        assert_eq!(state.view().name, "alice#2");
+        assert_eq!(state.find_client("alice"), Some(1));
+        assert_eq!(state.find_client("alice#2"), Some(2));
+    }
+
+    #[test]
+    fn followers_see_the_leaders_window() {
+        let mut state = TermTextState::default();
+        for (client, text) in [(1, "aaa\nbbb\n"), (2, "zzz\n")] {
+            let id = state.buffers.add(Buffer::new(None, text));
+            state.add_client(client);
+            state.set_client(client);
+            state.switch_buffer(id);
+            state.view_mut().size = (30, 5);
+            state.view_mut().name = format!("user{client}");
+        }
+        state.view_mut().following = Some(Follow { leader: 1, takeover: false });
+        state.set_client(1);
+        state.current_buffer_mut().unwrap().pos = Cursor::new(1, 1);
+
+        let frame = render(&state, 2);
+        let text: String = frame.iter().flat_map(|message| match message {
+            ServerMessage::Update(_, _, lines) => lines.iter().flatten().map(|span| span.text.clone()).collect(),
+            _ => vec![],
+        }).collect();
+        assert!(text.contains("bbb") && !text.contains("zzz"));
+        assert!(text.contains("following user1"));
+        assert_eq!(frame.last(), Some(&ServerMessage::Cursor((GUTTER_WIDTH as u16 + 1, 1))));
+    }
+}
@@ -0,0 +1,169 @@
+use tt::message::{Position, ServerMessage, Size, Span, Style, StyledLine};
+use tt::unicode;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// One terminal cell: the grapheme cluster drawn there, which is empty for the
+/// right half of a wide character, and its style.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Cell {
+    text: String,
+    style: Style,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Cell { text: " ".to_string(), style: Style::PLAIN }
+    }
+}
+
+/// What a client's terminal shows. Each frame is drawn onto a new screen and
+/// compared with the last one, so only the cells that changed are sent.
+#[derive(Debug, Default)]
+pub struct Screen {
+    size: Size,
+    rows: Vec<Vec<Cell>>,
+    cursor: Option<Position>,
+}
+
+impl Screen {
+    fn new(size: Size) -> Self {
+        Screen {
+            size,
+            rows: vec![vec![Cell::default(); size.0 as usize]; size.1 as usize],
+            cursor: None,
+        }
+    }
+
+    /// Sets a cell, blanking what is left of any wide character it overwrites half of.
+    fn set(&mut self, x: usize, y: usize, cell: Cell) {
+        let row = match self.rows.get_mut(y) {
+            Some(row) if x < row.len() => row,
+            _ => return,
+        };
+        if row[x].text.is_empty() && !cell.text.is_empty() && x > 0 {
+            row[x - 1] = Cell::default();
+        }
+        let mut next = x + 1;
+        while next < row.len() && row[next].text.is_empty() {
+            row[next] = Cell::default();
+            next += 1;
+        }
+        row[x] = cell;
+    }
+
+    /// Draws an `Update` the way the client would.
+    fn draw(&mut self, pos: Position, size: Size, lines: &[StyledLine]) {
+        let (left, right) = (pos.0 as usize, pos.0 as usize + size.0 as usize);
+        for i in 0..size.1 as usize {
+            let y = pos.1 as usize + i;
+            let mut x = left;
+            'spans: for span in lines.get(i).into_iter().flatten() {
+                for grapheme in span.text.graphemes(true) {
+                    let width = unicode::grapheme_width(grapheme);
+                    if x + width > right {
+                        break 'spans;
+                    }
+                    self.set(x, y, Cell { text: grapheme.to_string(), style: span.style });
+                    for half in x + 1..x + width {
+                        self.set(half, y, Cell { text: String::new(), style: span.style });
+                    }
+                    x += width;
+                }
+            }
+            for x in x..right {
+                self.set(x, y, Cell::default());
+            }
+        }
+    }
+
+    /// The spans drawing cells `start..end` of row `y`.
+    fn line(&self, y: usize, start: usize, end: usize) -> StyledLine {
+        let mut spans: Vec<Span> = vec![];
+        for cell in &self.rows[y][start..end] {
+            match spans.last_mut() {
+                Some(span) if span.style == cell.style => span.text.push_str(&cell.text),
+                _ => spans.push(Span::new(cell.text.clone(), cell.style)),
+            }
+        }
+        spans
+    }
+
+    /// Takes a frame from `render::render` at the client's `size` and returns
+    /// the messages that bring the client from the previous frame to it: the
+    /// changed part of each row, and the cursor if it moved or anything was drawn.
+    pub fn update(&mut self, size: Size, frame: Vec<ServerMessage>) -> Vec<ServerMessage> {
+        let mut next = Screen::new(size);
+        for message in &frame {
+            match message {
+                ServerMessage::Update(pos, size, lines) => next.draw(*pos, *size, lines),
+                ServerMessage::Cursor(pos) => next.cursor = Some(*pos),
+                _ => (),
+            }
+        }
+
+        // After a resize the client's screen can't be trusted, so all of it is redrawn.
+        let redraw = self.size != size;
+        let mut patches = vec![];
+        for (y, row) in next.rows.iter().enumerate() {
+            let old = self.rows.get(y).filter(|_| !redraw);
+            let changed = |x: &usize| old.is_none_or(|old| old.get(*x) != row.get(*x));
+            let (mut start, mut end) = match ((0..row.len()).find(changed), (0..row.len()).rfind(changed)) {
+                (Some(start), Some(end)) => (start, end + 1),
+                _ => continue,
+            };
+            // Wide characters are sent whole.
+            while start > 0 && row[start].text.is_empty() {
+                start -= 1;
+            }
+            while end < row.len() && row[end].text.is_empty() {
+                end += 1;
+            }
+            patches.push(((start as u16, y as u16), next.line(y, start, end)));
+        }
+
+        let mut messages = vec![];
+        let moved = next.cursor != self.cursor;
+        if !patches.is_empty() {
+            messages.push(ServerMessage::Patch(patches));
+        }
+        if let Some(cursor) = next.cursor.filter(|_| moved || !messages.is_empty()) {
+            messages.push(ServerMessage::Cursor(cursor));
+        }
+        *self = next;
+        messages
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn frame(lines: &[&str], cursor: Position) -> Vec<ServerMessage> {
+        let lines = lines.iter().map(|line| vec![Span::new(*line, Style::PLAIN)]).collect();
+        vec![ServerMessage::Update((0, 0), (6, 2), lines), ServerMessage::Cursor(cursor)]
+    }
+
+    #[test]
+    fn sends_only_what_changed() {
+        let mut screen = Screen::default();
+        let messages = screen.update((6, 2), frame(&["abc", "def"], (0, 0)));
+        assert_eq!(messages.len(), 2);
+
+        let messages = screen.update((6, 2), frame(&["abc", "dXf"], (0, 0)));
+        assert_eq!(messages, vec![
+            ServerMessage::Patch(vec![((1, 1), vec![Span::new("X", Style::PLAIN)])]),
+            ServerMessage::Cursor((0, 0)),
+        ]);
+
+        assert_eq!(screen.update((6, 2), frame(&["abc", "dXf"], (2, 1))), vec![ServerMessage::Cursor((2, 1))]);
+        assert_eq!(screen.update((6, 2), frame(&["abc", "dXf"], (2, 1))), vec![]);
+    }
+
+    #[test]
+    fn wide_characters_are_sent_whole() {
+        let mut screen = Screen::default();
+        screen.update((6, 2), frame(&["a日b", ""], (0, 0)));
+        let messages = screen.update((6, 2), frame(&["a本b", ""], (0, 0)));
+        assert_eq!(messages[0], ServerMessage::Patch(vec![((1, 0), vec![Span::new("本", Style::PLAIN)])]));
+    }
+}
@@ -0,0 +1,201 @@
+use crate::buffer::{Buffer, Cursor};
+use crate::window::Window;
+use crate::wrap::{self, Wrap};
+
+/// A command that scrolls the view rather than moving the cursor, although the
+/// cursor is moved when it would leave the screen.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Scroll {
+    /// Ctrl-e
+    LinesDown,
+    /// Ctrl-y
+    LinesUp,
+    /// Ctrl-d
+    HalfPageDown,
+    /// Ctrl-u
+    HalfPageUp,
+    /// Ctrl-f
+    PageDown,
+    /// Ctrl-b
+    PageUp,
+    /// `zt`
+    CursorToTop,
+    /// `zz`
+    CursorToMiddle,
+    /// `zb`
+    CursorToBottom,
+}
+
+/// The size of the text area of a window and how close the cursor may come to its edges.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Viewport {
+    pub rows: usize,
+    pub cols: usize,
+    pub scrolloff: usize,
+    pub sidescrolloff: usize,
+    /// How lines are wrapped, when they are.
+    pub wrap: Option<Wrap>,
+}
+
+impl Viewport {
+    /// The lines kept visible above and below the cursor, which can't be more
+    /// than fit on either side of it.
+    fn margin(&self) -> usize {
+        self.scrolloff.min(self.rows.saturating_sub(1) / 2)
+    }
+
+    fn side_margin(&self) -> usize {
+        self.sidescrolloff.min(self.cols.saturating_sub(1) / 2)
+    }
+}
+
+fn set_line(buffer: &mut Buffer, line: usize) {
+    buffer.pos = buffer.clamp_normal(Cursor::new(line, buffer.want_col));
+}
+
+/// Scrolls `window` so that a cursor on `line`, at screen column `col`, is
+/// inside the viewport and away from its edges.
+pub fn follow_cursor(window: &mut Window, buffer: &Buffer, line: usize, col: usize, viewport: Viewport) {
+    let last = buffer.line_count() - 1;
+    let above = viewport.margin().min(line);
+    let below = viewport.margin().min(last - line);
+    let rows = viewport.rows.max(1);
+    window.top = window.top.min(last);
+    if line < window.top + above {
+        window.top = line - above;
+    } else if line + below >= window.top + rows {
+        window.top = line + below + 1 - rows;
+    }
+
+    if let Some(wrap) = &viewport.wrap {
+        // Wrapped lines can take several rows, so the lines down to the
+        // cursor's (and those kept below it) may not fit yet.
+        window.left = 0;
+        let bottom = line + below;
+        let mut used: usize = (window.top..=bottom).map(|line| wrap::line_rows(buffer, line, wrap).len()).sum();
+        while used > rows && window.top < line {
+            used -= wrap::line_rows(buffer, window.top, wrap).len();
+            window.top += 1;
+        }
+        return;
+    }
+
+    let side = viewport.side_margin();
+    let cols = viewport.cols.max(1);
+    if col < window.left + side {
+        window.left = col.saturating_sub(side);
+    } else if col + side >= window.left + cols {
+        window.left = col + side + 1 - cols;
+    }
+}
+
+pub fn scroll(window: &mut Window, buffer: &mut Buffer, scroll: Scroll, count: Option<usize>, viewport: Viewport) {
+    let last = buffer.line_count() - 1;
+    let rows = viewport.rows.max(1);
+    let margin = viewport.margin();
+    let line = buffer.pos.line;
+    match scroll {
+        Scroll::LinesDown | Scroll::LinesUp => {
+            let n = count.unwrap_or(1);
+            window.top = if scroll == Scroll::LinesDown {
+                (window.top + n).min(last)
+            } else {
+                window.top.saturating_sub(n)
+            };
+            let first = (window.top + margin).min(last);
+            let bottom = (window.top + rows - 1).saturating_sub(margin).max(window.top);
+            if line < first {
+                set_line(buffer, first);
+            } else if line > bottom {
+                set_line(buffer, bottom);
+            }
+        },
+        Scroll::HalfPageDown | Scroll::HalfPageUp => {
+            let n = count.unwrap_or(rows / 2).max(1);
+            if scroll == Scroll::HalfPageDown {
+                // Stops scrolling once the last line is at the bottom.
+                window.top = (window.top + n).min(last.saturating_sub(rows - 1)).max(window.top.min(last));
+                set_line(buffer, (line + n).min(last));
+            } else {
+                window.top = window.top.saturating_sub(n);
+                set_line(buffer, line.saturating_sub(n));
+            }
+        },
+        Scroll::PageDown => {
+            let page = rows.saturating_sub(2).max(1);
+            window.top = (window.top + page * count.unwrap_or(1)).min(last);
+            set_line(buffer, line.max((window.top + margin).min(last)));
+        },
+        Scroll::PageUp => {
+            let page = rows.saturating_sub(2).max(1);
+            window.top = window.top.saturating_sub(page * count.unwrap_or(1));
+            let bottom = (window.top + rows - 1).saturating_sub(margin).min(last);
+            set_line(buffer, line.min(bottom));
+        },
+        Scroll::CursorToTop | Scroll::CursorToMiddle | Scroll::CursorToBottom => {
+            if let Some(count) = count {
+                set_line(buffer, count.saturating_sub(1).min(last));
+            }
+            let line = buffer.pos.line;
+            window.top = match scroll {
+                Scroll::CursorToTop => line.saturating_sub(margin),
+                Scroll::CursorToMiddle => line.saturating_sub(rows / 2),
+                _ => (line + margin + 1).saturating_sub(rows),
+            };
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn buffer(lines: usize) -> Buffer {
+        let text: Vec<String> = (0..lines).map(|i| format!("line {i}")).collect();
+        Buffer::new(None, &text.join("\n"))
+    }
+
+    const VIEWPORT: Viewport = Viewport { rows: 10, cols: 20, scrolloff: 2, sidescrolloff: 0, wrap: None };
+
+    #[test]
+    fn follows_the_cursor_with_a_margin() {
+        let mut buffer = buffer(100);
+        let mut window = Window::default();
+        buffer.pos = Cursor::new(8, 0);
+        follow_cursor(&mut window, &buffer, buffer.pos.line, 0, VIEWPORT);
+        assert_eq!(window.top, 1);
+        buffer.pos = Cursor::new(99, 0);
+        follow_cursor(&mut window, &buffer, buffer.pos.line, 0, VIEWPORT);
+        assert_eq!(window.top, 90);
+        buffer.pos = Cursor::new(91, 0);
+        follow_cursor(&mut window, &buffer, buffer.pos.line, 25, VIEWPORT);
+        assert_eq!((window.top, window.left), (89, 6));
+    }
+
+    #[test]
+    fn follows_the_cursor_past_wrapped_lines() {
+        let mut buffer = Buffer::new(None, &["x".repeat(45), "a".to_string(), "b".to_string()].join("\n"));
+        let mut window = Window::default();
+        let wrap = Wrap { width: 10, showbreak: 0, linebreak: false };
+        let viewport = Viewport { rows: 5, scrolloff: 0, wrap: Some(wrap), ..VIEWPORT };
+        buffer.pos = Cursor::new(1, 0);
+        follow_cursor(&mut window, &buffer, buffer.pos.line, 0, viewport);
+        assert_eq!(window.top, 1);
+    }
+
+    #[test]
+    fn scrolling_moves_the_cursor_on_screen() {
+        let mut buffer = buffer(100);
+        let mut window = Window::default();
+        scroll(&mut window, &mut buffer, Scroll::LinesDown, Some(3), VIEWPORT);
+        assert_eq!((window.top, buffer.pos.line), (3, 5));
+        scroll(&mut window, &mut buffer, Scroll::HalfPageDown, None, VIEWPORT);
+        assert_eq!((window.top, buffer.pos.line), (8, 10));
+        scroll(&mut window, &mut buffer, Scroll::PageUp, None, VIEWPORT);
+        assert_eq!((window.top, buffer.pos.line), (0, 7));
+        scroll(&mut window, &mut buffer, Scroll::CursorToMiddle, Some(50), VIEWPORT);
+        assert_eq!((window.top, buffer.pos.line), (44, 49));
+        scroll(&mut window, &mut buffer, Scroll::CursorToBottom, None, VIEWPORT);
+        assert_eq!(window.top, 42);
+    }
+}
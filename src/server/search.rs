@@ -0,0 +1,137 @@
+use regex::{Regex, RegexBuilder};
+
+use crate::buffer::{Buffer, Cursor};
+use crate::options::Options;
+
+/// The last search, repeated by `n` and `N`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Search {
+    pub pattern: String,
+    pub forward: bool,
+}
+
+/// A search being typed at the `/` or `?` prompt.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Prompt {
+    pub forward: bool,
+    pub pattern: String,
+    /// Where the cursor was when the prompt opened, restored if it is cancelled.
+    pub origin: Cursor,
+    pub count: usize,
+}
+
+/// Whether `pattern` contains an uppercase letter, ignoring escapes like `\W`.
+fn has_uppercase(pattern: &str) -> bool {
+    let mut escaped = false;
+    for ch in pattern.chars() {
+        if escaped {
+            escaped = false;
+        } else if ch == '\\' {
+            escaped = true;
+        } else if ch.is_uppercase() {
+            return true;
+        }
+    }
+    false
+}
+
+/// Compiles a search pattern, ignoring case when `ignorecase` is set unless
+/// `smartcase` is set and the pattern has an uppercase letter.
+pub fn compile(pattern: &str, options: &Options) -> Result<Regex, regex::Error> {
+    let ignore_case = options.ignorecase && !(options.smartcase && has_uppercase(pattern));
+    build(pattern, ignore_case)
+}
+
+pub fn build(pattern: &str, ignore_case: bool) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(pattern).case_insensitive(ignore_case).build()
+}
+
+/// The `(start, end)` character columns of every match in `line`.
+pub fn line_matches(regex: &Regex, line: &str) -> Vec<(usize, usize)> {
+    let col = |byte: usize| line[..byte].chars().count();
+    regex.find_iter(line)
+        .map(|m| (col(m.start()), col(m.end())))
+        .collect()
+}
+
+/// The next match after (or before, when searching backward) `from`, wrapping
+/// around the ends of the buffer. Also returns whether the search wrapped.
+pub fn find(buffer: &Buffer, regex: &Regex, from: Cursor, forward: bool) -> Option<(Cursor, bool)> {
+    let line_count = buffer.line_count();
+    for i in 0..=line_count {
+        let (line, wrapped) = if forward {
+            ((from.line + i) % line_count, from.line + i >= line_count)
+        } else {
+            ((from.line + line_count - i % line_count) % line_count, i > from.line)
+        };
+        let starts = line_matches(regex, &buffer.line(line)).into_iter().map(|(start, _end)| start);
+        let col = match (forward, i) {
+            (true, 0) => starts.filter(|&start| start > from.col).min(),
+            (true, _) if i == line_count => starts.filter(|&start| start <= from.col).min(),
+            (true, _) => starts.min(),
+            (false, 0) => starts.filter(|&start| start < from.col).max(),
+            (false, _) if i == line_count => starts.filter(|&start| start >= from.col).max(),
+            (false, _) => starts.max(),
+        };
+        if let Some(col) = col {
+            return Some((Cursor::new(line, col), wrapped));
+        }
+    }
+    None
+}
+
+/// The pattern `*` and `#` search for: the first keyword at or after the cursor,
+/// matched as a whole word.
+pub fn word_pattern(buffer: &Buffer, at: Cursor) -> Option<String> {
+    let is_keyword = |ch: char| ch.is_alphanumeric() || ch == '_';
+    let line: Vec<char> = buffer.line(at.line).chars().collect();
+    let mut start = at.col.min(line.len());
+    if start < line.len() && is_keyword(line[start]) {
+        while start > 0 && is_keyword(line[start - 1]) {
+            start -= 1;
+        }
+    } else {
+        start += line[start..].iter().position(|&ch| is_keyword(ch))?;
+    }
+    let end = start + line[start..].iter().take_while(|&&ch| is_keyword(ch)).count();
+    let word: String = line[start..end].iter().collect();
+    Some(format!(r"\b{}\b", regex::escape(&word)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn options() -> Options {
+        Options { ignorecase: true, smartcase: true, ..Options::default() }
+    }
+
+    #[test]
+    fn smart_case() {
+        assert!(compile("foo", &options()).unwrap().is_match("FOO"));
+        assert!(!compile("Foo", &options()).unwrap().is_match("FOO"));
+        assert!(compile(r"\Wfoo", &options()).unwrap().is_match(" FOO"));
+    }
+
+    #[test]
+    fn find_wraps_around() {
+        let buffer = Buffer::new(None, "foo bar\nbaz foo\nqux");
+        let regex = compile("foo", &options()).unwrap();
+        assert_eq!(find(&buffer, &regex, Cursor::new(0, 0), true), Some((Cursor::new(1, 4), false)));
+        assert_eq!(find(&buffer, &regex, Cursor::new(1, 4), true), Some((Cursor::new(0, 0), true)));
+        assert_eq!(find(&buffer, &regex, Cursor::new(1, 4), false), Some((Cursor::new(0, 0), false)));
+        assert_eq!(find(&buffer, &regex, Cursor::new(0, 0), false), Some((Cursor::new(1, 4), true)));
+
+        let regex = compile("bar", &options()).unwrap();
+        assert_eq!(find(&buffer, &regex, Cursor::new(0, 4), true), Some((Cursor::new(0, 4), true)));
+        assert_eq!(find(&buffer, &regex, Cursor::new(0, 0), true).map(|(at, _)| at), Some(Cursor::new(0, 4)));
+    }
+
+    #[test]
+    fn word_under_cursor() {
+        let buffer = Buffer::new(None, "let (x.y) = foo_bar;");
+        assert_eq!(word_pattern(&buffer, Cursor::new(0, 14)).as_deref(), Some(r"\bfoo_bar\b"));
+        assert_eq!(word_pattern(&buffer, Cursor::new(0, 3)).as_deref(), Some(r"\bx\b"));
+        assert_eq!(word_pattern(&buffer, Cursor::new(0, 19)), None);
+    }
+}
@@ -0,0 +1,328 @@
+use regex::{Captures, Regex};
+
+use crate::buffer::{Buffer, Cursor};
+use crate::range::LineRange;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct Flags {
+    /// `g`: replace every match in a line, not just the first.
+    pub global: bool,
+    /// `i` forces ignoring case and `I` forces matching it.
+    pub ignore_case: Option<bool>,
+    /// `c`: ask before each replacement.
+    pub confirm: bool,
+    /// `n`: only count the matches.
+    pub count_only: bool,
+}
+
+/// The arguments of `:s/pattern/replacement/flags [count]`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Command {
+    pub pattern: String,
+    pub replacement: String,
+    pub flags: Flags,
+    pub count: Option<usize>,
+}
+
+/// Splits `text` at the first `delimiter` not escaped by a backslash.
+fn split_delimited(text: &str, delimiter: char) -> (&str, Option<&str>) {
+    let mut escaped = false;
+    for (i, ch) in text.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if ch == '\\' {
+            escaped = true;
+        } else if ch == delimiter {
+            return (&text[..i], Some(&text[i + ch.len_utf8()..]));
+        }
+    }
+    (text, None)
+}
+
+/// Parses what follows `:s`, e.g. `/foo/bar/g`. The delimiter is whatever
+/// character comes first, and may be escaped inside the pattern and replacement.
+pub fn parse(args: &str) -> Result<Command, String> {
+    let delimiter = match args.chars().next() {
+        Some(ch) if !(ch.is_alphanumeric() || ch.is_whitespace() || matches!(ch, '\\' | '"' | '|')) => ch,
+        Some(_) => return Err("Regular expressions can't be delimited by letters".to_string()),
+        None => return Err("Missing pattern".to_string()),
+    };
+    let (pattern, rest) = split_delimited(&args[delimiter.len_utf8()..], delimiter);
+    let (replacement, rest) = match rest {
+        Some(rest) => split_delimited(rest, delimiter),
+        None => ("", None),
+    };
+    let escaped_delimiter = format!("\\{delimiter}");
+    let pattern = pattern.replace(&escaped_delimiter, &regex::escape(&delimiter.to_string()));
+
+    let mut flags = Flags::default();
+    let mut rest = rest.unwrap_or("");
+    while let Some(ch) = rest.chars().next() {
+        match ch {
+            'g' => flags.global = true,
+            'i' => flags.ignore_case = Some(true),
+            'I' => flags.ignore_case = Some(false),
+            'c' => flags.confirm = true,
+            'n' => flags.count_only = true,
+            _ => break,
+        }
+        rest = &rest[1..];
+    }
+    let rest = rest.trim();
+    let count = match rest {
+        "" => None,
+        _ => match rest.parse::<usize>() {
+            Ok(count) if count > 0 => Some(count),
+            _ => return Err(format!("Trailing characters: {rest}")),
+        },
+    };
+
+    Ok(Command { pattern, replacement: replacement.to_string(), flags, count })
+}
+
+/// Expands a replacement for one match: `&` and `\0` are the whole match, `\1`
+/// to `\9` its groups, `\n` or `\r` a line break, and `\x` any other `x`.
+fn expand(replacement: &str, captures: &Captures) -> String {
+    let group = |n: usize| captures.get(n).map_or("", |m| m.as_str());
+    let mut text = String::new();
+    let mut chars = replacement.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '&' => text.push_str(group(0)),
+            '\\' => match chars.next() {
+                Some(digit @ '0'..='9') => text.push_str(group(digit.to_digit(10).unwrap() as usize)),
+                Some('n' | 'r') => text.push('\n'),
+                Some('t') => text.push('\t'),
+                Some(ch) => text.push(ch),
+                None => text.push('\\'),
+            },
+            ch => text.push(ch),
+        }
+    }
+    text
+}
+
+/// A match found by a substitution, along with the text that would replace it.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Match {
+    pub start: Cursor,
+    pub end: Cursor,
+    pub text: String,
+}
+
+/// A substitution in progress, stepping through the matches in its range.
+#[derive(Debug, Clone)]
+pub struct Substitution {
+    pub regex: Regex,
+    pub replacement: String,
+    pub flags: Flags,
+    /// Where to look for the next match.
+    next: Cursor,
+    /// The end of the previous match, where an empty match isn't allowed.
+    previous_end: Option<Cursor>,
+    last_line: usize,
+    pub count: usize,
+    lines: usize,
+    /// The line of the most recent match.
+    pub line: Option<usize>,
+    /// The match waiting for the user to confirm it.
+    pub pending: Option<Match>,
+}
+
+impl Substitution {
+    /// A substitution over `range`, or when a count was given, over that many
+    /// lines starting at the range's last line.
+    pub fn new(regex: Regex, command: &Command, range: LineRange, line_count: usize) -> Self {
+        let range = match command.count {
+            Some(count) => LineRange::new(range.end, (range.end + count - 1).min(line_count - 1)),
+            None => range,
+        };
+        Substitution {
+            regex,
+            replacement: command.replacement.clone(),
+            flags: command.flags,
+            next: Cursor::new(range.start, 0),
+            previous_end: None,
+            last_line: range.end,
+            count: 0,
+            lines: 0,
+            line: None,
+            pending: None,
+        }
+    }
+
+    fn find_next(&mut self, buffer: &Buffer) -> Option<Match> {
+        while self.next.line <= self.last_line && self.next.line < buffer.line_count() {
+            let line_no = self.next.line;
+            let line = buffer.line(line_no);
+            let byte = line.char_indices().nth(self.next.col).map_or(line.len(), |(i, _ch)| i);
+            if self.next.col <= line.chars().count() {
+                if let Some(captures) = self.regex.captures_at(&line, byte) {
+                    let m = captures.get(0).unwrap();
+                    let col = |byte: usize| line[..byte].chars().count();
+                    let start = Cursor::new(line_no, col(m.start()));
+                    let end = Cursor::new(line_no, col(m.end()));
+                    if start == end && Some(start) == self.previous_end {
+                        self.next.col = start.col + 1;
+                        continue;
+                    }
+                    let text = expand(&self.replacement, &captures);
+                    return Some(Match { start, end, text });
+                }
+            }
+            self.next = Cursor::new(line_no + 1, 0);
+        }
+        None
+    }
+
+    fn tally(&mut self, line: usize) {
+        if self.line != Some(line) {
+            self.lines += 1;
+        }
+        self.count += 1;
+    }
+
+    /// Moves past a match that now ends at `end`.
+    fn advance(&mut self, m: &Match, end: Cursor) {
+        self.previous_end = Some(end);
+        self.next = if !self.flags.global {
+            Cursor::new(end.line + 1, 0)
+        } else if m.start == m.end {
+            Cursor::new(end.line, end.col + 1)
+        } else {
+            end
+        };
+    }
+
+    fn replace(&mut self, buffer: &mut Buffer, m: &Match) {
+        if m.start != m.end {
+            buffer.remove(m.start, m.end);
+        }
+        let end = if m.text.is_empty() { m.start } else { buffer.insert(m.start, &m.text) };
+        self.last_line += end.line - m.start.line;
+        self.tally(m.start.line);
+        self.line = Some(end.line);
+        self.advance(m, end);
+    }
+
+    fn skip(&mut self, m: &Match) {
+        self.advance(m, m.end);
+    }
+
+    /// Replaces (or with `n`, counts) every remaining match.
+    pub fn run(&mut self, buffer: &mut Buffer) {
+        while let Some(m) = self.find_next(buffer) {
+            if self.flags.count_only {
+                self.tally(m.start.line);
+                self.line = Some(m.start.line);
+                self.skip(&m);
+            } else {
+                self.replace(buffer, &m);
+            }
+        }
+    }
+
+    /// Runs the substitution, or with `c`, finds the first match to confirm.
+    /// Returns true while a match is waiting for confirmation.
+    pub fn start(&mut self, buffer: &mut Buffer) -> bool {
+        if self.flags.confirm && !self.flags.count_only {
+            self.pending = self.find_next(buffer);
+            self.pending.is_some()
+        } else {
+            self.run(buffer);
+            false
+        }
+    }
+
+    /// Answers the prompt for the pending match: `y` replaces it, `n` skips it,
+    /// `a` replaces it and all the rest, `l` replaces it and stops, and `q`
+    /// stops. Returns true while another match is waiting for confirmation.
+    pub fn confirm(&mut self, buffer: &mut Buffer, answer: char) -> bool {
+        let m = match self.pending.take() {
+            Some(m) => m,
+            None => return false,
+        };
+        match answer {
+            'y' | 'l' | 'a' => self.replace(buffer, &m),
+            'n' => self.skip(&m),
+            'q' => return false,
+            _ => {
+                self.pending = Some(m);
+                return true;
+            },
+        }
+        match answer {
+            'l' => false,
+            'a' => {
+                self.run(buffer);
+                false
+            },
+            _ => {
+                self.pending = self.find_next(buffer);
+                self.pending.is_some()
+            },
+        }
+    }
+
+    /// A summary for the status line, such as `3 substitutions on 2 lines`.
+    pub fn report(&self) -> String {
+        let noun = |n: usize, one: &str, many: &str| format!("{n} {}", if n == 1 { one } else { many });
+        let (one, many) = if self.flags.count_only { ("match", "matches") } else { ("substitution", "substitutions") };
+        format!("{} on {}", noun(self.count, one, many), noun(self.lines, "line", "lines"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn substitute(text: &str, args: &str, range: LineRange) -> (String, String) {
+        let mut buffer = Buffer::new(None, text);
+        let command = parse(args).unwrap();
+        let regex = Regex::new(&command.pattern).unwrap();
+        let mut substitution = Substitution::new(regex, &command, range, buffer.line_count());
+        substitution.run(&mut buffer);
+        (buffer.text.to_string(), substitution.report())
+    }
+
+    #[test]
+    fn parses_commands() {
+        let command = parse("#a\\#b#c\\1#gc 3").unwrap();
+        assert_eq!(command.pattern, "a\\#b");
+        assert_eq!(command.replacement, "c\\1");
+        assert_eq!(command.flags, Flags { global: true, confirm: true, ..Flags::default() });
+        assert_eq!(command.count, Some(3));
+
+        assert_eq!(parse("/x").unwrap().replacement, "");
+        assert!(parse("axbxcx").is_err());
+        assert!(parse("/a/b/z").is_err());
+    }
+
+    #[test]
+    fn substitutes_with_groups_and_flags() {
+        let all = LineRange::new(0, 2);
+        assert_eq!(
+            substitute("foo foo\nbar\nfoo", "/foo/baz/", all),
+            ("baz foo\nbar\nbaz".to_string(), "2 substitutions on 2 lines".to_string()),
+        );
+        assert_eq!(substitute("foo foo\nbar\nfoo", "/foo/baz/g", LineRange::new(0, 0)).0, "baz baz\nbar\nfoo");
+        assert_eq!(substitute("a=1, b=2", r"/(\w)=(\d)/\2:\1/g", LineRange::new(0, 0)).0, "1:a, 2:b");
+        assert_eq!(substitute("a,b\nc", r"/,/\r/g", all).0, "a\nb\nc");
+        assert_eq!(substitute("abc", "/x*/-/g", LineRange::new(0, 0)).0, "-a-b-c-");
+        assert_eq!(substitute("a a\na", "/a/&&/gn", all), ("a a\na".to_string(), "3 matches on 2 lines".to_string()));
+    }
+
+    #[test]
+    fn confirms_each_match() {
+        let mut buffer = Buffer::new(None, "x x x x");
+        let command = parse("/x/y/gc").unwrap();
+        let regex = Regex::new(&command.pattern).unwrap();
+        let mut substitution = Substitution::new(regex, &command, LineRange::new(0, 0), 1);
+        assert!(substitution.start(&mut buffer));
+        assert!(substitution.confirm(&mut buffer, 'y'));
+        assert!(substitution.confirm(&mut buffer, 'n'));
+        assert!(!substitution.confirm(&mut buffer, 'a'));
+        assert_eq!(buffer.text.to_string(), "y x y y");
+        assert_eq!(substitution.count, 3);
+    }
+}
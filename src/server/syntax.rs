@@ -0,0 +1,447 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use log::*;
+use regex::Regex;
+use ropey::Rope;
+use serde::Deserialize;
+
+/// The grammars built into the server. Files in the user's grammar directory
+/// with the same `name` replace them.
+const BUILTIN: &[(&str, &str)] = &[
+    ("rust.json", include_str!("../../syntax/rust.json")),
+    ("toml.json", include_str!("../../syntax/toml.json")),
+    ("json.json", include_str!("../../syntax/json.json")),
+    ("markdown.json", include_str!("../../syntax/markdown.json")),
+    ("shell.json", include_str!("../../syntax/shell.json")),
+    ("python.json", include_str!("../../syntax/python.json")),
+];
+
+/// A rule as written in a grammar file: a subset of the TextMate format.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct RuleDef {
+    #[serde(rename = "match")]
+    regex: Option<String>,
+    begin: Option<String>,
+    end: Option<String>,
+    name: Option<String>,
+    captures: HashMap<String, CaptureDef>,
+    patterns: Vec<RuleDef>,
+    include: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CaptureDef {
+    name: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GrammarDef {
+    name: String,
+    #[serde(default)]
+    file_types: Vec<String>,
+    first_line_match: Option<String>,
+    patterns: Vec<RuleDef>,
+    #[serde(default)]
+    repository: HashMap<String, RuleDef>,
+}
+
+type RuleId = usize;
+type ScopeId = usize;
+
+enum Kind {
+    /// A pattern matched within one line.
+    Match(Regex),
+    /// A region from `begin` to `end`, which can span lines.
+    Region(Regex, Regex),
+    /// Only a list of patterns, as repository entries often are.
+    Group,
+    /// A reference to a repository entry, or `$self`.
+    Include(String),
+}
+
+struct Rule {
+    kind: Kind,
+    scope: Option<ScopeId>,
+    /// The scopes of a match's capture groups.
+    captures: Vec<(usize, ScopeId)>,
+    /// The patterns a region's contents are matched against, with includes and
+    /// groups expanded once the whole grammar is compiled.
+    patterns: Vec<RuleId>,
+}
+
+/// A compiled grammar.
+pub struct Grammar {
+    pub name: String,
+    file_types: Vec<String>,
+    first_line_match: Option<Regex>,
+    scopes: Vec<String>,
+    rules: Vec<Rule>,
+    patterns: Vec<RuleId>,
+}
+
+/// A highlighted run of a line, in chars.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Token {
+    pub start: usize,
+    pub end: usize,
+    scope: ScopeId,
+}
+
+impl Grammar {
+    pub fn parse(json: &str) -> anyhow::Result<Self> {
+        let def: GrammarDef = serde_json::from_str(json)?;
+        let mut grammar = Grammar {
+            name: def.name,
+            file_types: def.file_types,
+            first_line_match: def.first_line_match.as_deref().map(Regex::new).transpose()?,
+            scopes: vec![],
+            rules: vec![],
+            patterns: vec![],
+        };
+        let mut repository = HashMap::new();
+        for (name, rule) in &def.repository {
+            let id = grammar.compile(rule)?;
+            repository.insert(format!("#{name}"), id);
+        }
+        let patterns = def.patterns.iter().map(|rule| grammar.compile(rule)).collect::<anyhow::Result<Vec<_>>>()?;
+
+        let top = grammar.expand(&patterns, &repository, &patterns, &mut vec![]);
+        for id in 0..grammar.rules.len() {
+            let expanded = grammar.expand(&grammar.rules[id].patterns, &repository, &patterns, &mut vec![]);
+            grammar.rules[id].patterns = expanded;
+        }
+        grammar.patterns = top;
+        Ok(grammar)
+    }
+
+    fn scope(&mut self, name: &Option<String>) -> Option<ScopeId> {
+        let name = name.as_ref()?;
+        match self.scopes.iter().position(|scope| scope == name) {
+            Some(id) => Some(id),
+            None => {
+                self.scopes.push(name.clone());
+                Some(self.scopes.len() - 1)
+            },
+        }
+    }
+
+    fn compile(&mut self, def: &RuleDef) -> anyhow::Result<RuleId> {
+        let kind = match (&def.regex, &def.begin, &def.end, &def.include) {
+            (Some(regex), ..) => Kind::Match(Regex::new(regex)?),
+            (_, Some(begin), Some(end), _) => Kind::Region(Regex::new(begin)?, Regex::new(end)?),
+            (.., Some(include)) => Kind::Include(include.clone()),
+            _ => Kind::Group,
+        };
+        let scope = self.scope(&def.name);
+        let mut captures = vec![];
+        for (group, capture) in &def.captures {
+            let scope = self.scope(&Some(capture.name.clone())).unwrap();
+            captures.push((group.parse()?, scope));
+        }
+        captures.sort();
+        let patterns = def.patterns.iter().map(|rule| self.compile(rule)).collect::<anyhow::Result<Vec<_>>>()?;
+        self.rules.push(Rule { kind, scope, captures, patterns });
+        Ok(self.rules.len() - 1)
+    }
+
+    /// Replaces includes and groups in `patterns` with the rules they stand for.
+    fn expand(&self, patterns: &[RuleId], repository: &HashMap<String, RuleId>, top: &[RuleId], seen: &mut Vec<RuleId>) -> Vec<RuleId> {
+        let mut expanded = vec![];
+        for &id in patterns {
+            let rule = &self.rules[id];
+            let inner = match &rule.kind {
+                Kind::Match(_) | Kind::Region(..) => {
+                    expanded.push(id);
+                    continue;
+                },
+                Kind::Group => rule.patterns.clone(),
+                Kind::Include(name) if name == "$self" => top.to_vec(),
+                Kind::Include(name) => match repository.get(name) {
+                    Some(&id) => vec![id],
+                    None => {
+                        warn!("Grammar {} includes unknown {name}", self.name);
+                        continue;
+                    },
+                },
+            };
+            // Includes can be recursive; each is only followed once.
+            if seen.contains(&id) {
+                continue;
+            }
+            seen.push(id);
+            expanded.extend(self.expand(&inner, repository, top, seen));
+            seen.pop();
+        }
+        expanded
+    }
+
+    pub fn scope_name(&self, id: ScopeId) -> &str {
+        &self.scopes[id]
+    }
+
+    /// Highlights one line, starting inside the regions open on `stack` and
+    /// leaving on it the ones still open at the end of the line.
+    fn tokenize(&self, line: &str, stack: &mut Vec<RuleId>) -> Vec<Token> {
+        let mut tokens: Vec<(usize, usize, ScopeId)> = vec![];
+        let mut push = |start: usize, end: usize, scope: Option<ScopeId>| {
+            let scope = match scope {
+                Some(scope) if start < end => scope,
+                _ => return,
+            };
+            match tokens.last_mut() {
+                Some(last) if last.1 == start && last.2 == scope => last.1 = end,
+                _ => tokens.push((start, end, scope)),
+            }
+        };
+
+        let mut pos = 0;
+        // Empty matches that don't move past anything could otherwise repeat forever.
+        for _ in 0..line.len() * 2 + 16 {
+            let region = stack.last().map(|&id| &self.rules[id]);
+            let patterns = region.map_or(&self.patterns, |region| &region.patterns);
+            let outer = stack.iter().rev().find_map(|&id| self.rules[id].scope);
+
+            // The earliest match wins, and the end of the region wins ties.
+            let mut best: Option<(usize, usize, Option<RuleId>)> = None;
+            if let Some(Kind::Region(_, end)) = region.map(|region| &region.kind) {
+                best = end.find_at(line, pos).map(|m| (m.start(), m.end(), None));
+            }
+            for &id in patterns {
+                let regex = match &self.rules[id].kind {
+                    Kind::Match(regex) | Kind::Region(regex, _) => regex,
+                    _ => continue,
+                };
+                if let Some(m) = regex.find_at(line, pos) {
+                    if best.is_none_or(|(start, ..)| m.start() < start) {
+                        best = Some((m.start(), m.end(), Some(id)));
+                    }
+                }
+            }
+
+            let (start, end, id) = match best {
+                Some(best) => best,
+                None => break,
+            };
+            push(pos, start, outer);
+            match id.map(|id| (id, &self.rules[id])) {
+                None => {
+                    push(start, end, outer);
+                    stack.pop();
+                },
+                Some((_, rule @ Rule { kind: Kind::Match(regex), .. })) => {
+                    if start == end {
+                        // Nothing to highlight; step over a character instead.
+                        let next = line[start..].chars().next().map_or(start, |ch| start + ch.len_utf8());
+                        push(start, next, outer);
+                        pos = next;
+                        if next == start {
+                            break;
+                        }
+                        continue;
+                    }
+                    let scope = rule.scope.or(outer);
+                    let mut at = start;
+                    if let Some(groups) = regex.captures_at(line, start).filter(|_| !rule.captures.is_empty()) {
+                        let mut captured: Vec<_> = rule.captures.iter()
+                            .filter_map(|&(group, scope)| groups.get(group).map(|m| (m.start(), m.end(), scope)))
+                            .collect();
+                        captured.sort();
+                        for (from, to, capture) in captured {
+                            if from >= at {
+                                push(at, from, scope);
+                                push(from, to, Some(capture));
+                                at = to;
+                            }
+                        }
+                    }
+                    push(at, end, scope);
+                },
+                Some((id, rule)) => {
+                    push(start, end, rule.scope.or(outer));
+                    stack.push(id);
+                },
+            }
+            pos = end;
+        }
+        if pos < line.len() {
+            push(pos, line.len(), stack.iter().rev().find_map(|&id| self.rules[id].scope));
+        }
+
+        // Byte offsets to chars.
+        let mut chars = vec![0; line.len() + 1];
+        for (i, (byte, _)) in line.char_indices().enumerate() {
+            chars[byte] = i;
+        }
+        chars[line.len()] = line.chars().count();
+        tokens.into_iter().map(|(start, end, scope)| Token { start: chars[start], end: chars[end], scope }).collect()
+    }
+}
+
+/// Every grammar the server knows.
+#[derive(Default)]
+pub struct Grammars {
+    grammars: Vec<Arc<Grammar>>,
+}
+
+impl Grammars {
+    /// The built-in grammars, and any in the `tt/syntax` configuration directory.
+    pub fn load() -> Self {
+        let mut grammars = Grammars::default();
+        for (file, json) in BUILTIN {
+            grammars.add(file, json);
+        }
+        for path in crate::config_files("syntax") {
+            match std::fs::read_to_string(&path) {
+                Ok(json) => grammars.add(&path.to_string_lossy(), &json),
+                Err(e) => warn!("Could not read grammar {path:?}: {e}"),
+            }
+        }
+        grammars
+    }
+
+    fn add(&mut self, file: &str, json: &str) {
+        match Grammar::parse(json) {
+            Ok(grammar) => {
+                info!("Loaded grammar {} from {file}", grammar.name);
+                self.grammars.retain(|other| other.name != grammar.name);
+                self.grammars.push(Arc::new(grammar));
+            },
+            Err(e) => warn!("Could not load grammar {file}: {e}"),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<Grammar>> {
+        self.grammars.iter().find(|grammar| grammar.name == name).cloned()
+    }
+
+    /// The grammar for a file, chosen by its extension or name, or else by a
+    /// first line such as a shebang.
+    pub fn detect(&self, path: Option<&Path>, first_line: &str) -> Option<Arc<Grammar>> {
+        let name = path.and_then(|path| path.file_name()).map(|name| name.to_string_lossy().into_owned());
+        let extension = path.and_then(|path| path.extension()).map(|ext| ext.to_string_lossy().into_owned());
+        self.grammars.iter()
+            .find(|grammar| grammar.file_types.iter().any(|file_type| Some(file_type) == extension.as_ref() || Some(file_type) == name.as_ref()))
+            .or_else(|| self.grammars.iter().find(|grammar| grammar.first_line_match.as_ref().is_some_and(|regex| regex.is_match(first_line))))
+            .cloned()
+    }
+}
+
+/// The highlighting of one buffer, kept line by line so that an edit only
+/// needs the lines from its own onward highlighted again.
+#[derive(Default)]
+pub struct Highlights {
+    grammar: Option<Arc<Grammar>>,
+    /// The regions open at the start of each highlighted line, and of the line after.
+    states: Vec<Vec<RuleId>>,
+    lines: Vec<Vec<Token>>,
+}
+
+impl Highlights {
+    pub fn grammar(&self) -> Option<&Grammar> {
+        self.grammar.as_deref()
+    }
+
+    pub fn set_grammar(&mut self, grammar: Option<Arc<Grammar>>) {
+        self.grammar = grammar;
+        self.invalidate(0);
+    }
+
+    /// Forgets the highlighting from `line` on, after it was edited.
+    pub fn invalidate(&mut self, line: usize) {
+        self.lines.truncate(line);
+        self.states.truncate(line + 1);
+    }
+
+    /// Highlights `text` up to line `last`, continuing from the last line
+    /// still highlighted.
+    pub fn update(&mut self, text: &Rope, last: usize) {
+        let grammar = match &self.grammar {
+            Some(grammar) => grammar,
+            None => return,
+        };
+        if self.states.is_empty() {
+            self.states.push(vec![]);
+        }
+        let last = last.min(text.len_lines().saturating_sub(1));
+        while self.lines.len() <= last {
+            let line = text.line(self.lines.len()).to_string();
+            let mut stack = self.states[self.lines.len()].clone();
+            self.lines.push(grammar.tokenize(line.trim_end_matches(['\n', '\r']), &mut stack));
+            self.states.push(stack);
+        }
+    }
+
+    /// The highlighted runs of `line`, with their scopes, if it has been highlighted.
+    pub fn tokens(&self, line: usize) -> impl Iterator<Item = (usize, usize, &str)> {
+        let grammar = self.grammar.as_deref();
+        self.lines.get(line).into_iter().flatten().filter_map(move |token| {
+            grammar.map(|grammar| (token.start, token.end, grammar.scope_name(token.scope)))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rust() -> Arc<Grammar> {
+        Grammars::load().get("rust").unwrap()
+    }
+
+    fn scopes(highlights: &Highlights, line: usize) -> Vec<(usize, usize, &str)> {
+        highlights.tokens(line).collect()
+    }
+
+    #[test]
+    fn builtin_grammars_load_and_detect() {
+        let grammars = Grammars::load();
+        for name in ["rust", "toml", "json", "markdown", "shell", "python"] {
+            assert!(grammars.get(name).is_some(), "{name}");
+        }
+        let detect = |path: &str, line: &str| grammars.detect(Some(Path::new(path)), line).map(|grammar| grammar.name.clone());
+        assert_eq!(detect("src/main.rs", ""), Some("rust".to_string()));
+        assert_eq!(detect("Cargo.lock", ""), Some("toml".to_string()));
+        assert_eq!(detect("build", "#!/usr/bin/env python3"), Some("python".to_string()));
+        assert_eq!(detect("run", "#!/bin/bash"), Some("shell".to_string()));
+        assert_eq!(detect("notes.txt", "hello"), None);
+    }
+
+    #[test]
+    fn highlights_matches_captures_and_regions() {
+        let mut highlights = Highlights::default();
+        highlights.set_grammar(Some(rust()));
+        let text = Rope::from_str("fn main() { // hi\n/* a\nb */ let x = \"\\n\";");
+        highlights.update(&text, 2);
+        assert_eq!(scopes(&highlights, 0), vec![
+            (0, 2, "keyword.other.fn"),
+            (3, 7, "entity.name.function"),
+            (12, 17, "comment.line"),
+        ]);
+        assert_eq!(scopes(&highlights, 1), vec![(0, 4, "comment.block")]);
+        assert_eq!(scopes(&highlights, 2), vec![
+            (0, 4, "comment.block"),
+            (5, 8, "keyword.other"),
+            (13, 14, "string.quoted.double"),
+            (14, 16, "constant.character.escape"),
+            (16, 17, "string.quoted.double"),
+        ]);
+    }
+
+    #[test]
+    fn edits_rehighlight_from_their_line() {
+        let mut highlights = Highlights::default();
+        highlights.set_grammar(Some(rust()));
+        let mut text = Rope::from_str("let a;\nlet b;\nlet c;");
+        highlights.update(&text, 2);
+        text.insert(7, "/*");
+        highlights.invalidate(1);
+        assert!(scopes(&highlights, 1).is_empty());
+        highlights.update(&text, 2);
+        assert_eq!(scopes(&highlights, 2), vec![(0, 6, "comment.block")]);
+        assert_eq!(scopes(&highlights, 0), vec![(0, 3, "keyword.other")]);
+    }
+}
@@ -0,0 +1,237 @@
+use crate::buffer::{Buffer, Cursor};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ObjectKind {
+    Word,
+    BigWord,
+    Paragraph,
+    Quote(char),
+    Bracket(char, char),
+}
+
+/// A text object such as `iw` or `a(`. `inner` distinguishes `i` from `a`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct TextObject {
+    pub inner: bool,
+    pub kind: ObjectKind,
+}
+
+impl TextObject {
+    pub fn from_char(inner: bool, ch: char) -> Option<TextObject> {
+        let kind = match ch {
+            'w' => ObjectKind::Word,
+            'W' => ObjectKind::BigWord,
+            'p' => ObjectKind::Paragraph,
+            '"' | '\'' | '`' => ObjectKind::Quote(ch),
+            '(' | ')' | 'b' => ObjectKind::Bracket('(', ')'),
+            '[' | ']' => ObjectKind::Bracket('[', ']'),
+            '{' | '}' | 'B' => ObjectKind::Bracket('{', '}'),
+            '<' | '>' => ObjectKind::Bracket('<', '>'),
+            _ => return None,
+        };
+        Some(TextObject { inner, kind })
+    }
+}
+
+/// The span of text a text object covers: `start` inclusive, `end` exclusive.
+/// For linewise spans only the lines matter, and `end.line` is inclusive.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Span {
+    pub start: Cursor,
+    pub end: Cursor,
+    pub linewise: bool,
+}
+
+fn class(ch: char, big: bool) -> u8 {
+    if ch.is_whitespace() {
+        0
+    } else if big || ch.is_alphanumeric() || ch == '_' {
+        1
+    } else {
+        2
+    }
+}
+
+fn word(buffer: &Buffer, at: Cursor, inner: bool, big: bool) -> Option<Span> {
+    let line: Vec<char> = buffer.line(at.line).chars().collect();
+    if line.is_empty() {
+        return None;
+    }
+    let col = at.col.min(line.len() - 1);
+    let run = |start: usize| {
+        let class_at = class(line[start], big);
+        let mut begin = start;
+        while begin > 0 && class(line[begin - 1], big) == class_at {
+            begin -= 1;
+        }
+        let mut end = start;
+        while end < line.len() && class(line[end], big) == class_at {
+            end += 1;
+        }
+        (begin, end)
+    };
+
+    let (mut begin, mut end) = run(col);
+    if !inner {
+        if class(line[col], big) == 0 {
+            // On whitespace, `aw` covers the whitespace and the following word.
+            if end < line.len() {
+                end = run(end).1;
+            }
+        } else if end < line.len() && class(line[end], big) == 0 {
+            end = run(end).1;
+        } else if begin > 0 && class(line[begin - 1], big) == 0 {
+            begin = run(begin - 1).0;
+        }
+    }
+    Some(Span {
+        start: Cursor::new(at.line, begin),
+        end: Cursor::new(at.line, end),
+        linewise: false,
+    })
+}
+
+fn paragraph(buffer: &Buffer, at: Cursor, inner: bool) -> Option<Span> {
+    let last = buffer.line_count() - 1;
+    let blank = |line: usize| buffer.line_len(line) == 0;
+    let on_blank = blank(at.line);
+
+    let mut first = at.line;
+    while first > 0 && blank(first - 1) == on_blank {
+        first -= 1;
+    }
+    let mut end = at.line;
+    while end < last && blank(end + 1) == on_blank {
+        end += 1;
+    }
+    if !inner {
+        while end < last && blank(end + 1) != on_blank {
+            end += 1;
+        }
+    }
+    Some(Span {
+        start: Cursor::new(first, 0),
+        end: Cursor::new(end, 0),
+        linewise: true,
+    })
+}
+
+fn quote(buffer: &Buffer, at: Cursor, inner: bool, quote: char) -> Option<Span> {
+    let line: Vec<char> = buffer.line(at.line).chars().collect();
+    let quotes: Vec<usize> = line.iter()
+        .enumerate()
+        .filter(|(i, &ch)| ch == quote && (*i == 0 || line[i - 1] != '\\'))
+        .map(|(i, _)| i)
+        .collect();
+
+    let (open, close) = quotes
+        .chunks(2)
+        .filter(|pair| pair.len() == 2)
+        .map(|pair| (pair[0], pair[1]))
+        .find(|&(_, close)| at.col <= close)?;
+
+    let (start, end) = if inner { (open + 1, close) } else { (open, close + 1) };
+    Some(Span {
+        start: Cursor::new(at.line, start),
+        end: Cursor::new(at.line, end),
+        linewise: false,
+    })
+}
+
+fn bracket(buffer: &Buffer, at: Cursor, inner: bool, open: char, close: char) -> Option<Span> {
+    let text = &buffer.text;
+    if text.len_chars() == 0 {
+        return None;
+    }
+    let idx = buffer.char_idx(at).min(text.len_chars() - 1);
+
+    let mut depth = 0;
+    let mut start = None;
+    let mut i = idx + 1;
+    while i > 0 {
+        i -= 1;
+        let ch = text.char(i);
+        if ch == close && i != idx {
+            depth += 1;
+        } else if ch == open {
+            if depth == 0 {
+                start = Some(i);
+                break;
+            }
+            depth -= 1;
+        }
+    }
+    let start = start?;
+
+    let mut depth = 0;
+    let mut end = None;
+    for (i, ch) in text.chars_at(start + 1).enumerate() {
+        if ch == open {
+            depth += 1;
+        } else if ch == close {
+            if depth == 0 {
+                end = Some(start + 1 + i);
+                break;
+            }
+            depth -= 1;
+        }
+    }
+    let end = end?;
+
+    let (start, end) = if inner { (start + 1, end) } else { (start, end + 1) };
+    Some(Span {
+        start: buffer.cursor_at(start),
+        end: buffer.cursor_at(end),
+        linewise: false,
+    })
+}
+
+/// Finds the span `object` covers around `at`, if there is one.
+pub fn select(buffer: &Buffer, at: Cursor, object: TextObject) -> Option<Span> {
+    match object.kind {
+        ObjectKind::Word => word(buffer, at, object.inner, false),
+        ObjectKind::BigWord => word(buffer, at, object.inner, true),
+        ObjectKind::Paragraph => paragraph(buffer, at, object.inner),
+        ObjectKind::Quote(ch) => quote(buffer, at, object.inner, ch),
+        ObjectKind::Bracket(open, close) => bracket(buffer, at, object.inner, open, close),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn text_of(buffer: &Buffer, span: Span) -> String {
+        let start = buffer.char_idx(span.start);
+        let end = buffer.char_idx(span.end);
+        buffer.text.slice(start..end).to_string()
+    }
+
+    fn select_str(data: &str, col: usize, inner: bool, ch: char) -> Option<String> {
+        let buffer = Buffer::new(None, data);
+        let object = TextObject::from_char(inner, ch).unwrap();
+        select(&buffer, Cursor::new(0, col), object).map(|span| text_of(&buffer, span))
+    }
+
+    #[test]
+    fn selects_words_quotes_and_brackets() {
+        assert_eq!(select_str("foo bar baz", 5, true, 'w').unwrap(), "bar");
+        assert_eq!(select_str("foo bar baz", 5, false, 'w').unwrap(), "bar ");
+        assert_eq!(select_str("foo bar", 5, false, 'w').unwrap(), " bar");
+        assert_eq!(select_str(r#"x = "a b" + "c""#, 6, true, '"').unwrap(), "a b");
+        assert_eq!(select_str(r#"x = "a b" + "c""#, 0, false, '"').unwrap(), "\"a b\"");
+        assert_eq!(select_str("f(a, (b), c)", 3, true, '(').unwrap(), "a, (b), c");
+        assert_eq!(select_str("f(a, (b), c)", 6, false, 'b').unwrap(), "(b)");
+        assert_eq!(select_str("f(a, (b), c)", 1, false, ')').unwrap(), "(a, (b), c)");
+        assert_eq!(select_str("no brackets", 3, true, '['), None);
+    }
+
+    #[test]
+    fn selects_paragraphs() {
+        let buffer = Buffer::new(None, "a\nb\n\n\nc");
+        let inner = select(&buffer, Cursor::new(1, 0), TextObject::from_char(true, 'p').unwrap()).unwrap();
+        assert_eq!((inner.start.line, inner.end.line), (0, 1));
+        let around = select(&buffer, Cursor::new(1, 0), TextObject::from_char(false, 'p').unwrap()).unwrap();
+        assert_eq!((around.start.line, around.end.line), (0, 3));
+    }
+}
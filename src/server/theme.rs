@@ -0,0 +1,284 @@
+use std::collections::HashMap;
+
+use log::*;
+use serde::Deserialize;
+use tt::message::{Color, ColorDepth, Style};
+
+/// The themes built into the server. Files in the user's theme directory with
+/// the same `name` replace them, so one named `default` is used from startup.
+const BUILTIN: &[(&str, &str)] = &[
+    ("default.json", include_str!("../../themes/default.json")),
+    ("gruvbox.json", include_str!("../../themes/gruvbox.json")),
+];
+
+/// The things a theme gives a style to, named as in Vim.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Group {
+    /// Text with no other group, and the colors other groups default to.
+    Normal,
+    LineNr,
+    /// The `~` lines past the end of a buffer, and `showbreak`.
+    NonText,
+    StatusLine,
+    /// The status lines of windows other than the current one.
+    StatusLineNC,
+    TabLine,
+    TabLineSel,
+    VertSplit,
+    Search,
+    Visual,
+    /// The selected command line completion.
+    WildMenu,
+    Comment,
+    Constant,
+    String,
+    /// Escapes, and other characters that stand for something else.
+    Special,
+    Identifier,
+    Function,
+    Keyword,
+    Type,
+    PreProc,
+    Title,
+    Underlined,
+    Bold,
+    Italic,
+}
+
+impl Group {
+    pub const ALL: &[Group] = &[
+        Group::Normal, Group::LineNr, Group::NonText, Group::StatusLine, Group::StatusLineNC,
+        Group::TabLine, Group::TabLineSel, Group::VertSplit, Group::Search, Group::Visual,
+        Group::WildMenu, Group::Comment, Group::Constant, Group::String, Group::Special,
+        Group::Identifier, Group::Function, Group::Keyword, Group::Type, Group::PreProc,
+        Group::Title, Group::Underlined, Group::Bold, Group::Italic,
+    ];
+
+    pub fn name(self) -> String {
+        format!("{self:?}")
+    }
+
+    fn from_name(name: &str) -> Option<Group> {
+        Group::ALL.iter().copied().find(|group| group.name() == name)
+    }
+}
+
+/// A color as written in a theme file: `"#rrggbb"`, one of the 16 terminal
+/// colors by name, `"default"`, or a number from the 256 color palette.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ColorDef {
+    Index(u8),
+    Name(String),
+}
+
+const COLOR_NAMES: &[&str] = &[
+    "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+    "brightblack", "brightred", "brightgreen", "brightyellow", "brightblue", "brightmagenta", "brightcyan", "brightwhite",
+];
+
+impl ColorDef {
+    fn color(&self) -> Result<Color, String> {
+        match self {
+            ColorDef::Index(index) if *index < 16 => Ok(Color::Ansi(*index)),
+            ColorDef::Index(index) => Ok(Color::Indexed(*index)),
+            ColorDef::Name(name) if name == "default" || name == "none" => Ok(Color::Default),
+            ColorDef::Name(name) => {
+                if let Some(hex) = name.strip_prefix('#').filter(|hex| hex.len() == 6) {
+                    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| format!("Invalid color: {name}"));
+                    return Ok(Color::Rgb(channel(0)?, channel(2)?, channel(4)?));
+                }
+                COLOR_NAMES.iter()
+                    .position(|color| color == name)
+                    .map(|index| Color::Ansi(index as u8))
+                    .ok_or_else(|| format!("Invalid color: {name}"))
+            },
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct StyleDef {
+    fg: Option<ColorDef>,
+    bg: Option<ColorDef>,
+    bold: bool,
+    italic: bool,
+    underline: bool,
+    reverse: bool,
+}
+
+#[derive(Deserialize)]
+struct ThemeDef {
+    name: String,
+    groups: HashMap<String, StyleDef>,
+}
+
+/// A color scheme: the style of each highlight group.
+#[derive(Debug, Clone)]
+pub struct Theme {
+    pub name: String,
+    styles: HashMap<Group, Style>,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme::parse(BUILTIN[0].1, None).expect("the default theme is valid")
+    }
+}
+
+impl Theme {
+    /// Parses a theme file. Groups it leaves out are styled as in `base`.
+    fn parse(json: &str, base: Option<&Theme>) -> Result<Theme, String> {
+        let def: ThemeDef = serde_json::from_str(json).map_err(|e| e.to_string())?;
+        let mut styles = base.map(|base| base.styles.clone()).unwrap_or_default();
+        for (name, style) in def.groups {
+            let group = Group::from_name(&name).ok_or_else(|| format!("Unknown highlight group: {name}"))?;
+            let color = |color: &Option<ColorDef>| color.as_ref().map_or(Ok(Color::Default), ColorDef::color);
+            styles.insert(group, Style {
+                fg: color(&style.fg)?,
+                bg: color(&style.bg)?,
+                bold: style.bold,
+                italic: style.italic,
+                underline: style.underline,
+                reverse: style.reverse,
+            });
+        }
+        Ok(Theme { name: def.name, styles })
+    }
+
+    /// The style of `group`. Colors it leaves as the default are Normal's.
+    pub fn style(&self, group: Group) -> Style {
+        let normal = self.styles.get(&Group::Normal).copied().unwrap_or(Style::PLAIN);
+        let mut style = self.styles.get(&group).copied().unwrap_or(normal);
+        if style.fg == Color::Default {
+            style.fg = normal.fg;
+        }
+        if style.bg == Color::Default {
+            style.bg = normal.bg;
+        }
+        style
+    }
+}
+
+/// Every theme the server knows.
+#[derive(Default)]
+pub struct Themes {
+    themes: Vec<Theme>,
+}
+
+impl Themes {
+    /// The built-in themes, and any in the `tt/themes` configuration directory.
+    pub fn load() -> Self {
+        let mut themes = Themes::default();
+        for (file, json) in BUILTIN {
+            themes.add(file, json);
+        }
+        for path in crate::config_files("themes") {
+            match std::fs::read_to_string(&path) {
+                Ok(json) => themes.add(&path.to_string_lossy(), &json),
+                Err(e) => warn!("Could not read theme {path:?}: {e}"),
+            }
+        }
+        themes
+    }
+
+    fn add(&mut self, file: &str, json: &str) {
+        // Every theme falls back on the built-in default for the groups it leaves out.
+        let base = Theme::default();
+        match Theme::parse(json, Some(&base)) {
+            Ok(theme) => {
+                info!("Loaded theme {} from {file}", theme.name);
+                self.themes.retain(|other| other.name != theme.name);
+                self.themes.push(theme);
+            },
+            Err(e) => warn!("Could not load theme {file}: {e}"),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Theme> {
+        self.themes.iter().find(|theme| theme.name == name)
+    }
+}
+
+/// The colors of the 256 color palette, as xterm shows them.
+fn palette(index: u8) -> (u8, u8, u8) {
+    const ANSI: [(u8, u8, u8); 16] = [
+        (0, 0, 0), (205, 0, 0), (0, 205, 0), (205, 205, 0), (0, 0, 238), (205, 0, 205), (0, 205, 205), (229, 229, 229),
+        (127, 127, 127), (255, 0, 0), (0, 255, 0), (255, 255, 0), (92, 92, 255), (255, 0, 255), (0, 255, 255), (255, 255, 255),
+    ];
+    const LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+    match index {
+        0..=15 => ANSI[index as usize],
+        16..=231 => {
+            let i = index as usize - 16;
+            (LEVELS[i / 36], LEVELS[i / 6 % 6], LEVELS[i % 6])
+        },
+        _ => {
+            let gray = 8 + 10 * (index - 232);
+            (gray, gray, gray)
+        },
+    }
+}
+
+/// The palette entry of `indices` closest to `rgb`.
+fn nearest(rgb: (u8, u8, u8), indices: std::ops::RangeInclusive<u8>) -> u8 {
+    let distance = |index: &u8| {
+        let (r, g, b) = palette(*index);
+        let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+        d(r, rgb.0) + d(g, rgb.1) + d(b, rgb.2)
+    };
+    indices.min_by_key(distance).unwrap_or(0)
+}
+
+fn downgrade_color(color: Color, depth: ColorDepth) -> Color {
+    match (color, depth) {
+        (Color::Rgb(..), ColorDepth::TrueColor) => color,
+        (Color::Rgb(r, g, b), ColorDepth::Indexed256) => Color::Indexed(nearest((r, g, b), 16..=255)),
+        (Color::Rgb(r, g, b), ColorDepth::Ansi16) => Color::Ansi(nearest((r, g, b), 0..=15)),
+        (Color::Indexed(index), ColorDepth::Ansi16) if index >= 16 => Color::Ansi(nearest(palette(index), 0..=15)),
+        _ => color,
+    }
+}
+
+/// `style` with its colors replaced by the nearest ones a terminal of `depth` has.
+pub fn downgrade(style: Style, depth: ColorDepth) -> Style {
+    Style { fg: downgrade_color(style.fg, depth), bg: downgrade_color(style.bg, depth), ..style }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn themes_fall_back_on_normal_and_the_default() {
+        let json = r##"{"name": "test", "groups": {
+            "Normal": {"fg": "#ebdbb2", "bg": 235},
+            "Comment": {"fg": "brightblack", "italic": true}
+        }}"##;
+        let theme = Theme::parse(json, Some(&Theme::default())).unwrap();
+        assert_eq!(theme.style(Group::Comment), Style {
+            fg: Color::Ansi(8),
+            bg: Color::Indexed(235),
+            italic: true,
+            ..Style::PLAIN
+        });
+        // LineNr comes from the default theme, on the new background.
+        assert_eq!(theme.style(Group::LineNr).fg, Theme::default().style(Group::LineNr).fg);
+        assert_eq!(theme.style(Group::LineNr).bg, Color::Indexed(235));
+        assert!(Theme::parse(r#"{"name": "bad", "groups": {"Nope": {}}}"#, None).is_err());
+        assert!(Theme::parse(r##"{"name": "bad", "groups": {"Normal": {"fg": "#12"}}}"##, None).is_err());
+    }
+
+    #[test]
+    fn colors_are_downgraded_to_the_nearest() {
+        let style = Style { fg: Color::Rgb(255, 0, 0), bg: Color::Rgb(20, 20, 20), ..Style::PLAIN };
+        assert_eq!(downgrade(style, ColorDepth::TrueColor), style);
+        assert_eq!(downgrade(style, ColorDepth::Indexed256).fg, Color::Indexed(196));
+        assert_eq!(downgrade(style, ColorDepth::Indexed256).bg, Color::Indexed(233));
+        assert_eq!(downgrade(style, ColorDepth::Ansi16).fg, Color::Ansi(9));
+        assert_eq!(downgrade(style, ColorDepth::Ansi16).bg, Color::Ansi(0));
+        assert_eq!(downgrade_color(Color::Indexed(231), ColorDepth::Ansi16), Color::Ansi(15));
+        assert_eq!(downgrade_color(Color::Ansi(3), ColorDepth::Ansi16), Color::Ansi(3));
+    }
+}
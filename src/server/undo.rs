@@ -0,0 +1,316 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use serde::{Serialize, Deserialize};
+
+use crate::buffer::Cursor;
+use crate::view::ClientId;
+
+/// A single replacement of `removed` by `inserted` at character index `at`.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct Edit {
+    pub at: usize,
+    pub removed: String,
+    pub inserted: String,
+}
+
+impl Edit {
+    pub fn inverse(&self) -> Edit {
+        Edit {
+            at: self.at,
+            removed: self.inserted.clone(),
+            inserted: self.removed.clone(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct UndoNode {
+    parent: Option<usize>,
+    /// The child that redo follows: the one most recently created or undone.
+    latest_child: Option<usize>,
+    edits: Vec<Edit>,
+    cursor: Cursor,
+    time: u64,
+}
+
+#[derive(Debug, Clone)]
+struct OpenGroup {
+    edits: Vec<Edit>,
+    cursor: Cursor,
+}
+
+/// The change history of a buffer. Node 0 is the unmodified text; every other
+/// node is a group of edits whose index doubles as its change number.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UndoTree {
+    nodes: Vec<UndoNode>,
+    current: usize,
+    #[serde(skip)]
+    open: Option<OpenGroup>,
+    /// The clients other than the acting one that had a group open when they
+    /// stopped acting. Their groups were ended then, so that the acting
+    /// client's edits neither land in them nor come before them in the tree,
+    /// and each is begun again when its client acts.
+    #[serde(skip)]
+    parked: Vec<ClientId>,
+}
+
+impl Default for UndoTree {
+    fn default() -> Self {
+        UndoTree {
+            nodes: vec![UndoNode {
+                parent: None,
+                latest_child: None,
+                edits: vec![],
+                cursor: Cursor::default(),
+                time: now(),
+            }],
+            current: 0,
+            open: None,
+            parked: vec![],
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+impl UndoTree {
+    /// Starts grouping edits until `end_group` is called. Nested calls are ignored.
+    pub fn begin_group(&mut self, cursor: Cursor) {
+        if self.open.is_none() {
+            self.open = Some(OpenGroup { edits: vec![], cursor });
+        }
+    }
+
+    pub fn end_group(&mut self) {
+        if let Some(group) = self.open.take() {
+            if !group.edits.is_empty() {
+                self.push(group.edits, group.cursor);
+            }
+        }
+    }
+
+    /// Ends the open group as `client` stops acting, remembering to begin
+    /// another when it acts again.
+    pub fn park_group(&mut self, client: ClientId) {
+        if self.open.is_some() {
+            self.end_group();
+            self.parked.push(client);
+        }
+    }
+
+    /// Begins a group again for `client` if it had one open when it was parked.
+    pub fn unpark_group(&mut self, client: ClientId, cursor: Cursor) {
+        if let Some(i) = self.parked.iter().position(|&owner| owner == client) {
+            self.parked.remove(i);
+            self.begin_group(cursor);
+        }
+    }
+
+    /// Forgets that `client` had a group open, as when it goes away.
+    pub fn end_parked_group(&mut self, client: ClientId) {
+        self.parked.retain(|&owner| owner != client);
+    }
+
+    /// Whether the open group has edits in it, which aren't a change of their own yet.
+    pub fn has_open_edits(&self) -> bool {
+        self.open.as_ref().is_some_and(|group| !group.edits.is_empty())
+    }
+
+    pub fn record(&mut self, edit: Edit, cursor: Cursor) {
+        match &mut self.open {
+            Some(group) => group.edits.push(edit),
+            None => self.push(vec![edit], cursor),
+        }
+    }
+
+    fn push(&mut self, edits: Vec<Edit>, cursor: Cursor) {
+        let seq = self.nodes.len();
+        self.nodes.push(UndoNode {
+            parent: Some(self.current),
+            latest_child: None,
+            edits,
+            cursor,
+            time: now(),
+        });
+        self.nodes[self.current].latest_child = Some(seq);
+        self.current = seq;
+    }
+
+    /// The change number of the current text state.
+    pub fn seq(&self) -> usize {
+        self.current
+    }
+
+    pub fn max_seq(&self) -> usize {
+        self.nodes.len() - 1
+    }
+
+    /// Steps back one change, returning the edits to apply and where to put the cursor.
+    pub fn undo(&mut self) -> Option<(Vec<Edit>, Cursor)> {
+        self.end_group();
+        let node = &self.nodes[self.current];
+        let parent = node.parent?;
+        let edits = node.edits.iter().rev().map(Edit::inverse).collect();
+        let cursor = node.cursor;
+        self.nodes[parent].latest_child = Some(self.current);
+        self.current = parent;
+        Some((edits, cursor))
+    }
+
+    /// Steps forward along the most recent branch, returning the edits to apply.
+    pub fn redo(&mut self) -> Option<Vec<Edit>> {
+        self.end_group();
+        let child = self.nodes[self.current].latest_child?;
+        self.current = child;
+        Some(self.nodes[child].edits.clone())
+    }
+
+    fn ancestors(&self, mut seq: usize) -> Vec<usize> {
+        let mut path = vec![seq];
+        while let Some(parent) = self.nodes[seq].parent {
+            path.push(parent);
+            seq = parent;
+        }
+        path
+    }
+
+    /// Moves to the text state after change `seq`, possibly on another branch,
+    /// returning the edits needed to get there in order.
+    pub fn goto(&mut self, seq: usize) -> Option<Vec<Edit>> {
+        self.end_group();
+        if seq >= self.nodes.len() {
+            return None;
+        }
+        let from = self.ancestors(self.current);
+        let to = self.ancestors(seq);
+        let common = *from.iter().find(|node| to.contains(node))?;
+
+        let mut edits = vec![];
+        for &node in from.iter().take_while(|&&node| node != common) {
+            edits.extend(self.nodes[node].edits.iter().rev().map(Edit::inverse));
+        }
+        let down: Vec<usize> = to.iter().take_while(|&&node| node != common).copied().collect();
+        for &node in down.iter().rev() {
+            let parent = self.nodes[node].parent.unwrap();
+            self.nodes[parent].latest_child = Some(node);
+            edits.extend(self.nodes[node].edits.iter().cloned());
+        }
+        self.current = seq;
+        Some(edits)
+    }
+
+    /// The change number of the latest state no newer than `offset` from the current one.
+    pub fn seq_at_time_offset(&self, offset: Duration, forward: bool) -> usize {
+        let time = self.nodes[self.current].time;
+        let target = if forward {
+            time.saturating_add(offset.as_secs())
+        } else {
+            time.saturating_sub(offset.as_secs())
+        };
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| node.time <= target)
+            .map(|(seq, _)| seq)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// The argument of `:earlier` and `:later`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Offset {
+    Steps(usize),
+    Time(Duration),
+}
+
+/// Parses either a step count or a duration like `5m`.
+pub fn parse_offset(arg: &str) -> Option<Offset> {
+    let arg = arg.trim();
+    if arg.is_empty() {
+        return Some(Offset::Steps(1));
+    }
+    if let Ok(count) = arg.parse::<usize>() {
+        return Some(Offset::Steps(count));
+    }
+    let (number, unit) = arg.split_at(arg.char_indices().last()?.0);
+    let number: u64 = number.parse().ok()?;
+    let secs = match unit {
+        "s" => number,
+        "m" => number * 60,
+        "h" => number * 60 * 60,
+        "d" => number * 60 * 60 * 24,
+        _ => return None,
+    };
+    Some(Offset::Time(Duration::from_secs(secs)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn edit(at: usize, inserted: &str) -> Edit {
+        Edit { at, removed: String::new(), inserted: inserted.to_string() }
+    }
+
+    #[test]
+    fn groups_and_branches() {
+        let mut tree = UndoTree::default();
+        tree.begin_group(Cursor::default());
+        tree.record(edit(0, "a"), Cursor::default());
+        tree.record(edit(1, "b"), Cursor::default());
+        tree.end_group();
+        tree.record(edit(2, "c"), Cursor::default());
+        assert_eq!(tree.seq(), 2);
+
+        let (edits, _) = tree.undo().unwrap();
+        assert_eq!(edits, vec![edit(2, "c").inverse()]);
+        tree.record(edit(2, "d"), Cursor::default());
+        assert_eq!(tree.seq(), 3);
+
+        // Jumping to change 2 undoes "d" and redoes "c" on the other branch.
+        let edits = tree.goto(2).unwrap();
+        assert_eq!(edits, vec![edit(2, "d").inverse(), edit(2, "c")]);
+        assert_eq!(tree.redo(), None);
+
+        let (edits, _) = tree.undo().unwrap();
+        assert_eq!(edits.len(), 1);
+        let (edits, _) = tree.undo().unwrap();
+        assert_eq!(edits, vec![edit(1, "b").inverse(), edit(0, "a").inverse()]);
+        assert!(tree.undo().is_none());
+        assert_eq!(tree.redo().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn parked_groups_are_ended_before_other_edits() {
+        let mut tree = UndoTree::default();
+        tree.begin_group(Cursor::default());
+        tree.record(edit(5, "AB"), Cursor::default());
+        tree.park_group(1);
+        assert!(!tree.has_open_edits());
+        assert_eq!(tree.seq(), 1);
+
+        // Another client's edit comes after the parked group, so undoing that
+        // group later doesn't need offsets shifted past it.
+        tree.record(edit(0, "XYZ"), Cursor::default());
+        tree.unpark_group(1, Cursor::default());
+        tree.record(edit(10, "C"), Cursor::default());
+        assert!(tree.has_open_edits());
+        let (edits, _) = tree.undo().unwrap();
+        assert_eq!(edits, vec![edit(10, "C").inverse()]);
+        let (edits, _) = tree.undo().unwrap();
+        assert_eq!(edits, vec![edit(0, "XYZ").inverse()]);
+        let (edits, _) = tree.undo().unwrap();
+        assert_eq!(edits, vec![edit(5, "AB").inverse()]);
+    }
+
+    #[test]
+    fn parses_offsets() {
+        assert_eq!(parse_offset(""), Some(Offset::Steps(1)));
+        assert_eq!(parse_offset("3"), Some(Offset::Steps(3)));
+        assert_eq!(parse_offset("5m"), Some(Offset::Time(Duration::from_secs(300))));
+        assert_eq!(parse_offset("5x"), None);
+    }
+}
@@ -0,0 +1,164 @@
+use tt::message::{ColorDepth, Key, Size};
+
+use crate::BufferMode;
+use crate::buffer::BufferId;
+use crate::cmdline::CommandLine;
+use crate::search::Prompt;
+use crate::substitute::Substitution;
+use crate::visual::BlockInsert;
+use crate::window::{Layout, Rect, Window};
+
+pub type ClientId = usize;
+
+/// What a client is in the middle of, which goes on while others act.
+#[derive(Debug, Clone, Default)]
+pub struct Session {
+    pub mode: BufferMode,
+    pub command: Option<CommandLine>,
+    pub pending: Vec<Key>,
+    pub message: Option<String>,
+    /// A put from the clipboard registers, waiting for the client to send its clipboard.
+    pub clipboard_put: Option<(bool, usize)>,
+    pub block_insert: Option<BlockInsert>,
+    pub search_prompt: Option<Prompt>,
+    /// A `:s` command waiting for the user to confirm its next replacement.
+    pub substitution: Option<Substitution>,
+}
+
+/// Another client whose current window a client is shown in place of its own.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Follow {
+    pub leader: ClientId,
+    /// Whether the follower's first key stops following, carrying on from
+    /// where the leader is, instead of being refused.
+    pub takeover: bool,
+}
+
+/// What one client sees: its tab pages, each with its own window layout.
+#[derive(Debug, Clone)]
+pub struct View {
+    /// What other clients see this one as.
+    pub name: String,
+    pub tabs: Vec<Layout>,
+    /// The index of the tab page shown.
+    pub tab: usize,
+    /// The size of the client's terminal.
+    pub size: Size,
+    /// The colors the client's terminal can show.
+    pub colors: ColorDepth,
+    pub following: Option<Follow>,
+    /// Set for clients started with `--readonly`, which can look around but not edit.
+    pub readonly: bool,
+    /// The client's session while another client is acting. The acting
+    /// client's is in `TermTextState::session`.
+    pub session: Session,
+}
+
+impl Default for View {
+    fn default() -> Self {
+        View::new(None)
+    }
+}
+
+impl View {
+    /// A view with one tab page, showing `buffer`.
+    pub fn new(buffer: Option<BufferId>) -> Self {
+        View {
+            name: String::new(),
+            tabs: vec![Layout::new(buffer)],
+            tab: 0,
+            size: (0, 0),
+            colors: ColorDepth::default(),
+            following: None,
+            readonly: false,
+            session: Session::default(),
+        }
+    }
+
+    pub fn layout(&self) -> &Layout {
+        &self.tabs[self.tab]
+    }
+
+    pub fn layout_mut(&mut self) -> &mut Layout {
+        &mut self.tabs[self.tab]
+    }
+
+    /// Every window, in every tab page.
+    pub fn windows_mut(&mut self) -> impl Iterator<Item = &mut Window> {
+        self.tabs.iter_mut().flat_map(|layout| layout.windows_mut())
+    }
+
+    /// Whether the tab line is shown, which like Vim is only when there are several tabs.
+    pub fn shows_tab_line(&self) -> bool {
+        self.tabs.len() > 1
+    }
+
+    /// The part of the client's screen the windows share, between the tab line
+    /// and the command line.
+    pub fn area(&self) -> Rect {
+        let size = self.size;
+        let top = if self.shows_tab_line() { 1 } else { 0 };
+        Rect::new(0, top, size.0, size.1.saturating_sub(1 + top))
+    }
+
+    /// Adds a tab page after the current one, returning its index.
+    pub fn add_tab(&mut self, buffer: Option<BufferId>) -> usize {
+        self.tabs.insert(self.tab + 1, Layout::new(buffer));
+        self.tab + 1
+    }
+
+    /// Closes tab page `index`, returning false if it is the last one.
+    pub fn close_tab(&mut self, index: usize) -> bool {
+        if self.tabs.len() == 1 || index >= self.tabs.len() {
+            return false;
+        }
+        self.tabs.remove(index);
+        if self.tab > index || self.tab == self.tabs.len() {
+            self.tab -= 1;
+        }
+        true
+    }
+
+    pub fn only_tab(&mut self) {
+        let layout = self.tabs.swap_remove(self.tab);
+        self.tabs = vec![layout];
+        self.tab = 0;
+    }
+
+    /// The index of the tab page `offset` places after the current one, wrapping around.
+    pub fn cycle_tab(&self, offset: isize) -> usize {
+        (self.tab as isize + offset).rem_euclid(self.tabs.len() as isize) as usize
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn tabs_open_after_the_current_one() {
+        let mut view = View::new(Some(1));
+        view.tab = view.add_tab(Some(2));
+        view.tab = view.add_tab(Some(3));
+        view.tab = 0;
+        assert_eq!(view.add_tab(Some(4)), 1);
+        let buffers: Vec<_> = view.tabs.iter().map(|layout| layout.current().buffer).collect();
+        assert_eq!(buffers, vec![Some(1), Some(4), Some(2), Some(3)]);
+        assert_eq!(view.cycle_tab(-1), 3);
+        view.size = (80, 24);
+        assert_eq!(view.area(), Rect::new(0, 1, 80, 22));
+    }
+
+    #[test]
+    fn closing_tabs() {
+        let mut view = View::new(Some(1));
+        view.add_tab(Some(2));
+        view.tab = view.add_tab(Some(3));
+        assert!(view.close_tab(2));
+        assert_eq!(view.tab, 1);
+        assert!(view.close_tab(0));
+        assert_eq!((view.tab, view.layout().current().buffer), (0, Some(3)));
+        assert!(!view.close_tab(0));
+        assert!(!view.shows_tab_line());
+    }
+}
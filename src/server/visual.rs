@@ -0,0 +1,154 @@
+use crate::BufferMode;
+use crate::buffer::{Buffer, Cursor};
+use crate::operator::Block;
+use crate::textobject::Span;
+
+/// What a Visual mode selection covers, ready to hand to an operator.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Selection {
+    Span(Span),
+    Block(Block),
+}
+
+/// The current selection between the buffer's anchor and cursor.
+pub fn selection(buffer: &Buffer, mode: BufferMode) -> Option<Selection> {
+    select(buffer, buffer.anchor?, buffer.pos, buffer.want_col, mode)
+}
+
+/// The selection in `mode` between `anchor` and a cursor at `pos` that wants column `want_col`.
+fn select(buffer: &Buffer, anchor: Cursor, pos: Cursor, want_col: usize, mode: BufferMode) -> Option<Selection> {
+    let (start, end) = if anchor <= pos { (anchor, pos) } else { (pos, anchor) };
+
+    match mode {
+        BufferMode::Visual => {
+            let mut end = Cursor::new(end.line, end.col + 1);
+            if end.col > buffer.line_len(end.line) && end.line + 1 < buffer.line_count() {
+                // Selecting past the end of a line takes its line break too.
+                end = Cursor::new(end.line + 1, 0);
+            }
+            Some(Selection::Span(Span { start, end, linewise: false }))
+        },
+        BufferMode::VisualLine => Some(Selection::Span(Span { start, end, linewise: true })),
+        BufferMode::VisualBlock => {
+            let right = if want_col == usize::MAX {
+                (start.line..=end.line)
+                    .map(|line| buffer.line_len(line).saturating_sub(1))
+                    .max()
+                    .unwrap_or(0)
+            } else {
+                anchor.col.max(pos.col)
+            };
+            Some(Selection::Block(Block {
+                first: start.line,
+                last: end.line,
+                left: anchor.col.min(pos.col),
+                right,
+            }))
+        },
+        _ => None,
+    }
+}
+
+/// The selected character columns of each selected line, as `(line, start, end)`
+/// with `end` exclusive. Empty lines get one column so they are still visible.
+pub fn highlights(buffer: &Buffer, mode: BufferMode) -> Vec<(usize, usize, usize)> {
+    match selection(buffer, mode) {
+        Some(selection) => selection_ranges(buffer, selection),
+        None => vec![],
+    }
+}
+
+/// Like `highlights`, for a selection kept outside the buffer, such as
+/// another client's.
+pub fn highlights_between(buffer: &Buffer, anchor: Cursor, pos: Cursor, want_col: usize, mode: BufferMode) -> Vec<(usize, usize, usize)> {
+    match select(buffer, anchor, pos, want_col, mode) {
+        Some(selection) => selection_ranges(buffer, selection),
+        None => vec![],
+    }
+}
+
+fn selection_ranges(buffer: &Buffer, selection: Selection) -> Vec<(usize, usize, usize)> {
+    let visible_end = |line: usize, end: usize| end.min(buffer.line_len(line).max(1));
+
+    match selection {
+        Selection::Span(span) if span.linewise => (span.start.line..=span.end.line)
+            .map(|line| (line, 0, visible_end(line, usize::MAX)))
+            .collect(),
+        Selection::Span(span) => {
+            let mut ranges = vec![];
+            for line in span.start.line..=span.end.line {
+                let start = if line == span.start.line { span.start.col } else { 0 };
+                let end = if line == span.end.line { span.end.col } else { usize::MAX };
+                if end > start {
+                    ranges.push((line, start, visible_end(line, end)));
+                }
+            }
+            ranges
+        },
+        Selection::Block(block) => (block.first..=block.last)
+            .map(|line| (line, block.left, block.right + 1))
+            .collect(),
+    }
+}
+
+/// An Insert session started from Visual-Block mode, whose text is copied to
+/// every line of the block when it ends.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct BlockInsert {
+    pub first: usize,
+    pub last: usize,
+    pub col: usize,
+    /// Appending (`A`) pads short lines; inserting (`I`, `c`) skips them.
+    pub pad: bool,
+}
+
+pub fn finish_block_insert(buffer: &mut Buffer, insert: BlockInsert) {
+    if buffer.pos.line != insert.first || buffer.pos.col <= insert.col {
+        return;
+    }
+    let text: String = buffer.line(insert.first)
+        .chars()
+        .skip(insert.col)
+        .take(buffer.pos.col - insert.col)
+        .collect();
+    for line in insert.first + 1..=insert.last.min(buffer.line_count() - 1) {
+        let len = buffer.line_len(line);
+        if len < insert.col {
+            if !insert.pad {
+                continue;
+            }
+            buffer.insert(Cursor::new(line, len), &" ".repeat(insert.col - len));
+        }
+        buffer.insert(Cursor::new(line, insert.col), &text);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn block_insert_copies_text_to_each_line() {
+        let mut buffer = Buffer::new(None, "abc\nd\nefg");
+        buffer.pos = Cursor::new(0, 2);
+        buffer.insert_at_cursor("XY");
+        finish_block_insert(&mut buffer, BlockInsert { first: 0, last: 2, col: 2, pad: false });
+        assert_eq!(buffer.text.to_string(), "abXYc\nd\nefXYg");
+
+        let mut buffer = Buffer::new(None, "abc\nd");
+        buffer.pos = Cursor::new(0, 3);
+        buffer.insert_at_cursor("!");
+        finish_block_insert(&mut buffer, BlockInsert { first: 0, last: 1, col: 3, pad: true });
+        assert_eq!(buffer.text.to_string(), "abc!\nd  !");
+    }
+
+    #[test]
+    fn selections_follow_the_mode() {
+        let mut buffer = Buffer::new(None, "hello\nworld");
+        buffer.anchor = Some(Cursor::new(1, 3));
+        buffer.pos = Cursor::new(0, 1);
+        assert_eq!(highlights(&buffer, BufferMode::Visual), vec![(0, 1, 5), (1, 0, 4)]);
+        assert_eq!(highlights(&buffer, BufferMode::VisualLine), vec![(0, 0, 5), (1, 0, 5)]);
+        assert_eq!(highlights(&buffer, BufferMode::VisualBlock), vec![(0, 1, 4), (1, 1, 4)]);
+    }
+}
@@ -0,0 +1,423 @@
+use crate::buffer::{BufferId, Cursor};
+
+pub type WindowId = usize;
+
+/// A view onto a buffer. The cursor of the focused window lives in its buffer,
+/// so `cursor` and `want_col` are only up to date for the other windows.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Window {
+    pub id: WindowId,
+    pub buffer: Option<BufferId>,
+    /// The buffer shown before the current one, which Ctrl-^ returns to.
+    pub alternate: Option<BufferId>,
+    pub cursor: Cursor,
+    pub want_col: usize,
+    /// Where the Visual selection started, while there is one.
+    pub anchor: Option<Cursor>,
+    /// The first buffer line shown.
+    pub top: usize,
+    /// The first screen column of text shown, when lines are scrolled sideways.
+    pub left: usize,
+}
+
+/// Which way the children of a split are laid out.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Direction {
+    /// Stacked top to bottom, as by `:split`.
+    Down,
+    /// Side by side, as by `:vsplit`.
+    Right,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Side {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+/// A `Ctrl-w` command.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum WindowCommand {
+    Split(Direction),
+    Close,
+    /// Like `:quit`, which closes the buffer along with the last window.
+    Quit,
+    Only,
+    Focus(Side),
+    Next,
+    Previous,
+    /// Grows (or with a negative amount, shrinks) the window along a direction,
+    /// by the amount times the count.
+    Resize(Direction, isize),
+    /// Sets the window's size along a direction to the count, or with no count,
+    /// makes it as big as possible.
+    SetSize(Direction),
+    Equalize,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct Rect {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+impl Rect {
+    pub fn new(x: u16, y: u16, width: u16, height: u16) -> Self {
+        Rect { x, y, width, height }
+    }
+
+    fn contains(&self, x: u16, y: u16) -> bool {
+        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+    }
+
+    fn extent(&self, direction: Direction) -> u16 {
+        match direction {
+            Direction::Down => self.height,
+            Direction::Right => self.width,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum Node {
+    Window(WindowId),
+    /// Children with their share of the split's space.
+    Split(Direction, Vec<(Node, f64)>),
+}
+
+impl Node {
+    fn contains(&self, id: WindowId) -> bool {
+        match self {
+            Node::Window(window) => *window == id,
+            Node::Split(_, children) => children.iter().any(|(child, _)| child.contains(id)),
+        }
+    }
+
+    fn first_window(&self) -> WindowId {
+        match self {
+            Node::Window(window) => *window,
+            Node::Split(_, children) => children[0].0.first_window(),
+        }
+    }
+}
+
+/// Divides `total` cells between children in proportion to their weights.
+fn sizes(total: u16, weights: &[f64]) -> Vec<u16> {
+    let sum: f64 = weights.iter().sum();
+    let mut edges = vec![0];
+    let mut cumulative = 0.0;
+    for weight in weights {
+        cumulative += weight;
+        edges.push((total as f64 * cumulative / sum).round() as u16);
+    }
+    edges.windows(2).map(|edge| edge[1] - edge[0]).collect()
+}
+
+/// The areas of a split's children. Side-by-side children give up their last
+/// column to a separator.
+fn child_rects(direction: Direction, area: Rect, weights: &[f64]) -> Vec<Rect> {
+    let mut offset = 0;
+    let count = weights.len();
+    sizes(area.extent(direction), weights)
+        .into_iter()
+        .enumerate()
+        .map(|(i, size)| {
+            let rect = match direction {
+                Direction::Down => Rect::new(area.x, area.y + offset, area.width, size),
+                Direction::Right => {
+                    let separator = if i + 1 < count { 1 } else { 0 };
+                    Rect::new(area.x + offset, area.y, size.saturating_sub(separator), area.height)
+                },
+            };
+            offset += size;
+            rect
+        })
+        .collect()
+}
+
+/// The smallest a window may be: one line of text and its status line, or one
+/// column and its separator.
+const MIN_SIZE: isize = 2;
+
+/// The tree of windows dividing the screen.
+#[derive(Debug, Clone)]
+pub struct Layout {
+    root: Node,
+    windows: Vec<Window>,
+    pub current: WindowId,
+    last_id: WindowId,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Layout::new(None)
+    }
+}
+
+impl Layout {
+    /// A layout of one window, showing `buffer`.
+    pub fn new(buffer: Option<BufferId>) -> Self {
+        Layout {
+            root: Node::Window(1),
+            windows: vec![Window { id: 1, buffer, ..Window::default() }],
+            current: 1,
+            last_id: 1,
+        }
+    }
+
+    pub fn windows(&self) -> impl Iterator<Item = &Window> {
+        self.windows.iter()
+    }
+
+    pub fn windows_mut(&mut self) -> impl Iterator<Item = &mut Window> {
+        self.windows.iter_mut()
+    }
+
+    /// Whether there is more than one window.
+    pub fn is_split(&self) -> bool {
+        self.windows.len() > 1
+    }
+
+    pub fn get(&self, id: WindowId) -> Option<&Window> {
+        self.windows.iter().find(|window| window.id == id)
+    }
+
+    pub fn current(&self) -> &Window {
+        self.get(self.current).expect("current window exists")
+    }
+
+    pub fn current_mut(&mut self) -> &mut Window {
+        let current = self.current;
+        self.windows.iter_mut().find(|window| window.id == current).expect("current window exists")
+    }
+
+    /// Splits the current window, putting a copy of it above or to the left.
+    /// Returns the new window, which the caller should focus.
+    pub fn split(&mut self, direction: Direction) -> WindowId {
+        self.last_id += 1;
+        let id = self.last_id;
+        let window = Window { id, ..self.current().clone() };
+        self.windows.push(window);
+        split_node(&mut self.root, self.current, id, direction);
+        id
+    }
+
+    /// Closes a window, giving its space to a neighbour. Returns the window that
+    /// should be focused next, or `None` if this was the last window.
+    pub fn close(&mut self, id: WindowId) -> Option<WindowId> {
+        if self.windows.len() == 1 || !self.root.contains(id) {
+            return None;
+        }
+        let next = remove_node(&mut self.root, id);
+        self.windows.retain(|window| window.id != id);
+        next
+    }
+
+    /// Closes every window except the current one.
+    pub fn only(&mut self) {
+        self.root = Node::Window(self.current);
+        let current = self.current;
+        self.windows.retain(|window| window.id == current);
+    }
+
+    /// The area each window covers, including its status line.
+    pub fn rects(&self, area: Rect) -> Vec<(WindowId, Rect)> {
+        let mut rects = vec![];
+        collect_rects(&self.root, area, &mut rects, &mut vec![]);
+        rects
+    }
+
+    /// The columns separating side-by-side windows.
+    pub fn separators(&self, area: Rect) -> Vec<Rect> {
+        let mut separators = vec![];
+        collect_rects(&self.root, area, &mut vec![], &mut separators);
+        separators
+    }
+
+    pub fn rect(&self, area: Rect, id: WindowId) -> Option<Rect> {
+        self.rects(area).into_iter().find(|(window, _)| *window == id).map(|(_, rect)| rect)
+    }
+
+    /// The window next to `id` on `side`, level with screen position `at`.
+    pub fn neighbour(&self, area: Rect, id: WindowId, side: Side, at: (u16, u16)) -> Option<WindowId> {
+        let rect = self.rect(area, id)?;
+        let (x, y) = match side {
+            Side::Left => (rect.x.checked_sub(2)?, at.1),
+            Side::Right => (rect.x + rect.width + 1, at.1),
+            Side::Up => (at.0, rect.y.checked_sub(1)?),
+            Side::Down => (at.0, rect.y + rect.height),
+        };
+        self.rects(area).into_iter().find(|(_, rect)| rect.contains(x, y)).map(|(window, _)| window)
+    }
+
+    /// The window `offset` places after `id`, in screen order, wrapping around.
+    pub fn cycle(&self, id: WindowId, offset: isize) -> WindowId {
+        let order: Vec<WindowId> = self.rects(Rect::new(0, 0, 1000, 1000)).into_iter().map(|(window, _)| window).collect();
+        let index = order.iter().position(|window| *window == id).unwrap_or(0) as isize;
+        order[(index + offset).rem_euclid(order.len() as isize) as usize]
+    }
+
+    /// Changes the size of a window along `direction` by `delta` cells, taking
+    /// the space from (or giving it to) its neighbour.
+    pub fn resize(&mut self, area: Rect, id: WindowId, direction: Direction, delta: isize) {
+        resize_node(&mut self.root, area, id, direction, delta);
+    }
+
+    pub fn set_size(&mut self, area: Rect, id: WindowId, direction: Direction, size: usize) {
+        if let Some(rect) = self.rect(area, id) {
+            let current = match direction {
+                Direction::Down => rect.height,
+                Direction::Right => rect.width,
+            };
+            self.resize(area, id, direction, size as isize - current as isize);
+        }
+    }
+
+    /// Gives every window in each split the same share of its space.
+    pub fn equalize(&mut self) {
+        fn equalize(node: &mut Node) {
+            if let Node::Split(_, children) = node {
+                for (child, weight) in children {
+                    *weight = 1.0;
+                    equalize(child);
+                }
+            }
+        }
+        equalize(&mut self.root);
+    }
+}
+
+fn split_node(node: &mut Node, target: WindowId, id: WindowId, direction: Direction) -> bool {
+    match node {
+        Node::Window(window) if *window == target => {
+            *node = Node::Split(direction, vec![(Node::Window(id), 1.0), (Node::Window(target), 1.0)]);
+            true
+        },
+        Node::Window(_) => false,
+        Node::Split(split_direction, children) => {
+            let index = match children.iter().position(|(child, _)| *child == Node::Window(target)) {
+                Some(index) if *split_direction == direction => index,
+                _ => return children.iter_mut().any(|(child, _)| split_node(child, target, id, direction)),
+            };
+            let weight = children[index].1 / 2.0;
+            children[index].1 = weight;
+            children.insert(index, (Node::Window(id), weight));
+            true
+        },
+    }
+}
+
+/// Removes window `id` from the tree, returning the window that took its space.
+fn remove_node(node: &mut Node, id: WindowId) -> Option<WindowId> {
+    let children = match node {
+        Node::Window(_) => return None,
+        Node::Split(_, children) => children,
+    };
+    let index = match children.iter().position(|(child, _)| *child == Node::Window(id)) {
+        Some(index) => index,
+        None => return children.iter_mut().find_map(|(child, _)| remove_node(child, id)),
+    };
+    let (_, weight) = children.remove(index);
+    let neighbour = if index < children.len() { index } else { index - 1 };
+    children[neighbour].1 += weight;
+    let next = children[neighbour].0.first_window();
+    if children.len() == 1 {
+        *node = children.remove(0).0;
+    }
+    Some(next)
+}
+
+fn collect_rects(node: &Node, area: Rect, rects: &mut Vec<(WindowId, Rect)>, separators: &mut Vec<Rect>) {
+    match node {
+        Node::Window(id) => rects.push((*id, area)),
+        Node::Split(direction, children) => {
+            let weights: Vec<f64> = children.iter().map(|(_, weight)| *weight).collect();
+            let child_areas = child_rects(*direction, area, &weights);
+            for (i, ((child, _), child_area)) in children.iter().zip(&child_areas).enumerate() {
+                if *direction == Direction::Right && i + 1 < children.len() {
+                    separators.push(Rect::new(child_area.x + child_area.width, area.y, 1, area.height));
+                }
+                collect_rects(child, *child_area, rects, separators);
+            }
+        },
+    }
+}
+
+fn resize_node(node: &mut Node, area: Rect, id: WindowId, direction: Direction, delta: isize) -> bool {
+    let (split_direction, children) = match node {
+        Node::Window(_) => return false,
+        Node::Split(split_direction, children) => (*split_direction, children),
+    };
+    let weights: Vec<f64> = children.iter().map(|(_, weight)| *weight).collect();
+    let child_areas = child_rects(split_direction, area, &weights);
+    let index = match children.iter().position(|(child, _)| child.contains(id)) {
+        Some(index) => index,
+        None => return false,
+    };
+    // The deepest split in the right direction is the one that gets resized.
+    if resize_node(&mut children[index].0, child_areas[index], id, direction, delta) {
+        return true;
+    }
+    if split_direction != direction || children.len() < 2 {
+        return false;
+    }
+
+    let mut sizes = sizes(area.extent(direction), &weights);
+    let neighbour = if index + 1 < children.len() { index + 1 } else { index - 1 };
+    let min = MIN_SIZE;
+    let available = sizes[index] as isize + sizes[neighbour] as isize;
+    let size = (sizes[index] as isize + delta).clamp(min.min(available), (available - min).max(min));
+    sizes[index] = size as u16;
+    sizes[neighbour] = (available - size) as u16;
+    for ((_, weight), size) in children.iter_mut().zip(sizes) {
+        *weight = size as f64;
+    }
+    true
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const AREA: Rect = Rect { x: 0, y: 0, width: 81, height: 20 };
+
+    #[test]
+    fn splits_divide_the_area() {
+        let mut layout = Layout::default();
+        let top = layout.split(Direction::Down);
+        assert_eq!(layout.rects(AREA), vec![(top, Rect::new(0, 0, 81, 10)), (1, Rect::new(0, 10, 81, 10))]);
+
+        layout.current = top;
+        let left = layout.split(Direction::Right);
+        assert_eq!(layout.rects(AREA)[..2], [(left, Rect::new(0, 0, 40, 10)), (top, Rect::new(41, 0, 40, 10))]);
+        assert_eq!(layout.separators(AREA), vec![Rect::new(40, 0, 1, 10)]);
+
+        assert_eq!(layout.neighbour(AREA, left, Side::Right, (0, 3)), Some(top));
+        assert_eq!(layout.neighbour(AREA, top, Side::Left, (50, 3)), Some(left));
+        assert_eq!(layout.neighbour(AREA, top, Side::Down, (50, 3)), Some(1));
+        assert_eq!(layout.neighbour(AREA, 1, Side::Up, (10, 15)), Some(left));
+        assert_eq!(layout.neighbour(AREA, 1, Side::Down, (10, 15)), None);
+        assert_eq!(layout.cycle(left, 1), top);
+        assert_eq!(layout.cycle(left, -1), 1);
+    }
+
+    #[test]
+    fn resizing_and_closing() {
+        let mut layout = Layout::default();
+        let top = layout.split(Direction::Down);
+        layout.resize(AREA, top, Direction::Down, 3);
+        assert_eq!(layout.rect(AREA, top), Some(Rect::new(0, 0, 81, 13)));
+        layout.set_size(AREA, 1, Direction::Down, 100);
+        assert_eq!(layout.rect(AREA, 1), Some(Rect::new(0, 2, 81, 18)));
+        layout.equalize();
+        assert_eq!(layout.rect(AREA, 1), Some(Rect::new(0, 10, 81, 10)));
+
+        assert_eq!(layout.close(top), Some(1));
+        assert_eq!(layout.rects(AREA), vec![(1, AREA)]);
+        assert_eq!(layout.close(1), None);
+    }
+}
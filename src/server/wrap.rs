@@ -0,0 +1,126 @@
+use crate::buffer::{Buffer, Cursor};
+use crate::render;
+use tt::unicode;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// How long lines are broken into screen rows.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Wrap {
+    /// The columns available for text.
+    pub width: usize,
+    /// The width of the `showbreak` text starting each continuation row.
+    pub showbreak: usize,
+    /// Break at whitespace rather than at any character.
+    pub linebreak: bool,
+}
+
+impl Wrap {
+    /// The columns taken by the `showbreak` text on `row`.
+    pub fn prefix(&self, row: usize) -> usize {
+        if row > 0 { self.showbreak } else { 0 }
+    }
+}
+
+/// Splits a line of display text into the screen columns shown on each row.
+/// Wide characters that would be cut at the edge move to the next row.
+pub fn rows(text: &str, wrap: &Wrap) -> Vec<(usize, usize)> {
+    let mut rows = vec![];
+    let mut start = 0;
+    let mut col = 0;
+    // The column after the last whitespace on the current row.
+    let mut space = None;
+    for grapheme in text.graphemes(true) {
+        let width = unicode::grapheme_width(grapheme);
+        while col > start && col + width - start > wrap.width.saturating_sub(wrap.prefix(rows.len())).max(1) {
+            let end = match space {
+                Some(space) if wrap.linebreak && space > start => space,
+                _ => col,
+            };
+            rows.push((start, end));
+            start = end;
+            space = None;
+        }
+        col += width;
+        if grapheme.chars().all(char::is_whitespace) {
+            space = Some(col);
+        }
+    }
+    rows.push((start, col));
+    rows
+}
+
+/// The rows buffer line `line` takes.
+pub fn line_rows(buffer: &Buffer, line: usize, wrap: &Wrap) -> Vec<(usize, usize)> {
+    rows(&render::expand_tabs(&buffer.line(line), buffer.indent.tabstop), wrap)
+}
+
+/// The row of `rows` screen column `col` is on.
+pub fn row_of(rows: &[(usize, usize)], col: usize) -> usize {
+    rows.iter().position(|&(start, end)| col >= start && col < end).unwrap_or(rows.len() - 1)
+}
+
+/// `gj` and `gk`: moves the cursor `count` screen rows down or up, keeping its
+/// screen column where the row is long enough.
+pub fn move_by_rows(buffer: &mut Buffer, wrap: &Wrap, down: bool, count: usize) {
+    let mut line = buffer.pos.line;
+    let mut rows = line_rows(buffer, line, wrap);
+    let col = render::screen_col(&buffer.line(line), buffer.pos.col, buffer.indent.tabstop);
+    let mut row = row_of(&rows, col);
+    let x = col - rows[row].0 + wrap.prefix(row);
+
+    for _ in 0..count {
+        if down && row + 1 < rows.len() {
+            row += 1;
+        } else if down && line + 1 < buffer.line_count() {
+            line += 1;
+            rows = line_rows(buffer, line, wrap);
+            row = 0;
+        } else if !down && row > 0 {
+            row -= 1;
+        } else if !down && line > 0 {
+            line -= 1;
+            rows = line_rows(buffer, line, wrap);
+            row = rows.len() - 1;
+        } else {
+            break;
+        }
+    }
+
+    let (start, end) = rows[row];
+    let col = (start + x.saturating_sub(wrap.prefix(row))).min(end.saturating_sub(1).max(start));
+    let col = render::char_col(&buffer.line(line), col, buffer.indent.tabstop);
+    buffer.pos = buffer.clamp_normal(Cursor::new(line, col));
+    buffer.want_col = buffer.pos.col;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn wraps_at_the_width_or_at_spaces() {
+        let wrap = Wrap { width: 10, showbreak: 2, linebreak: false };
+        assert_eq!(rows("", &wrap), vec![(0, 0)]);
+        assert_eq!(rows("abcdefghijklmnopqrstuvwxyz", &wrap), vec![(0, 10), (10, 18), (18, 26)]);
+
+        let wrap = Wrap { width: 10, showbreak: 0, linebreak: true };
+        assert_eq!(rows("the quick brown fox", &wrap), vec![(0, 10), (10, 19)]);
+        assert_eq!(rows("a quickbrownfox", &wrap), vec![(0, 2), (2, 12), (12, 15)]);
+
+        let wrap = Wrap { width: 5, showbreak: 0, linebreak: false };
+        assert_eq!(rows("ab日本語", &wrap), vec![(0, 4), (4, 8)]);
+    }
+
+    #[test]
+    fn moves_by_screen_rows() {
+        let wrap = Wrap { width: 10, showbreak: 0, linebreak: false };
+        let mut buffer = Buffer::new(None, "abcdefghijklmnopqrstuvwxy\nshort");
+        buffer.pos = Cursor::new(0, 3);
+        move_by_rows(&mut buffer, &wrap, true, 1);
+        assert_eq!(buffer.pos, Cursor::new(0, 13));
+        move_by_rows(&mut buffer, &wrap, true, 2);
+        assert_eq!(buffer.pos, Cursor::new(1, 3));
+        move_by_rows(&mut buffer, &wrap, false, 1);
+        assert_eq!(buffer.pos, Cursor::new(0, 23));
+    }
+}
@@ -0,0 +1,59 @@
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// The terminal columns a grapheme cluster takes. Every cluster takes at least
+/// one, so the cursor always has a cell to sit on.
+pub fn grapheme_width(grapheme: &str) -> usize {
+    grapheme.width().max(1)
+}
+
+/// The terminal columns `text` takes.
+pub fn width(text: &str) -> usize {
+    text.graphemes(true).map(grapheme_width).sum()
+}
+
+/// The char index where each grapheme cluster of `text` starts, followed by
+/// the length of `text` in chars.
+pub fn boundaries(text: &str) -> Vec<usize> {
+    let mut col = 0;
+    let mut boundaries = vec![0];
+    for grapheme in text.graphemes(true) {
+        col += grapheme.chars().count();
+        boundaries.push(col);
+    }
+    boundaries
+}
+
+/// The start of the grapheme cluster containing char `col`.
+pub fn grapheme_start(text: &str, col: usize) -> usize {
+    boundaries(text).into_iter().take_while(|&start| start <= col).last().unwrap_or(0)
+}
+
+/// The start of the grapheme cluster after the one at char `col`.
+pub fn next_grapheme(text: &str, col: usize) -> usize {
+    let boundaries = boundaries(text);
+    let len = boundaries[boundaries.len() - 1];
+    boundaries.into_iter().find(|&start| start > col).unwrap_or(len)
+}
+
+/// The start of the grapheme cluster before the one at char `col`.
+pub fn prev_grapheme(text: &str, col: usize) -> usize {
+    boundaries(text).into_iter().take_while(|&start| start < col).last().unwrap_or(0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn widths_and_boundaries() {
+        assert_eq!(width("abc"), 3);
+        assert_eq!(width("日本"), 4);
+        assert_eq!(width("e\u{301}x"), 2);
+        assert_eq!(boundaries("e\u{301}日x"), vec![0, 2, 3, 4]);
+        assert_eq!(grapheme_start("e\u{301}x", 1), 0);
+        assert_eq!(next_grapheme("e\u{301}x", 0), 2);
+        assert_eq!(prev_grapheme("e\u{301}x", 2), 0);
+        assert_eq!(next_grapheme("ab", 1), 2);
+    }
+}